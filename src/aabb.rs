@@ -67,6 +67,18 @@ impl AABB {
         let diff = self.max - self.min;
         2.0 * (diff.x * diff.y + diff.x * diff.z + diff.y * diff.z)
     }
+    // Widen any axis thinner than `delta` so flat boxes (e.g. around planar triangles) still pass the slab test
+    pub fn pad(&self, delta: f64) -> AABB {
+        let mut min = self.min;
+        let mut max = self.max;
+        for a in 0..3 {
+            if max[a] - min[a] < delta {
+                min[a] -= delta / 2.0;
+                max[a] += delta / 2.0;
+            }
+        }
+        AABB::new(min, max)
+    }
 }
 pub fn box_x_compare(a: &Box<dyn Hitable>, b: &Box<dyn Hitable>) -> std::cmp::Ordering {
     if let (Some(a_box), Some(b_box)) = (a.bounding_box(0.0, 0.0), b.bounding_box(0.0, 0.0)) {
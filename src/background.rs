@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use nalgebra::Vector3;
+
+use crate::{envmap::EnvironmentMap, light::LightSample, sky::PhysicalSky, spectrum::upsample};
+
+// Radiance arriving along rays that leave the scene without hitting anything
+#[derive(Clone)]
+pub enum Background {
+    // Daylight from the Preetham sky model, sun disk included
+    Sky(Arc<PhysicalSky>),
+    // A constant colour; black for interiors lit only by emissive surfaces
+    Solid(Vector3<f64>),
+    // An HDR panorama, which also lights the scene through importance sampling
+    Environment(Arc<EnvironmentMap>),
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Sky(Arc::new(PhysicalSky::default()))
+    }
+}
+
+impl Background {
+    pub fn color(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        upsample(match self {
+            Background::Sky(sky) => sky.radiance(direction),
+            Background::Solid(color) => *color,
+            Background::Environment(map) => map.radiance(direction),
+        })
+    }
+
+    // Directions to sample the background as a light, empty for backgrounds that do not support it.
+    // Each sample's pdf is the combined density of all of them, as returned by pdf().
+    pub fn sample(&self) -> Vec<LightSample> {
+        let mut samples = match self {
+            Background::Sky(sky) => sky.sample(),
+            Background::Environment(map) => map.sample().into_iter().collect(),
+            Background::Solid(_) => Vec::new(),
+        };
+        samples.iter_mut().for_each(|sample| sample.radiance = upsample(sample.radiance));
+        samples
+    }
+
+    // Density with which sample() picks `direction`; 0 when the background is not sampled
+    pub fn pdf(&self, direction: &Vector3<f64>) -> f64 {
+        match self {
+            Background::Sky(sky) => sky.pdf(direction),
+            Background::Environment(map) => map.pdf(direction),
+            Background::Solid(_) => 0.0,
+        }
+    }
+}
@@ -0,0 +1,548 @@
+use nalgebra::{Point3, Vector3};
+
+use crate::{
+    camera::Camera,
+    hitrecord::{HitRecord, Hitable},
+    integrator::{Integrator, Splat},
+    light::Light,
+    ray::Ray,
+    renderer::{MisHeuristic, RenderSettings},
+    scene::Scene,
+    util::{hit_light, random_f64, sample_lights, visibility},
+};
+
+// Bidirectional path tracer (Veach 1997, following the structure of pbrt's BDPT). Every camera
+// sample traces a subpath from the camera and one from a light, then joins every prefix of one
+// to every prefix of the other and weights each joined path by MIS against all the other ways
+// it could have been built. Paths that end on the sky, the environment or a directional light
+// cannot be started from the light's side and are handled as in the path tracer; so is light
+// from emissive objects that are not scene lights.
+pub struct Bdpt {
+    max_depth: u32,
+    roulette_depth: u32,
+    heuristic: MisHeuristic,
+    camera: Camera,
+    width: u32,
+    height: u32,
+    // Area of the viewport get_ray is sampled over, relative to the unit square: pixel i covers
+    // [i, i + 1) / (width - 1), so the whole image reaches a little past 1
+    film_scale: f64,
+}
+
+enum VertexKind<'a> {
+    Camera,
+    // A point on a light: where a light subpath starts, where a camera subpath hits a light,
+    // or where a light sample lands. `radiance` is what a camera subpath saw on hitting it.
+    Light { light: &'a Light, radiance: Vector3<f64> },
+    // `incoming` is the direction the subpath arrived along; `importance` marks light subpaths,
+    // whose BSDFs are evaluated for light traced from the lights
+    Surface { hit_record: HitRecord, incoming: Vector3<f64>, importance: bool },
+}
+
+struct Vertex<'a> {
+    kind: VertexKind<'a>,
+    p: Point3<f64>,
+    // Zero for the camera and for point and spot lights
+    normal: Vector3<f64>,
+    // Contribution of the subpath up to, not including, this vertex
+    throughput: Vector3<f64>,
+    // Density per unit area of reaching this vertex when its subpath is generated, and when the
+    // same path is generated from the other end
+    pdf_fwd: f64,
+    pdf_rev: f64,
+    // The subpath left this vertex by a specular bounce
+    delta: bool,
+}
+
+// Unweighted contribution of a path joined from a light and a camera subpath
+struct Connection<'a> {
+    contribution: Vector3<f64>,
+    // Endpoint drawn just for this path, for light sampling (s = 1) and camera sampling (t = 1)
+    sampled: Option<Vertex<'a>>,
+    // Where on the image the path lands, when it was not traced from this pixel (t = 1)
+    pixel: Option<(u32, u32)>,
+}
+
+impl<'a> Vertex<'a> {
+    fn surface(hit_record: HitRecord, incoming: Vector3<f64>, importance: bool, throughput: Vector3<f64>) -> Self {
+        Vertex {
+            p: hit_record.p,
+            normal: hit_record.normal,
+            kind: VertexKind::Surface { hit_record, incoming, importance },
+            throughput,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            delta: false,
+        }
+    }
+
+    fn light(light: &'a Light, p: Point3<f64>, normal: Vector3<f64>, radiance: Vector3<f64>, throughput: Vector3<f64>) -> Self {
+        Vertex {
+            kind: VertexKind::Light { light, radiance },
+            p,
+            normal,
+            throughput,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            delta: false,
+        }
+    }
+
+    fn camera(p: Point3<f64>, throughput: Vector3<f64>) -> Self {
+        Vertex {
+            kind: VertexKind::Camera,
+            p,
+            normal: Vector3::zeros(),
+            throughput,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            delta: false,
+        }
+    }
+
+    fn is_on_surface(&self) -> bool {
+        self.normal != Vector3::zeros()
+    }
+
+    // Whether a path can be joined at this vertex; specular surfaces can only be left by sampling
+    fn is_connectible(&self) -> bool {
+        match &self.kind {
+            VertexKind::Camera => true,
+            VertexKind::Light { light, .. } => !light.is_infinite(),
+            VertexKind::Surface { hit_record, .. } => hit_record.material.lobes().is_smooth(),
+        }
+    }
+
+    // Turn a solid angle density at this vertex into an area density at `next`
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = next.p - self.p;
+        let distance_squared = w.magnitude_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        let mut pdf = pdf / distance_squared;
+        if next.is_on_surface() {
+            pdf *= next.normal.dot(&w).abs() / distance_squared.sqrt();
+        }
+        pdf
+    }
+
+    // BSDF times the cosine at this surface vertex, for light leaving towards `next`
+    fn eval(&self, next: &Vertex) -> Vector3<f64> {
+        match &self.kind {
+            VertexKind::Surface { hit_record, incoming, importance } => {
+                let ray_in = Ray::new(self.p - incoming, *incoming);
+                let direction = (next.p - self.p).normalize();
+                let f = hit_record.material.eval(&ray_in, hit_record, &direction);
+                if *importance {
+                    f * hit_record.material.importance_scale(&ray_in, hit_record, &direction)
+                } else {
+                    f
+                }
+            }
+            _ => Vector3::zeros(),
+        }
+    }
+
+    // Density per unit area of light emitted at this light vertex reaching `next`
+    fn pdf_light(&self, next: &Vertex) -> f64 {
+        let VertexKind::Light { light, .. } = &self.kind else {
+            return 0.0;
+        };
+        let direction = (next.p - self.p).normalize();
+        self.convert_density(light.emission_pdf_direction(&self.normal, &direction), next)
+    }
+
+    // Density per unit area of a light subpath starting at this light vertex
+    fn pdf_light_origin(&self, light_choice: f64) -> f64 {
+        match &self.kind {
+            VertexKind::Light { light, .. } => light_choice * light.emission_pdf_position(),
+            _ => 0.0,
+        }
+    }
+}
+
+impl Bdpt {
+    pub fn new(settings: &RenderSettings, camera: Camera) -> Self {
+        let (width, height) = (settings.width as f64, settings.height as f64);
+        Bdpt {
+            max_depth: settings.max_depth,
+            roulette_depth: settings.roulette_depth,
+            heuristic: settings.mis,
+            camera,
+            width: settings.width,
+            height: settings.height,
+            film_scale: (width - 1.0) * (height - 1.0) / (width * height),
+        }
+    }
+
+    // Pixel (column, row from the top) that camera rays from `lens_point` through `point` are
+    // sampled for
+    fn pixel(&self, lens_point: &Point3<f64>, point: &Point3<f64>) -> Option<(u32, u32)> {
+        let uv = self.camera.viewport_coordinates(lens_point, point)?;
+        let x = (uv.x * (self.width - 1) as f64).floor();
+        let j = (uv.y * (self.height - 1) as f64).floor();
+        if x < 0.0 || j < 0.0 || x >= self.width as f64 || j >= self.height as f64 {
+            return None;
+        }
+        Some((x as u32, self.height - 1 - j as u32))
+    }
+
+    // Density per unit solid angle of a camera sample leaving `lens_point` along the direction
+    // to `point`, over the whole image
+    fn camera_pdf(&self, lens_point: &Point3<f64>, point: &Point3<f64>) -> f64 {
+        if self.pixel(lens_point, point).is_none() {
+            return 0.0;
+        }
+        self.film_scale * self.camera.direction_pdf(&(point - lens_point))
+    }
+
+    // Density per unit area with which `vertex`, reached from `prev`, samples `next`
+    fn pdf(&self, vertex: &Vertex, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let pdf = match &vertex.kind {
+            VertexKind::Light { .. } => return vertex.pdf_light(next),
+            VertexKind::Camera => self.camera_pdf(&vertex.p, &next.p),
+            VertexKind::Surface { hit_record, .. } => {
+                let Some(prev) = prev else {
+                    return 0.0;
+                };
+                let ray_in = Ray::new(prev.p, vertex.p - prev.p);
+                hit_record.material.pdf(&ray_in, hit_record, &(next.p - vertex.p).normalize())
+            }
+        };
+        vertex.convert_density(pdf, next)
+    }
+
+    // Extend `path`, which holds just its endpoint, by sampling BSDFs from `ray` until it leaves
+    // the scene, is absorbed or is long enough. `pdf` is the solid angle density `ray` was sampled
+    // with. On the camera side, light reaching the path that only it can find is returned.
+    fn random_walk<'a>(
+        &self,
+        world: &dyn Hitable,
+        scene: &'a Scene,
+        ray: Ray,
+        throughput: Vector3<f64>,
+        pdf: f64,
+        path: &mut Vec<Vertex<'a>>,
+    ) -> Vector3<f64> {
+        let from_camera = matches!(path[0].kind, VertexKind::Camera);
+        // Camera subpaths need one vertex more to reach the same depth, as they can end on a light
+        let max_vertices = self.max_depth as usize + if from_camera { 2 } else { 1 };
+        let mut unidirectional = Vector3::new(0.0, 0.0, 0.0);
+        let (mut ray, mut throughput, mut pdf_fwd) = (ray, throughput, pdf);
+        // Solid angle density the previous vertex sampled `ray` with, None after a specular bounce
+        let mut bsdf_pdf: Option<f64> = None;
+        let mut bounce = 0;
+
+        while path.len() < max_vertices {
+            // Lights are not part of the world, so camera paths only look for surfaces, and fog,
+            // in front of the nearest one
+            let light_hit = if from_camera { hit_light(&scene.lights, &ray, 0.001, f64::INFINITY) } else { None };
+            let hit = world.hit(&ray, 0.001, light_hit.map_or(f64::INFINITY, |(_, t, _)| t));
+            if let (None, Some((light, t, radiance))) = (&hit, light_hit) {
+                let p = ray.point_at_parameter(t);
+                let mut vertex = Vertex::light(light, p, light.normal_at(&ray, t), radiance, throughput);
+                vertex.pdf_fwd = path.last().map_or(0.0, |prev| prev.convert_density(pdf_fwd, &vertex));
+                path.push(vertex);
+                break;
+            }
+            let Some(hit_record) = hit else {
+                if from_camera {
+                    let background = scene.background.color(&ray.direction);
+                    let weight = bsdf_pdf.map_or(1.0, |pdf| self.heuristic.weight(pdf, scene.background.pdf(&ray.direction)));
+                    unidirectional += throughput.component_mul(&background) * weight;
+                }
+                break;
+            };
+
+            let incoming = ray.direction.normalize();
+            let material = hit_record.material.clone();
+            if from_camera {
+                unidirectional += throughput.component_mul(&material.emitted(&ray, &hit_record));
+            }
+            let mut vertex = Vertex::surface(hit_record, incoming, !from_camera, throughput);
+            let prev = path.last().expect("random walks start from an endpoint vertex");
+            vertex.pdf_fwd = prev.convert_density(pdf_fwd, &vertex);
+            path.push(vertex);
+            if path.len() >= max_vertices {
+                break;
+            }
+
+            let vertex = path.last().unwrap();
+            let VertexKind::Surface { hit_record, .. } = &vertex.kind else {
+                unreachable!();
+            };
+            let Some(scatter) = material.sample(&ray, hit_record) else {
+                break;
+            };
+            if from_camera && material.lobes().is_smooth() {
+                // Sky and directional lights by next-event estimation, as in the path tracer
+                let direct = sample_lights(&ray, hit_record, world, scene, self.heuristic, Light::is_infinite);
+                unidirectional += throughput.component_mul(&direct);
+            }
+
+            let direction = scatter.scattered.direction.normalize();
+            let (pdf, pdf_rev) = if scatter.is_specular() {
+                (0.0, 0.0)
+            } else {
+                let reverse = Ray::new(vertex.p + direction, -direction);
+                (scatter.pdf, material.pdf(&reverse, hit_record, &-incoming))
+            };
+            throughput.component_mul_assign(&scatter.attenuation);
+            if !from_camera {
+                throughput *= material.importance_scale(&ray, hit_record, &direction);
+            }
+            if bounce >= self.roulette_depth {
+                let survival = throughput.max().min(0.95);
+                if survival <= 0.0 || random_f64() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+
+            let n = path.len();
+            path[n - 1].delta = scatter.is_specular();
+            path[n - 2].pdf_rev = path[n - 1].convert_density(pdf_rev, &path[n - 2]);
+            pdf_fwd = pdf;
+            bsdf_pdf = if scatter.is_specular() { None } else { Some(pdf) };
+            ray = Ray::new(scatter.scattered.origin, direction);
+            bounce += 1;
+        }
+        unidirectional
+    }
+
+    fn light_subpath<'a>(&self, world: &dyn Hitable, scene: &'a Scene, lights: &[&'a Light]) -> Vec<Vertex<'a>> {
+        let mut path = Vec::new();
+        if lights.is_empty() {
+            return path;
+        }
+        let light_choice = 1.0 / lights.len() as f64;
+        let light = lights[((random_f64() * lights.len() as f64) as usize).min(lights.len() - 1)];
+        let Some(emission) = light.sample_emission() else {
+            return path;
+        };
+        if emission.pdf_position <= 0.0 || emission.pdf_direction <= 0.0 || emission.radiance == Vector3::zeros() {
+            return path;
+        }
+        let mut origin = Vertex::light(light, emission.origin, emission.normal, emission.radiance, emission.radiance);
+        origin.pdf_fwd = light_choice * emission.pdf_position;
+        let cosine = if origin.is_on_surface() { emission.normal.dot(&emission.direction).abs() } else { 1.0 };
+        let throughput = emission.radiance * cosine / (light_choice * emission.pdf_position * emission.pdf_direction);
+        path.push(origin);
+        let ray = Ray::new(emission.origin, emission.direction);
+        self.random_walk(world, scene, ray, throughput, emission.pdf_direction, &mut path);
+        path
+    }
+
+    // MIS weight of the path made of the first `s` light vertices and first `t` camera vertices,
+    // with `sampled` standing in for the light endpoint when s = 1 or the camera when t = 1
+    fn mis_weight(
+        &self,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        sampled: Option<&Vertex>,
+        s: usize,
+        t: usize,
+        light_choice: f64,
+    ) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+        // (pdf_fwd, pdf_rev, delta) of each vertex as this strategy sees it
+        let mut camera_pdfs: Vec<(f64, f64, bool)> = camera_path[..t].iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect();
+        let light_vertices = if s == 1 { std::slice::from_ref(sampled.unwrap()) } else { &light_path[..s] };
+        let mut light_pdfs: Vec<(f64, f64, bool)> = light_vertices.iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect();
+
+        let pt = if t == 1 { sampled.unwrap() } else { &camera_path[t - 1] };
+        let pt_minus = if t > 1 { Some(&camera_path[t - 2]) } else { None };
+        let qs = light_vertices.last();
+        let qs_minus = if s > 1 { Some(&light_path[s - 2]) } else { None };
+        if t == 1 {
+            camera_pdfs[0] = (pt.pdf_fwd, pt.pdf_rev, false);
+        }
+
+        // The joined endpoints were not sampled specularly, whatever their subpath did next
+        camera_pdfs[t - 1].2 = false;
+        if s > 0 {
+            light_pdfs[s - 1].2 = false;
+        }
+        camera_pdfs[t - 1].1 = match qs {
+            Some(qs) => self.pdf(qs, qs_minus, pt),
+            None => pt.pdf_light_origin(light_choice),
+        };
+        if let Some(pt_minus) = pt_minus {
+            camera_pdfs[t - 2].1 = match qs {
+                Some(qs) => self.pdf(pt, Some(qs), pt_minus),
+                None => pt.pdf_light(pt_minus),
+            };
+        }
+        if let Some(qs) = qs {
+            light_pdfs[s - 1].1 = self.pdf(pt, pt_minus, qs);
+        }
+        if let (Some(qs), Some(qs_minus)) = (qs, qs_minus) {
+            light_pdfs[s - 2].1 = self.pdf(qs, Some(pt), qs_minus);
+        }
+
+        // Densities of delta vertices are stored as 0, and cancel in the ratios
+        let remap = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+        let power = |r: f64| match self.heuristic {
+            MisHeuristic::Balance => r,
+            MisHeuristic::Power => r * r,
+        };
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (1..t).rev() {
+            ratio *= remap(camera_pdfs[i].1) / remap(camera_pdfs[i].0);
+            if !camera_pdfs[i].2 && !camera_pdfs[i - 1].2 {
+                sum += power(ratio);
+            }
+        }
+        let mut ratio = 1.0;
+        for i in (0..s).rev() {
+            ratio *= remap(light_pdfs[i].1) / remap(light_pdfs[i].0);
+            let delta_light_vertex = if i > 0 {
+                light_pdfs[i - 1].2
+            } else {
+                matches!(light_vertices[0].kind, VertexKind::Light { light, .. } if light.is_delta())
+            };
+            if !light_pdfs[i].2 && !delta_light_vertex {
+                sum += power(ratio);
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+
+    // Join the first s light and t camera vertices into a full path
+    fn connect<'a>(
+        &self,
+        world: &dyn Hitable,
+        lights: &[&'a Light],
+        light_path: &[Vertex<'a>],
+        camera_path: &[Vertex<'a>],
+        s: usize,
+        t: usize,
+    ) -> Option<Connection<'a>> {
+        if s == 0 {
+            let pt = &camera_path[t - 1];
+            let VertexKind::Light { radiance, .. } = &pt.kind else {
+                return None;
+            };
+            return Some(Connection { contribution: pt.throughput.component_mul(radiance), sampled: None, pixel: None });
+        }
+        if matches!(camera_path[t - 1].kind, VertexKind::Light { .. }) {
+            return None;
+        }
+
+        if t == 1 {
+            // Light tracing: join the light subpath to a point on the lens
+            let qs = &light_path[s - 1];
+            if !qs.is_connectible() {
+                return None;
+            }
+            let lens_point = self.camera.sample_lens();
+            let pixel = self.pixel(&lens_point, &qs.p)?;
+            let camera = Vertex::camera(lens_point, Vector3::new(1.0, 1.0, 1.0));
+            let to_lens = lens_point - qs.p;
+            let distance_squared = to_lens.magnitude_squared();
+            let distance = distance_squared.sqrt();
+            // The camera's importance, over the lens density, is film_scale * direction_pdf / cos
+            // at the lens, and the cosine there cancels with the one in the geometry term
+            let importance = self.film_scale * self.camera.direction_pdf(&-to_lens) / distance_squared;
+            let contribution = qs.throughput.component_mul(&qs.eval(&camera)) * importance;
+            if contribution == Vector3::zeros() {
+                return None;
+            }
+            let visibility = visibility(world, &qs.p, &(to_lens / distance), distance);
+            if visibility == 0.0 {
+                return None;
+            }
+            return Some(Connection { contribution: contribution * visibility, sampled: Some(camera), pixel: Some(pixel) });
+        }
+
+        let pt = &camera_path[t - 1];
+        if !pt.is_connectible() {
+            return None;
+        }
+        if s == 1 {
+            // Next-event estimation: draw a fresh point on a light rather than using light_path[0]
+            let light_choice = 1.0 / lights.len() as f64;
+            let light = lights[((random_f64() * lights.len() as f64) as usize).min(lights.len() - 1)];
+            let sample = light.sample(&pt.p)?;
+            if sample.pdf <= 0.0 || sample.radiance == Vector3::zeros() {
+                return None;
+            }
+            let normal = light.normal_at(&Ray::new(pt.p, sample.direction), sample.distance);
+            let p = pt.p + sample.direction * sample.distance;
+            let mut vertex = Vertex::light(light, p, normal, sample.radiance, sample.radiance / (sample.pdf * light_choice));
+            vertex.pdf_fwd = vertex.pdf_light_origin(light_choice);
+            let contribution = pt.throughput.component_mul(&pt.eval(&vertex)).component_mul(&vertex.throughput);
+            if contribution == Vector3::zeros() {
+                return None;
+            }
+            let visibility = visibility(world, &pt.p, &sample.direction, sample.distance);
+            if visibility == 0.0 {
+                return None;
+            }
+            return Some(Connection { contribution: contribution * visibility, sampled: Some(vertex), pixel: None });
+        }
+
+        let qs = &light_path[s - 1];
+        if !qs.is_connectible() {
+            return None;
+        }
+        let to_camera = pt.p - qs.p;
+        let distance_squared = to_camera.magnitude_squared();
+        let distance = distance_squared.sqrt();
+        let contribution = qs
+            .throughput
+            .component_mul(&qs.eval(pt))
+            .component_mul(&pt.eval(qs))
+            .component_mul(&pt.throughput)
+            / distance_squared;
+        if contribution == Vector3::zeros() {
+            return None;
+        }
+        let visibility = visibility(world, &qs.p, &(to_camera / distance), distance);
+        if visibility == 0.0 {
+            return None;
+        }
+        Some(Connection { contribution: contribution * visibility, sampled: None, pixel: None })
+    }
+}
+
+impl Integrator for Bdpt {
+    fn radiance(&self, ray: &Ray, world: &dyn Hitable, scene: &Scene, splats: &mut Vec<Splat>) -> Vector3<f64> {
+        // Paths can only start on lights with a position
+        let lights: Vec<&Light> = scene.lights.iter().filter(|light| !light.is_infinite()).collect();
+        let light_choice = if lights.is_empty() { 0.0 } else { 1.0 / lights.len() as f64 };
+
+        let mut camera_path = vec![Vertex::camera(ray.origin, Vector3::new(1.0, 1.0, 1.0))];
+        let direction = ray.direction.normalize();
+        let pdf = self.film_scale * self.camera.direction_pdf(&direction);
+        let mut radiance = self.random_walk(world, scene, Ray::new(ray.origin, direction), Vector3::new(1.0, 1.0, 1.0), pdf, &mut camera_path);
+        let light_path = self.light_subpath(world, scene, &lights);
+
+        for t in 1..=camera_path.len() {
+            // Light sampling (s = 1) draws its own light vertex, so it runs even without a light subpath
+            for s in 0..=light_path.len().max(1) {
+                let depth = s + t;
+                if (s == 1 && t == 1) || depth < 2 || depth - 2 > self.max_depth as usize {
+                    continue;
+                }
+                if s == 1 && lights.is_empty() {
+                    continue;
+                }
+                let Some(connection) = self.connect(world, &lights, &light_path, &camera_path, s, t) else {
+                    continue;
+                };
+                let weight = self.mis_weight(&light_path, &camera_path, connection.sampled.as_ref(), s, t, light_choice);
+                let contribution = connection.contribution * weight;
+                match connection.pixel {
+                    Some((x, y)) => splats.push(Splat { x, y, radiance: contribution }),
+                    None => radiance += contribution,
+                }
+            }
+        }
+        radiance
+    }
+}
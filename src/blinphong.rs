@@ -0,0 +1,57 @@
+use nalgebra::{Unit, Vector3};
+
+use crate::{material::{Material, ScatterRecord}, ray::Ray, hitrecord::HitRecord, spectrum::upsample};
+
+// Rasterizer-style preview shading: ambient + Lambert + Blinn-Phong highlight from a single
+// directional light. The surface shades itself and never scatters, so it is cheap and noise free
+// but not physically based; use Lambertian/Metal/Dielectric for final renders.
+pub struct BlinnPhong {
+    albedo: Vector3<f64>,
+    // Direction the light travels in, like the old hard-coded light_dir
+    light_dir: Unit<Vector3<f64>>,
+    ambient: f64,
+    specular: Vector3<f64>,
+    shininess: f64,
+}
+
+impl BlinnPhong {
+    pub fn new(albedo: Vector3<f64>, light_dir: Vector3<f64>) -> Self {
+        BlinnPhong {
+            albedo,
+            light_dir: Unit::new_normalize(light_dir),
+            ambient: 0.1,
+            specular: Vector3::zeros(),
+            shininess: 1.0,
+        }
+    }
+
+    pub fn with_ambient(mut self, ambient: f64) -> Self {
+        self.ambient = ambient;
+        self
+    }
+
+    pub fn with_specular(mut self, specular: Vector3<f64>, shininess: f64) -> Self {
+        self.specular = specular;
+        self.shininess = shininess;
+        self
+    }
+}
+
+impl Material for BlinnPhong {
+    fn sample(&self, _ray_in: &Ray, _hit_record: &HitRecord) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, ray_in: &Ray, hit_record: &HitRecord) -> Vector3<f64> {
+        let view_dir = -ray_in.direction.normalize();
+        let normal = if view_dir.dot(&hit_record.normal) > 0.0 { hit_record.normal } else { -hit_record.normal };
+        let to_light = -self.light_dir.into_inner();
+        let halfway_dir = (to_light + view_dir).normalize();
+
+        let albedo = hit_record.albedo(self.albedo);
+        let ambient = albedo * self.ambient;
+        let diffuse = albedo * normal.dot(&to_light).max(0.0);
+        let specular = upsample(self.specular) * normal.dot(&halfway_dir).max(0.0).powf(self.shininess);
+        ambient + diffuse + specular
+    }
+}
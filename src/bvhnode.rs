@@ -155,6 +155,18 @@ impl Hitable for BVHNode {
         }
     }
 
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> f64 {
+        if !self.aabb.hit(ray, t_min, t_max) {
+            return 1.0;
+        }
+        let left = self.left.transmittance(ray, t_min, t_max);
+        // Leaves with a single object hold it on both sides
+        if left == 0.0 || Arc::ptr_eq(&self.left, &self.right) {
+            return left;
+        }
+        left * self.right.transmittance(ray, t_min, t_max)
+    }
+
     fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
         Some(self.aabb)
     }
@@ -1,6 +1,6 @@
 use crate::{ray::Ray, util::random_in_unit_disk};
 
-use nalgebra::{Point3, Vector3};
+use nalgebra::{Point3, Vector2, Vector3};
 
 #[derive(Copy, Clone,Debug)]
 pub struct Camera {
@@ -11,6 +11,11 @@ pub struct Camera {
     u: Vector3<f64>,
     v: Vector3<f64>,
     lens_radius: f64,
+    // Construction parameters, kept so the camera can be rebuilt for another image shape
+    look_at: Point3<f64>,
+    vup: Vector3<f64>,
+    vfov: f64,
+    focus_dist: f64,
 }
 impl Camera {
     pub fn new(
@@ -43,8 +48,64 @@ impl Camera {
             u,
             v,
             lens_radius: aperture / 2.0,
+            look_at,
+            vup,
+            vfov,
+            focus_dist,
         }
     }
+    pub fn with_aspect_ratio(&self, aspect_ratio: f64) -> Self {
+        Camera::new(
+            self.origin,
+            self.look_at,
+            self.vup,
+            self.vfov,
+            aspect_ratio,
+            2.0 * self.lens_radius,
+            self.focus_dist,
+        )
+    }
+    // Width of a pixel of an image `width` pixels wide, on the plane through look_at
+    pub fn pixel_footprint(&self, width: u32) -> f64 {
+        let distance = (self.look_at - self.origin).magnitude();
+        self.horizontal.magnitude() / self.focus_dist * distance / (width - 1) as f64
+    }
+    // Unit view direction
+    pub fn forward(&self) -> Vector3<f64> {
+        self.v.cross(&self.u)
+    }
+    // Uniformly distributed point on the lens, where camera rays start
+    pub fn sample_lens(&self) -> Point3<f64> {
+        let rd = self.lens_radius * random_in_unit_disk();
+        self.origin + self.u * rd.x + self.v * rd.y
+    }
+    // The (u, v) that get_ray maps to the ray from `lens_point` through `point`, or None when
+    // the point is not in front of the camera
+    pub fn viewport_coordinates(&self, lens_point: &Point3<f64>, point: &Point3<f64>) -> Option<Vector2<f64>> {
+        let direction = point - lens_point;
+        let cos_theta = direction.dot(&self.forward());
+        if cos_theta <= 0.0 {
+            return None;
+        }
+        // Every ray through a viewport position crosses the focus plane at the same point
+        let t = (self.lower_left_corner - lens_point).dot(&self.forward()) / cos_theta;
+        let on_plane = lens_point + direction * t - self.lower_left_corner;
+        Some(Vector2::new(
+            on_plane.dot(&self.horizontal) / self.horizontal.magnitude_squared(),
+            on_plane.dot(&self.vertical) / self.vertical.magnitude_squared(),
+        ))
+    }
+    // Density per unit solid angle of the directions leaving a lens point when get_ray is given
+    // (u, v) uniformly over the unit square
+    pub fn direction_pdf(&self, direction: &Vector3<f64>) -> f64 {
+        let cos_theta = direction.normalize().dot(&self.forward());
+        if cos_theta <= 0.0 {
+            return 0.0;
+        }
+        // Viewport area at unit distance in front of the lens
+        let area = self.horizontal.magnitude() * self.vertical.magnitude() / (self.focus_dist * self.focus_dist);
+        1.0 / (area * cos_theta.powi(3))
+    }
     pub fn get_ray(&self, u: f64, v: f64) -> Ray {
         let rd = self.lens_radius * random_in_unit_disk();
         let offset = self.u * rd.x + self.v * rd.y;
@@ -1,7 +1,7 @@
 use crate::{Ray, hitrecord::{Hitable, HitRecord}, aabb::AABB};
 use std::{sync::Arc};
 
-use nalgebra::{Point3, Vector3};
+use nalgebra::{Point3, Vector2, Vector3};
 
 use crate::material::Material;
 
@@ -59,6 +59,9 @@ impl Hitable for Cone {
                             p,
                             normal,
                             material: Arc::clone(&self.material),
+                            uv: Vector2::zeros(),
+                            barycentric: None,
+                            color: None,
                         });
                     }
                 }
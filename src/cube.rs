@@ -1,7 +1,7 @@
 use crate::{Ray, hitrecord::{Hitable, HitRecord}, aabb::AABB};
 use std::{sync::Arc};
 
-use nalgebra::{Point3, Vector3};
+use nalgebra::{Point3, Vector2, Vector3};
 
 use crate::material::Material;
 
@@ -46,6 +46,9 @@ impl Hitable for Cube {
                             p,
                             normal,
                             material: Arc::clone(&self.material),
+                            uv: Vector2::zeros(),
+                            barycentric: None,
+                            color: None,
                         });
                     }
                 }
@@ -2,7 +2,7 @@
 use crate::{Ray, hitrecord::{Hitable, HitRecord}, aabb::AABB};
 use std::sync::Arc;
 
-use nalgebra::Point3;
+use nalgebra::{Point3, Vector2};
 
 use crate::material::Material;
 
@@ -51,6 +51,9 @@ impl Hitable for Cylinder {
                             p,
                             normal,
                             material: Arc::clone(&self.material),
+                            uv: Vector2::zeros(),
+                            barycentric: None,
+                            color: None,
                         });
                     }
                 }
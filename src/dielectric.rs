@@ -1,57 +1,363 @@
-use nalgebra::{Vector3, Unit};
-use rand::Rng;
+use std::f64::consts::PI;
 
-use crate::{material::Material, ray::Ray, hitrecord::HitRecord, util::{reflect, schlick, refract}};
+use nalgebra::Vector3;
 
+use crate::{
+    frame::Frame,
+    material::{Lobes, Material, ScatterRecord},
+    ray::Ray,
+    hitrecord::HitRecord,
+    spectrum::{upsample, wavelength},
+    util::{reflect, refract, random_f64},
+};
 
+// Wavelength of the sodium d line, at which catalogues quote a glass's index of refraction
+const D_LINE: f64 = 587.56;
 
+// Fresnel reflectance of unpolarised light at a smooth interface, for light arriving at
+// `cos_i` to the normal and a relative index of refraction `eta` = n_t / n_i; 1 under total
+// internal reflection
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let g_squared = eta * eta - 1.0 + cos_i * cos_i;
+    if g_squared < 0.0 {
+        return 1.0;
+    }
+    let g = g_squared.sqrt();
+    let a = (g - cos_i) / (g + cos_i);
+    let b = (cos_i * (g + cos_i) - 1.0) / (cos_i * (g - cos_i) + 1.0);
+    0.5 * a * a * (1.0 + b * b)
+}
+
+// How a material's index of refraction changes with wavelength, with wavelengths in micrometres
+// as glass catalogues give their coefficients
+#[derive(Copy, Clone, Debug)]
+pub enum Dispersion {
+    // n = a + b / lambda^2, enough for most glasses over the visible range
+    Cauchy { a: f64, b: f64 },
+    // n^2 = 1 + sum of b_i lambda^2 / (lambda^2 - c_i), accurate well beyond it
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    // Index of refraction at `lambda` nanometres
+    pub fn ior(&self, lambda: f64) -> f64 {
+        let l2 = (lambda * 1e-3).powi(2);
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => (1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt(),
+        }
+    }
+}
+
+// Glass, water and other transparent materials. A surface separates the inside, which its
+// normals point away from, from the outside, and each side has its own index of refraction
+// and absorption. Air is the default outside, but a surface can also be the boundary between
+// two dielectrics, such as where a liquid meets the glass holding it: modelling that as one
+// surface, with the liquid inside and the glass outside, refracts correctly where two separate
+// surfaces would leave a thin gap of air.
+//
+// Absorption follows the Beer-Lambert law over the distance a ray travelled to reach the
+// surface, on whichever side it arrived from, so tinted glass gets darker where it is thicker.
+//
+// Glass with dispersion bends each wavelength by its own index in spectral rendering, splitting
+// white light into colours; RGB rendering uses its index at the d line.
 pub struct Dielectric {
     pub ref_idx: f64,
+    dispersion: Option<Dispersion>,
+    outside_idx: f64,
+    // Absorption coefficients per unit distance inside and outside
+    absorption: Vector3<f64>,
+    outside_absorption: Vector3<f64>,
+    // GGX width of the microfacet normals, 0 for smooth glass
+    alpha: f64,
+}
+
+// Absorption coefficients that leave `tint` of the light after `distance`
+fn absorption_for(tint: Vector3<f64>, distance: f64) -> Vector3<f64> {
+    tint.map(|c| -c.clamp(1e-6, 1.0).ln() / distance)
 }
 
 impl Dielectric {
     pub fn new(ref_idx: f64) -> Self {
-        Dielectric { ref_idx }
+        Dielectric {
+            ref_idx,
+            dispersion: None,
+            outside_idx: 1.0,
+            absorption: Vector3::zeros(),
+            outside_absorption: Vector3::zeros(),
+            alpha: 0.0,
+        }
     }
-    
-    
-    
-}
-impl Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
-        let reflected = reflect(ray_in.direction, hit_record.normal);
-        let view_direction = -ray_in.direction.normalize();
-        let light_direction = Vector3::new(1.0, 1.0, 1.0).normalize();
-        let light_color = Vector3::new(1.0, 1.0, 1.0);
-        let half_vector = (view_direction + light_direction).normalize();
-        let specular_intensity = hit_record.normal.dot(&half_vector).max(0.0).powf(90.0);
-        let (outward_normal, ni_over_nt, cosine) = if ray_in.direction.dot(&hit_record.normal) > 0.0 {
-            (
-                -hit_record.normal,
-                self.ref_idx,
-                self.ref_idx * ray_in.direction.dot(&hit_record.normal) / ray_in.direction.magnitude(),
-            )
+
+    // Let the inside's index of refraction vary with wavelength
+    pub fn with_dispersion(mut self, dispersion: Dispersion) -> Self {
+        self.ref_idx = dispersion.ior(D_LINE);
+        self.dispersion = Some(dispersion);
+        self
+    }
+
+    // Tint the inside so that light keeps `tint` of its colour after travelling `distance`
+    pub fn with_tint(mut self, tint: Vector3<f64>, distance: f64) -> Self {
+        self.absorption = absorption_for(tint, distance);
+        self
+    }
+
+    // What is on the outside of the surface, for interfaces between two dielectrics
+    pub fn with_outside(mut self, ref_idx: f64, tint: Vector3<f64>, distance: f64) -> Self {
+        self.outside_idx = ref_idx;
+        self.outside_absorption = absorption_for(tint, distance);
+        self
+    }
+
+    // Rough glass, `roughness` from 0 (smooth) to 1; the GGX alpha is its square, which
+    // spreads perceived roughness more evenly
+    pub fn with_roughness(mut self, roughness: f64) -> Self {
+        self.alpha = roughness * roughness;
+        self
+    }
+
+    fn is_rough(&self) -> bool {
+        // Narrower lobes than this are numerically a mirror
+        self.alpha > 1e-4
+    }
+
+    // Indices of refraction on the side the ray arrives from and the side it would enter, at
+    // the path's wavelength
+    fn indices(&self, entering: bool) -> (f64, f64) {
+        let inside = match (self.dispersion, wavelength()) {
+            (Some(dispersion), Some(lambda)) => dispersion.ior(lambda),
+            _ => self.ref_idx,
+        };
+        if entering {
+            (self.outside_idx, inside)
         } else {
-            (
-                hit_record.normal,
-                1.0 / self.ref_idx,
-                -ray_in.direction.dot(&hit_record.normal) / ray_in.direction.magnitude(),
-            )
+            (inside, self.outside_idx)
+        }
+    }
+
+    // Share of the light that survived the way from the ray's origin to the hit
+    fn transmittance(&self, ray_in: &Ray, hit_record: &HitRecord) -> Vector3<f64> {
+        let entering = ray_in.direction.dot(&hit_record.normal) < 0.0;
+        let absorption = if entering { self.outside_absorption } else { self.absorption };
+        if absorption == Vector3::zeros() {
+            return Vector3::new(1.0, 1.0, 1.0);
+        }
+        let distance = hit_record.t * ray_in.direction.magnitude();
+        upsample(absorption.map(|a| (-a * distance).exp()))
+    }
+
+    // GGX distribution of microfacet normals at `cos_m` to the surface normal
+    fn distribution(&self, cos_m: f64) -> f64 {
+        let alpha2 = self.alpha * self.alpha;
+        let d = cos_m * cos_m * (alpha2 - 1.0) + 1.0;
+        alpha2 / (PI * d * d)
+    }
+
+    // Smith masking for a direction at `cos_v` to the surface normal
+    fn masking(&self, cos_v: f64) -> f64 {
+        let cos2 = cos_v * cos_v;
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        2.0 / (1.0 + (1.0 + self.alpha * self.alpha * tan2).sqrt())
+    }
+
+    // Microfacet BSDF of rough glass (Walter et al. 2007) for light arriving from unit `wi` and
+    // leaving along unit `wo`, both pointing away from the surface, with the frame's normal on
+    // the side of `wi`: brdf * cos and the density sample() picks `wo` with. Transmission leaves
+    // out the 1 / eta^2 radiance scaling, so a sampled refraction is weighted by 1 - F alone and
+    // narrow lobes converge to smooth glass, whose refracted rays are not scaled either.
+    fn microfacet(&self, frame: &Frame, wi: &Vector3<f64>, wo: &Vector3<f64>, entering: bool) -> Option<(f64, f64)> {
+        let cos_i = frame.cos_theta(wi);
+        let cos_o = frame.cos_theta(wo);
+        if cos_i <= 0.0 || cos_o == 0.0 {
+            return None;
+        }
+        let (eta_i, eta_o) = self.indices(entering);
+        let reflection = cos_o > 0.0;
+        let m = if reflection { wi + wo } else { -(eta_i * wi + eta_o * wo) };
+        let m = m.try_normalize(1e-12)?;
+        let m = if frame.cos_theta(&m) < 0.0 { -m } else { m };
+        let (cos_im, cos_om) = (wi.dot(&m), wo.dot(&m));
+        // Each direction has to be on the side of the microfacet its side of the surface implies
+        if cos_im <= 0.0 || (reflection && cos_om <= 0.0) || (!reflection && cos_om >= 0.0) {
+            return None;
+        }
+        let cos_m = frame.cos_theta(&m);
+        let fresnel = fresnel_dielectric(cos_im, eta_o / eta_i);
+        let d = self.distribution(cos_m);
+        let g = self.masking(cos_i) * self.masking(cos_o.abs());
+        if reflection {
+            Some((fresnel * d * g / (4.0 * cos_i), fresnel * d * cos_m / (4.0 * cos_im)))
+        } else {
+            let denominator = eta_i * cos_im + eta_o * cos_om;
+            let denominator2 = denominator * denominator;
+            let value = (1.0 - fresnel) * d * g * eta_o * eta_o * cos_im * -cos_om / (cos_i * denominator2);
+            let pdf = (1.0 - fresnel) * d * cos_m * eta_o * eta_o * -cos_om / denominator2;
+            Some((value, pdf))
+        }
+    }
+
+    fn sample_smooth(&self, ray_in: &Ray, hit_record: &HitRecord, frame: Frame, entering: bool) -> Option<ScatterRecord> {
+        let (eta_i, eta_o) = self.indices(entering);
+        let cosine = -frame.cos_theta(&ray_in.direction) / ray_in.direction.magnitude();
+        let reflected = reflect(ray_in.direction, frame.n);
+        let (direction, side) = match refract(ray_in.direction, frame.n, eta_i / eta_o) {
+            Some(refracted) if random_f64() >= fresnel_dielectric(cosine, eta_o / eta_i) => (refracted, Lobes::TRANSMISSION),
+            _ => (reflected, Lobes::REFLECTION),
         };
+        // Fresnel is handled by choosing between the two rays
+        Some(ScatterRecord {
+            attenuation: self.transmittance(ray_in, hit_record),
+            scattered: Ray::new(hit_record.p, direction),
+            pdf: 0.0,
+            lobe: Lobes::SPECULAR | side,
+            frame,
+        })
+    }
+
+    // Draws a microfacet normal from the GGX distribution, then reflects or refracts about it
+    // with the chance given by its Fresnel reflectance
+    fn sample_rough(&self, ray_in: &Ray, hit_record: &HitRecord, frame: Frame, entering: bool) -> Option<ScatterRecord> {
+        let (eta_i, eta_o) = self.indices(entering);
+        let wi = -ray_in.direction.normalize();
+        let (xi1, xi2) = (random_f64(), random_f64());
+        let tan2 = self.alpha * self.alpha * xi1 / (1.0 - xi1);
+        let cos_theta = 1.0 / (1.0 + tan2).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * xi2;
+        let m = frame.to_world(&Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
 
-        let scattered = if let Some(refracted) = refract(ray_in.direction, outward_normal, ni_over_nt) {
-            let reflect_prob = schlick(cosine, self.ref_idx);
-            if rand::thread_rng().gen::<f64>() < reflect_prob {
-                Ray::new(hit_record.p, reflected)
-            } else {
-                Ray::new(hit_record.p, refracted)
+        let cos_im = wi.dot(&m);
+        if cos_im <= 0.0 {
+            return None;
+        }
+        let (wo, side) = match refract(-wi, m, eta_i / eta_o) {
+            Some(refracted) if random_f64() >= fresnel_dielectric(cos_im, eta_o / eta_i) => {
+                (refracted.normalize(), Lobes::TRANSMISSION)
             }
+            _ => (reflect(-wi, m), Lobes::REFLECTION),
+        };
+        // Rays that a microfacet turns back through the surface are lost; the density of the
+        // other side does not account for them
+        let reflected = frame.cos_theta(&wo) > 0.0;
+        if reflected != (side == Lobes::REFLECTION) {
+            return None;
+        }
+        let (value, pdf) = self.microfacet(&frame, &wi, &wo, entering)?;
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            attenuation: self.transmittance(ray_in, hit_record) * (value / pdf),
+            scattered: Ray::new(hit_record.p, wo),
+            pdf,
+            lobe: Lobes::GLOSSY | side,
+            frame,
+        })
+    }
+}
+
+impl Material for Dielectric {
+    fn sample(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
+        // The frame faces the incoming ray, so its normal points out of the glass when entering
+        // and into it when leaving
+        let frame = Frame::facing(hit_record.normal, &ray_in.direction);
+        let entering = ray_in.direction.dot(&hit_record.normal) < 0.0;
+        if self.is_rough() {
+            self.sample_rough(ray_in, hit_record, frame, entering)
+        } else {
+            self.sample_smooth(ray_in, hit_record, frame, entering)
+        }
+    }
+
+    fn eval(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vector3<f64>) -> Vector3<f64> {
+        if !self.is_rough() {
+            return Vector3::zeros();
+        }
+        let frame = Frame::facing(hit_record.normal, &ray_in.direction);
+        let entering = ray_in.direction.dot(&hit_record.normal) < 0.0;
+        match self.microfacet(&frame, &-ray_in.direction.normalize(), &direction.normalize(), entering) {
+            Some((value, _)) => self.transmittance(ray_in, hit_record) * value,
+            None => Vector3::zeros(),
+        }
+    }
+
+    fn pdf(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vector3<f64>) -> f64 {
+        if !self.is_rough() {
+            return 0.0;
+        }
+        let frame = Frame::facing(hit_record.normal, &ray_in.direction);
+        let entering = ray_in.direction.dot(&hit_record.normal) < 0.0;
+        self.microfacet(&frame, &-ray_in.direction.normalize(), &direction.normalize(), entering)
+            .map_or(0.0, |(_, pdf)| pdf)
+    }
+
+    fn importance_scale(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vector3<f64>) -> f64 {
+        // Refraction is weighted without 1 / eta^2 either way, so only the reverse direction
+        // picks up the ratio of the indices
+        let entering = ray_in.direction.dot(&hit_record.normal) < 0.0;
+        if (direction.dot(&hit_record.normal) < 0.0) != entering {
+            return 1.0;
+        }
+        let (eta_i, eta_o) = self.indices(entering);
+        (eta_i / eta_o) * (eta_i / eta_o)
+    }
+
+    fn lobes(&self) -> Lobes {
+        if self.is_rough() {
+            Lobes::GLOSSY | Lobes::REFLECTION | Lobes::TRANSMISSION
         } else {
-            Ray::new(hit_record.p, reflected)
+            Lobes::SPECULAR | Lobes::REFLECTION | Lobes::TRANSMISSION
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nalgebra::{Point3, Vector2};
+
+    use super::*;
+    use crate::util::seed_rng;
+
+    // Share of the light a glass sphere's surface passes at 30 degrees, entering or leaving it,
+    // and the mean direction it goes in
+    fn transmission(glass: Dielectric, entering: bool) -> (f64, Vector3<f64>) {
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let hit_record = HitRecord {
+            t: 1.0,
+            p: Point3::origin(),
+            normal,
+            material: Arc::new(Dielectric::new(1.5)),
+            uv: Vector2::zeros(),
+            barycentric: None,
+            color: None,
         };
-        let color = Vector3::new(1.0, 1.0, 1.0) + specular_intensity * light_color;
-        Some((color, scattered))
+        let direction = Vector3::new(0.5, 0.0, -(0.75f64).sqrt());
+        let direction = if entering { direction } else { direction.component_mul(&Vector3::new(1.0, 1.0, -1.0)) };
+        let ray = Ray::new(Point3::origin() - direction, direction);
+        seed_rng(7);
+        let samples = 200_000;
+        let (mut total, mut mean_direction) = (0.0, Vector3::zeros());
+        for _ in 0..samples {
+            let Some(scatter) = glass.sample(&ray, &hit_record) else {
+                continue;
+            };
+            if scatter.lobe.contains(Lobes::TRANSMISSION) {
+                total += scatter.attenuation.x;
+                mean_direction += scatter.scattered.direction.normalize() * scatter.attenuation.x;
+            }
+        }
+        (total / samples as f64, mean_direction.normalize())
     }
-    
-    
-}
\ No newline at end of file
+
+    #[test]
+    fn barely_rough_glass_transmits_like_smooth_glass() {
+        for entering in [true, false] {
+            let (smooth, smooth_direction) = transmission(Dielectric::new(1.5), entering);
+            let (rough, rough_direction) = transmission(Dielectric::new(1.5).with_roughness(0.02), entering);
+            assert!((rough - smooth).abs() < 0.01, "entering {}: rough {} smooth {}", entering, rough, smooth);
+            assert!(rough_direction.dot(&smooth_direction) > 0.999);
+        }
+    }
+}
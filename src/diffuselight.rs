@@ -0,0 +1,35 @@
+use nalgebra::Vector3;
+
+use crate::{material::{Material, ScatterRecord}, ray::Ray, hitrecord::HitRecord};
+
+// Emits the same radiance in every direction and reflects nothing
+pub struct DiffuseLight {
+    emit: Vector3<f64>,
+    two_sided: bool,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Vector3<f64>) -> Self {
+        DiffuseLight { emit, two_sided: true }
+    }
+    // Only emit from the front face, the side the surface normal points to
+    pub fn one_sided(mut self) -> Self {
+        self.two_sided = false;
+        self
+    }
+    pub fn emit(&self) -> Vector3<f64> {
+        self.emit
+    }
+}
+
+impl Material for DiffuseLight {
+    fn sample(&self, _ray_in: &Ray, _hit_record: &HitRecord) -> Option<ScatterRecord> {
+        None
+    }
+    fn emitted(&self, ray_in: &Ray, hit_record: &HitRecord) -> Vector3<f64> {
+        if !self.two_sided && ray_in.direction.dot(&hit_record.normal) >= 0.0 {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+        hit_record.albedo(self.emit)
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{Ray, hitrecord::{Hitable, HitRecord}, aabb::AABB, quad::SphericalRectangle, util::{orthonormal_basis, random_f64}};
+use std::{f64::consts::PI, sync::Arc};
+
+use nalgebra::{Point3, Vector2, Vector3};
+
+use crate::material::Material;
+
+// Flat disk facing along `normal`; the front face matters for one-sided emitters
+pub struct Disk {
+    center: Point3<f64>,
+    normal: Vector3<f64>,
+    radius: f64,
+    // Tangents spanning the disk's plane
+    s: Vector3<f64>,
+    t: Vector3<f64>,
+    material: Arc<dyn Material>,
+}
+
+impl Disk {
+    pub fn new(center: Point3<f64>, normal: Vector3<f64>, radius: f64, material: Arc<dyn Material>) -> Self {
+        let normal = normal.normalize();
+        let (s, t) = orthonormal_basis(&normal);
+        Disk {
+            center,
+            normal,
+            radius,
+            s,
+            t,
+            material,
+        }
+    }
+
+    // The square circumscribing the disk. Directions are drawn uniformly in its solid angle and
+    // the ones outside the disk are dropped, which keeps the density exactly 1 / solid angle.
+    fn bounding_square(&self, origin: &Point3<f64>) -> Option<SphericalRectangle> {
+        let corner = self.center - self.radius * (self.s + self.t);
+        SphericalRectangle::new(origin, &corner, &(2.0 * self.radius * self.s), &(2.0 * self.radius * self.t))
+    }
+
+    fn intersect(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, Vector3<f64>)> {
+        let denom = self.normal.dot(&ray.direction);
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let t = self.normal.dot(&(self.center - ray.origin)) / denom;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+        let offset = ray.point_at_parameter(t) - self.center;
+        if offset.magnitude_squared() > self.radius * self.radius {
+            return None;
+        }
+        Some((t, offset))
+    }
+}
+
+impl Hitable for Disk {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (t, offset) = self.intersect(ray, t_min, t_max)?;
+        // Polar coordinates: u is the distance from the centre, v the angle around it
+        let phi = offset.dot(&self.t).atan2(offset.dot(&self.s));
+        Some(HitRecord {
+            t,
+            p: ray.point_at_parameter(t),
+            normal: self.normal,
+            material: Arc::clone(&self.material),
+            uv: Vector2::new(offset.magnitude() / self.radius, (phi + PI) / (2.0 * PI)),
+            barycentric: None,
+            color: None,
+        })
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        // Extent of a circle along each axis is radius * sin of the angle between axis and normal
+        let extent = self.normal.map(|n| self.radius * (1.0 - n * n).max(0.0).sqrt());
+        Some(AABB::new(self.center - extent, self.center + extent).pad(1e-4))
+    }
+
+    fn pdf_value(&self, origin: &Point3<f64>, direction: &Vector3<f64>) -> f64 {
+        let ray = Ray::new(*origin, *direction);
+        if self.intersect(&ray, 0.001, f64::INFINITY).is_none() {
+            return 0.0;
+        }
+        self.bounding_square(origin).map_or(0.0, |square| 1.0 / square.solid_angle)
+    }
+
+    fn random(&self, origin: &Point3<f64>) -> Option<Vector3<f64>> {
+        let target = self.bounding_square(origin)?.sample(random_f64(), random_f64());
+        if (target - self.center).magnitude_squared() > self.radius * self.radius {
+            return None;
+        }
+        Some((target - origin).normalize())
+    }
+
+    fn area(&self) -> f64 {
+        PI * self.radius * self.radius
+    }
+
+    fn sample_surface(&self) -> Option<(Point3<f64>, Vector3<f64>)> {
+        let r = self.radius * random_f64().sqrt();
+        let phi = 2.0 * PI * random_f64();
+        Some((self.center + r * (phi.cos() * self.s + phi.sin() * self.t), self.normal))
+    }
+}
@@ -0,0 +1,76 @@
+use nalgebra::Vector2;
+
+// Piecewise constant density over [0, 1) proportional to a tabulated function, sampled by
+// inverting its CDF
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].max(0.0) / n as f64;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            // An all-zero function samples uniformly rather than not at all
+            *c = if integral > 0.0 { *c / integral } else { i as f64 / n as f64 };
+        }
+        Distribution1D { func, cdf, integral }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    // Returns x in [0, 1), its density and the index of the segment it falls in
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let n = self.count();
+        let offset = self.cdf.partition_point(|&c| c <= u).saturating_sub(1).min(n - 1);
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > 0.0 { (u - self.cdf[offset]) / width } else { 0.0 };
+        let pdf = if self.integral > 0.0 { self.func[offset].max(0.0) / self.integral } else { 1.0 };
+        ((offset as f64 + du) / n as f64, pdf, offset)
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        let offset = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        if self.integral > 0.0 { self.func[offset].max(0.0) / self.integral } else { 1.0 }
+    }
+}
+
+// Piecewise constant density over [0, 1)^2 from a row-major table: a marginal distribution
+// picks the row (v), then that row's conditional distribution picks the column (u)
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f64], width: usize, height: usize) -> Self {
+        let conditional: Vec<Distribution1D> =
+            func.chunks_exact(width).take(height).map(|row| Distribution1D::new(row.to_vec())).collect();
+        let marginal = Distribution1D::new(conditional.iter().map(Distribution1D::integral).collect());
+        Distribution2D { conditional, marginal }
+    }
+
+    // Returns (u, v) and its density with respect to area in the unit square
+    pub fn sample(&self, u0: f64, u1: f64) -> (Vector2<f64>, f64) {
+        let (v, pdf_v, row) = self.marginal.sample(u1);
+        let (u, pdf_u, _) = self.conditional[row].sample(u0);
+        (Vector2::new(u, v), pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, uv: &Vector2<f64>) -> f64 {
+        let row = ((uv.y * self.marginal.count() as f64) as usize).min(self.marginal.count() - 1);
+        self.marginal.pdf(uv.y) * self.conditional[row].pdf(uv.x)
+    }
+}
@@ -0,0 +1,158 @@
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use image::codecs::hdr::HdrDecoder;
+use nalgebra::{Rotation3, Vector2, Vector3};
+
+use crate::{distribution::Distribution2D, light::LightSample, loaderror::LoadError, util::random_f64};
+
+// Equirectangular environment: +y is up, the top row of the image looks straight up and the
+// centre of the image looks down -z. Importance sampled in proportion to luminance.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    // Row-major linear radiance, top row first
+    pixels: Vec<Vector3<f64>>,
+    // Rotation about +y applied to the map
+    rotation: Rotation3<f64>,
+    intensity: f64,
+    distribution: Distribution2D,
+}
+
+pub fn luminance(c: &Vector3<f64>) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+// Direction through a point of an unrotated map
+fn equirect_direction(uv: &Vector2<f64>) -> Vector3<f64> {
+    let phi = (uv.x - 0.5) * 2.0 * PI;
+    let theta = uv.y * PI;
+    Vector3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+}
+
+impl EnvironmentMap {
+    pub fn new(width: usize, height: usize, pixels: Vec<Vector3<f64>>) -> Self {
+        assert_eq!(pixels.len(), width * height, "environment map needs width * height pixels");
+        // Rows near the poles cover less solid angle, so weight by sin(theta)
+        let weights: Vec<f64> = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let theta = ((i / width) as f64 + 0.5) / height as f64 * PI;
+                luminance(c).max(0.0) * theta.sin()
+            })
+            .collect();
+        EnvironmentMap {
+            width,
+            height,
+            distribution: Distribution2D::new(&weights, width, height),
+            pixels,
+            rotation: Rotation3::identity(),
+            intensity: 1.0,
+        }
+    }
+
+    // Tabulate radiance along the direction through the centre of each pixel
+    pub fn from_fn(width: usize, height: usize, radiance: impl Fn(&Vector3<f64>) -> Vector3<f64>) -> Self {
+        let pixels = (0..width * height)
+            .map(|i| {
+                let uv = Vector2::new(((i % width) as f64 + 0.5) / width as f64, ((i / width) as f64 + 0.5) / height as f64);
+                radiance(&equirect_direction(&uv))
+            })
+            .collect();
+        EnvironmentMap::new(width, height, pixels)
+    }
+
+    // Load a Radiance .hdr or OpenEXR .exr panorama
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+        match extension.as_str() {
+            "hdr" => {
+                let file = File::open(path).map_err(|e| LoadError::io(path, e))?;
+                let decoder = HdrDecoder::new(BufReader::new(file)).map_err(|e| LoadError::format(path, e.to_string()))?;
+                let metadata = decoder.metadata();
+                let pixels = decoder
+                    .read_image_hdr()
+                    .map_err(|e| LoadError::format(path, e.to_string()))?
+                    .into_iter()
+                    .map(|p| Vector3::new(p[0] as f64, p[1] as f64, p[2] as f64))
+                    .collect();
+                Ok(EnvironmentMap::new(metadata.width as usize, metadata.height as usize, pixels))
+            }
+            "exr" => {
+                let image = exr::prelude::read_first_rgba_layer_from_file(
+                    path,
+                    |resolution, _| (resolution.width(), vec![Vector3::zeros(); resolution.width() * resolution.height()]),
+                    |(width, pixels): &mut (usize, Vec<Vector3<f64>>), position, (r, g, b, _a): (f32, f32, f32, f32)| {
+                        pixels[position.y() * *width + position.x()] = Vector3::new(r as f64, g as f64, b as f64);
+                    },
+                )
+                .map_err(|e| LoadError::format(path, e.to_string()))?;
+                let (width, pixels) = image.layer_data.channel_data.pixels;
+                let height = pixels.len() / width.max(1);
+                Ok(EnvironmentMap::new(width, height, pixels))
+            }
+            _ => Err(LoadError::format(path, "environment maps must be .hdr or .exr")),
+        }
+    }
+
+    // Turn the map about the up axis, in degrees
+    pub fn with_rotation(mut self, degrees: f64) -> Self {
+        self.rotation = Rotation3::from_axis_angle(&Vector3::y_axis(), degrees.to_radians());
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    fn to_uv(&self, direction: &Vector3<f64>) -> Vector2<f64> {
+        let d = self.rotation.inverse_transform_vector(&direction.normalize());
+        let u = 0.5 + d.x.atan2(-d.z) / (2.0 * PI);
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+        Vector2::new(u.rem_euclid(1.0), v)
+    }
+
+    fn to_direction(&self, uv: &Vector2<f64>) -> Vector3<f64> {
+        self.rotation * equirect_direction(uv)
+    }
+
+    fn lookup(&self, uv: &Vector2<f64>) -> Vector3<f64> {
+        let x = ((uv.x * self.width as f64) as usize).min(self.width - 1);
+        let y = ((uv.y * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x] * self.intensity
+    }
+
+    pub fn radiance(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        self.lookup(&self.to_uv(direction))
+    }
+
+    // Pick a direction with probability proportional to the map's luminance
+    pub fn sample(&self) -> Option<LightSample> {
+        let (uv, pdf_uv) = self.distribution.sample(random_f64(), random_f64());
+        let sin_theta = (uv.y * PI).sin();
+        if pdf_uv <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction: self.to_direction(&uv),
+            distance: f64::INFINITY,
+            radiance: self.lookup(&uv),
+            // From density over the unit square to density over solid angle
+            pdf: pdf_uv / (2.0 * PI * PI * sin_theta),
+        })
+    }
+
+    pub fn pdf(&self, direction: &Vector3<f64>) -> f64 {
+        let uv = self.to_uv(direction);
+        let sin_theta = (uv.y * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(&uv) / (2.0 * PI * PI * sin_theta)
+    }
+}
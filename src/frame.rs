@@ -0,0 +1,42 @@
+use nalgebra::Vector3;
+
+use crate::util::orthonormal_basis;
+
+// Orthonormal shading frame: `n` is the shading normal, `s` and `t` span the tangent plane.
+// Local coordinates put the normal on +z, so cos(theta) of a local direction is just its z.
+#[derive(Copy, Clone, Debug)]
+pub struct Frame {
+    pub s: Vector3<f64>,
+    pub t: Vector3<f64>,
+    pub n: Vector3<f64>,
+}
+
+impl Frame {
+    pub fn new(normal: Vector3<f64>) -> Self {
+        let n = normal.normalize();
+        let (s, t) = orthonormal_basis(&n);
+        Frame { s, t, n }
+    }
+
+    // Frame around the side of the surface that `incoming` arrives from. Primitives report
+    // outward normals, so this flips them for rays that hit a surface from behind.
+    pub fn facing(normal: Vector3<f64>, incoming: &Vector3<f64>) -> Self {
+        if incoming.dot(&normal) < 0.0 {
+            Frame::new(normal)
+        } else {
+            Frame::new(-normal)
+        }
+    }
+
+    pub fn to_local(&self, v: &Vector3<f64>) -> Vector3<f64> {
+        Vector3::new(v.dot(&self.s), v.dot(&self.t), v.dot(&self.n))
+    }
+
+    pub fn to_world(&self, v: &Vector3<f64>) -> Vector3<f64> {
+        self.s * v.x + self.t * v.y + self.n * v.z
+    }
+
+    pub fn cos_theta(&self, v: &Vector3<f64>) -> f64 {
+        v.dot(&self.n)
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use gltf::{camera::Projection, mesh::Mode, Gltf};
+use nalgebra::{Matrix3, Matrix4, Point3, Vector2, Vector3};
+
+use crate::{
+    camera::Camera, dielectric::Dielectric, diffuselight::DiffuseLight, hitrecord::Hitable, lambertian::Lambertian, loaderror::LoadError,
+    material::Material, mesh::TriangleMesh, metal::Metal,
+};
+
+pub struct GltfCamera {
+    pub name: Option<String>,
+    pub camera: Camera,
+}
+
+pub struct GltfScene {
+    pub objects: Vec<Arc<dyn Hitable>>,
+    pub cameras: Vec<GltfCamera>,
+}
+
+// Translate a metallic-roughness material onto the closest material we can render
+pub fn convert_material(material: &gltf::Material) -> Arc<dyn Material> {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, alpha] = pbr.base_color_factor();
+    let base_color = Vector3::new(r as f64, g as f64, b as f64);
+    let transmission = material.transmission().map_or(0.0, |t| t.transmission_factor());
+    let ior = material.ior().unwrap_or(1.5) as f64;
+    let [er, eg, eb] = material.emissive_factor();
+    let strength = material.emissive_strength().unwrap_or(1.0) as f64;
+    let emission = Vector3::new(er as f64, eg as f64, eb as f64) * strength;
+
+    if emission.max() > 0.0 {
+        Arc::new(DiffuseLight::new(emission))
+    } else if transmission > 0.5 || (material.alpha_mode() == gltf::material::AlphaMode::Blend && alpha < 0.5) {
+        let mut glass = Dielectric::new(ior);
+        // The roughness factor defaults to 1, which would frost every glass that leaves it out
+        let roughness = pbr.roughness_factor();
+        if roughness < 1.0 || pbr.metallic_roughness_texture().is_some() {
+            glass = glass.with_roughness(roughness as f64);
+        }
+        // The attenuation distance is infinite, so nothing is absorbed, unless the file says otherwise
+        if let Some(volume) = material.volume() {
+            let [ar, ag, ab] = volume.attenuation_color();
+            glass = glass.with_tint(Vector3::new(ar as f64, ag as f64, ab as f64), volume.attenuation_distance() as f64);
+        }
+        Arc::new(glass)
+    } else if pbr.metallic_factor() >= 0.5 {
+        Arc::new(Metal::new(base_color, pbr.roughness_factor() as f64))
+    } else {
+        Arc::new(Lambertian::new(base_color))
+    }
+}
+
+fn to_matrix(columns: [[f32; 4]; 4]) -> Matrix4<f64> {
+    Matrix4::from_fn(|row, column| columns[column][row] as f64)
+}
+
+struct Importer<'a> {
+    path: &'a Path,
+    buffers: Vec<gltf::buffer::Data>,
+    aspect_ratio: f64,
+    default_material: Arc<dyn Material>,
+    materials: HashMap<usize, Arc<dyn Material>>,
+    scene: GltfScene,
+}
+
+impl<'a> Importer<'a> {
+    fn visit(&mut self, node: &gltf::Node, parent: &Matrix4<f64>) -> Result<(), LoadError> {
+        let transform = parent * to_matrix(node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if let Some(object) = self.primitive(&primitive, &transform)? {
+                    self.scene.objects.push(object);
+                }
+            }
+        }
+
+        if let Some(camera) = node.camera() {
+            // Orthographic cameras have no equivalent in our pinhole/thin-lens camera
+            if let Projection::Perspective(perspective) = camera.projection() {
+                let origin = transform.transform_point(&Point3::origin());
+                let forward = transform.transform_vector(&-Vector3::z());
+                let up = transform.transform_vector(&Vector3::y());
+                let aspect_ratio = perspective.aspect_ratio().map_or(self.aspect_ratio, |a| a as f64);
+                self.scene.cameras.push(GltfCamera {
+                    name: camera.name().map(str::to_string),
+                    camera: Camera::new(
+                        origin,
+                        origin + forward,
+                        up,
+                        (perspective.yfov() as f64).to_degrees(),
+                        aspect_ratio,
+                        0.0,
+                        1.0,
+                    ),
+                });
+            }
+        }
+
+        for child in node.children() {
+            self.visit(&child, &transform)?;
+        }
+        Ok(())
+    }
+
+    fn primitive(
+        &mut self,
+        primitive: &gltf::Primitive,
+        transform: &Matrix4<f64>,
+    ) -> Result<Option<Arc<dyn Hitable>>, LoadError> {
+        let mode = primitive.mode();
+        if !matches!(mode, Mode::Triangles | Mode::TriangleStrip | Mode::TriangleFan) {
+            return Ok(None);
+        }
+        let reader = primitive.reader(|buffer| self.buffers.get(buffer.index()).map(|data| &data.0[..]));
+
+        let positions: Vec<Point3<f64>> = match reader.read_positions() {
+            Some(positions) => positions
+                .map(|[x, y, z]| transform.transform_point(&Point3::new(x as f64, y as f64, z as f64)))
+                .collect(),
+            None => return Err(LoadError::format(self.path, "mesh primitive has no POSITION attribute")),
+        };
+        let linear = transform.fixed_slice::<3, 3>(0, 0).into_owned();
+        let normal_matrix = linear.try_inverse().map_or_else(Matrix3::identity, |m| m.transpose());
+        let normals: Vec<Vector3<f64>> = reader
+            .read_normals()
+            .map(|normals| {
+                normals
+                    .map(|[x, y, z]| (normal_matrix * Vector3::new(x as f64, y as f64, z as f64)).normalize())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let uvs: Vec<Vector2<f64>> = reader
+            .read_tex_coords(0)
+            .map(|uvs| uvs.into_f32().map(|[u, v]| Vector2::new(u as f64, v as f64)).collect())
+            .unwrap_or_default();
+        let colors: Vec<Vector3<f64>> = reader
+            .read_colors(0)
+            .map(|colors| colors.into_rgb_f32().map(|[r, g, b]| Vector3::new(r as f64, g as f64, b as f64)).collect())
+            .unwrap_or_default();
+
+        let vertices: Vec<usize> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+            None => (0..positions.len()).collect(),
+        };
+        let mut indices: Vec<[usize; 3]> = match mode {
+            Mode::TriangleStrip => (2..vertices.len())
+                .map(|i| {
+                    if i % 2 == 0 {
+                        [vertices[i - 2], vertices[i - 1], vertices[i]]
+                    } else {
+                        [vertices[i - 1], vertices[i - 2], vertices[i]]
+                    }
+                })
+                .collect(),
+            Mode::TriangleFan => (2..vertices.len())
+                .map(|i| [vertices[0], vertices[i - 1], vertices[i]])
+                .collect(),
+            _ => vertices.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect(),
+        };
+        if indices.is_empty() {
+            return Ok(None);
+        }
+        if let Some(bad) = indices.iter().flatten().find(|&&i| i >= positions.len()) {
+            return Err(LoadError::format(
+                self.path,
+                format!("mesh index {} out of range ({} vertices)", bad, positions.len()),
+            ));
+        }
+        // Mirroring transforms flip the winding, which would turn normals inside out
+        if linear.determinant() < 0.0 {
+            for triangle in indices.iter_mut() {
+                triangle.swap(1, 2);
+            }
+        }
+
+        let material = match primitive.material().index() {
+            Some(index) => self
+                .materials
+                .entry(index)
+                .or_insert_with(|| convert_material(&primitive.material()))
+                .clone(),
+            None => self.default_material.clone(),
+        };
+        let normals = if normals.len() == positions.len() { normals } else { Vec::new() };
+        let uvs = if uvs.len() == positions.len() { uvs } else { Vec::new() };
+        let colors = if colors.len() == positions.len() { colors } else { Vec::new() };
+        let mesh = TriangleMesh::new(positions, normals, uvs, indices, material).with_colors(colors);
+        Ok(Some(Arc::new(mesh)))
+    }
+}
+
+// Import the default scene of a .gltf or .glb file. `aspect_ratio` is used for cameras
+// that do not specify one; `default_material` for primitives without a material.
+pub fn load_gltf(
+    path: impl AsRef<Path>,
+    aspect_ratio: f64,
+    default_material: Arc<dyn Material>,
+) -> Result<GltfScene, LoadError> {
+    let path = path.as_ref();
+    let gltf = Gltf::open(path).map_err(|e| match e {
+        gltf::Error::Io(e) => LoadError::io(path, e),
+        gltf::Error::Deserialize(e) if e.is_syntax() || e.is_eof() => LoadError::parse(path, e.line(), e.to_string()),
+        e => LoadError::format(path, e.to_string()),
+    })?;
+    let buffers = gltf::import_buffers(&gltf.document, path.parent(), gltf.blob.clone())
+        .map_err(|e| LoadError::format(path, e.to_string()))?;
+
+    let document = &gltf.document;
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| LoadError::format(path, "file contains no scene"))?;
+
+    let mut importer = Importer {
+        path,
+        buffers,
+        aspect_ratio,
+        default_material,
+        materials: HashMap::new(),
+        scene: GltfScene { objects: Vec::new(), cameras: Vec::new() },
+    };
+    for node in scene.nodes() {
+        importer.visit(&node, &Matrix4::identity())?;
+    }
+    Ok(importer.scene)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lobes, util::temp_file};
+
+    fn material_lobes(material: &str) -> Lobes {
+        let json = format!(
+            r#"{{"asset": {{"version": "2.0"}}, "extensionsUsed": ["KHR_materials_transmission"], "materials": [{}]}}"#,
+            material
+        );
+        let gltf = Gltf::from_slice(json.as_bytes()).unwrap();
+        let material = gltf.materials().next().unwrap();
+        convert_material(&material).lobes()
+    }
+
+    // A triangle, moved up by its node, seen from a camera 3 units away
+    const TRIANGLE: &str = r#"{
+        "asset": {"version": "2.0"},
+        "scene": 0,
+        "scenes": [{"nodes": [0, 1]}],
+        "nodes": [
+            {"mesh": 0, "translation": [0, 2, 0]},
+            {"camera": 0, "translation": [0, 0, 3]}
+        ],
+        "cameras": [{"type": "perspective", "perspective": {"yfov": 0.8, "znear": 0.1}}],
+        "meshes": [{"primitives": [{"attributes": {"POSITION": 0}}]}],
+        "accessors": [{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0]}],
+        "bufferViews": [{"buffer": 0, "byteLength": 36}],
+        "buffers": [{"byteLength": 36, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA"}]
+    }"#;
+
+    fn grey() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Vector3::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn imports_meshes_through_their_nodes_and_cameras() {
+        let scene = load_gltf(temp_file("triangle.gltf", TRIANGLE), 1.0, grey()).unwrap();
+        assert_eq!((scene.objects.len(), scene.cameras.len()), (1, 1));
+        let bounds = scene.objects[0].bounding_box(0.0, 0.0).unwrap();
+        assert!((bounds.min.y - 2.0).abs() < 1e-3 && (bounds.max.y - 3.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn reports_the_line_of_broken_json() {
+        let broken = TRIANGLE.replace(r#""scene": 0,"#, r#""scene": 0"#);
+        match load_gltf(temp_file("broken.gltf", broken), 1.0, grey()).err().unwrap() {
+            LoadError::Parse { line, .. } => assert_eq!(line, 4),
+            other => panic!("expected a parse error, got {}", other),
+        }
+    }
+
+    #[test]
+    fn transmissive_material_without_roughness_is_clear_glass() {
+        let lobes = material_lobes(r#"{"extensions": {"KHR_materials_transmission": {"transmissionFactor": 1.0}}}"#);
+        assert!(lobes.is_specular());
+        assert!(lobes.contains(Lobes::TRANSMISSION));
+    }
+
+    #[test]
+    fn transmissive_material_with_roughness_is_frosted_glass() {
+        let lobes = material_lobes(
+            r#"{"pbrMetallicRoughness": {"roughnessFactor": 0.5}, "extensions": {"KHR_materials_transmission": {"transmissionFactor": 1.0}}}"#,
+        );
+        assert!(!lobes.is_specular());
+        assert!(lobes.contains(Lobes::GLOSSY | Lobes::TRANSMISSION));
+    }
+}
@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use nalgebra::{Point3, Vector3};
+
+use crate::{
+    aabb::AABB,
+    hitrecord::{HitRecord, Hitable},
+    material::{Lobes, Material, ScatterRecord},
+    medium::medium_hit,
+    ray::Ray,
+    spectrum::{blackbody_normalized, blackbody_rgb, upsample, wavelength},
+    util::random_f64,
+    voxelgrid::VoxelGrid,
+};
+
+// Entries in the table of black body colours emission is looked up in
+const BLACKBODY_TABLE_SIZE: usize = 1024;
+// Below this transmittance ratio tracking plays Russian roulette with the estimate
+const ROULETTE_TRANSMITTANCE: f64 = 0.1;
+
+// Position of `p` inside `bounds`, scaled to the unit cube
+fn grid_coordinates(bounds: &AABB, p: &Point3<f64>) -> Point3<f64> {
+    Point3::from((p - bounds.min).component_div(&(bounds.max - bounds.min)))
+}
+
+// Light given off by hot parts of a medium, from a temperature grid over the same box as its
+// density. Emission is black body light with the colour of the temperature at each point,
+// spectrum scaled to a peak of 1, times `strength`.
+pub struct GridEmission {
+    temperature: VoxelGrid,
+    bounds: AABB,
+    // Kelvin per grid unit, and kelvin added after scaling
+    temperature_scale: f64,
+    temperature_offset: f64,
+    strength: f64,
+    // Colours from 0 to `max_temperature` kelvin
+    table: Vec<Vector3<f64>>,
+    max_temperature: f64,
+}
+
+impl GridEmission {
+    pub fn new(temperature: VoxelGrid, temperature_scale: f64, temperature_offset: f64, strength: f64) -> Self {
+        let max_temperature = (temperature.max() * temperature_scale + temperature_offset).max(1.0);
+        let table = (0..BLACKBODY_TABLE_SIZE)
+            .map(|i| blackbody_rgb(max_temperature * i as f64 / (BLACKBODY_TABLE_SIZE - 1) as f64))
+            .collect();
+        GridEmission {
+            bounds: temperature.bounds,
+            temperature,
+            temperature_scale,
+            temperature_offset,
+            strength,
+            table,
+            max_temperature,
+        }
+    }
+
+    // Place the temperature grid over `bounds` instead of the box recorded in its file
+    pub fn with_bounds(mut self, bounds: AABB) -> Self {
+        self.bounds = bounds;
+        self
+    }
+
+    pub fn radiance(&self, p: &Point3<f64>) -> Vector3<f64> {
+        let temperature = self.temperature.lookup(&grid_coordinates(&self.bounds, p)) * self.temperature_scale
+            + self.temperature_offset;
+        if temperature <= 0.0 {
+            return Vector3::zeros();
+        }
+        // Spectral rendering takes the black body spectrum at the path's wavelength directly
+        if let Some(lambda) = wavelength() {
+            return Vector3::repeat(blackbody_normalized(lambda, temperature) * self.strength);
+        }
+        let x = (temperature / self.max_temperature * (BLACKBODY_TABLE_SIZE - 1) as f64).min((BLACKBODY_TABLE_SIZE - 1) as f64);
+        let i = (x as usize).min(BLACKBODY_TABLE_SIZE - 2);
+        let f = x - i as f64;
+        (self.table[i] * (1.0 - f) + self.table[i + 1] * f) * self.strength
+    }
+}
+
+// Phase function of an emissive medium. Where a ray collides with the medium, the part of the
+// collision that is absorption rather than scattering, 1 - albedo, gives off the emission, so
+// on average emission is collected in proportion to the absorption coefficient.
+struct EmissiveVolume {
+    phase_function: Arc<dyn Material>,
+    albedo: Vector3<f64>,
+    emission: GridEmission,
+}
+
+impl Material for EmissiveVolume {
+    fn sample(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
+        self.phase_function.sample(ray_in, hit_record)
+    }
+
+    fn eval(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vector3<f64>) -> Vector3<f64> {
+        self.phase_function.eval(ray_in, hit_record, direction)
+    }
+
+    fn pdf(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vector3<f64>) -> f64 {
+        self.phase_function.pdf(ray_in, hit_record, direction)
+    }
+
+    fn lobes(&self) -> Lobes {
+        self.phase_function.lobes()
+    }
+
+    fn emitted(&self, _ray_in: &Ray, hit_record: &HitRecord) -> Vector3<f64> {
+        let absorption = Vector3::new(1.0, 1.0, 1.0) - self.albedo;
+        upsample(absorption).component_mul(&self.emission.radiance(&hit_record.p))
+    }
+}
+
+// Medium whose density varies through a box, given by a voxel grid such as a smoke simulation
+// writes out. Collisions are found by delta tracking (Woodcock tracking): tentative collisions
+// are drawn as if the whole box had the grid's largest density, and each is real with the
+// ratio of the density there to that maximum. Shadow rays use ratio tracking instead, which
+// multiplies up the chances of passing the tentative collisions into a transmittance estimate
+// with far less noise than stopping at the first real one.
+pub struct GridMedium {
+    density: VoxelGrid,
+    bounds: AABB,
+    // Extinction coefficient per unit of grid value
+    density_scale: f64,
+    majorant: f64,
+    material: Arc<dyn Material>,
+}
+
+impl GridMedium {
+    pub fn new(density: VoxelGrid, density_scale: f64, phase_function: Arc<dyn Material>) -> Self {
+        GridMedium {
+            bounds: density.bounds,
+            majorant: density.max() * density_scale,
+            density,
+            density_scale,
+            material: phase_function,
+        }
+    }
+
+    // Place the grid over `bounds` instead of the box recorded in its file
+    pub fn with_bounds(mut self, bounds: AABB) -> Self {
+        self.bounds = bounds;
+        self
+    }
+
+    // `albedo` is the fraction of collisions that scatter rather than absorb, and must be the
+    // one the phase function was made with
+    pub fn with_emission(mut self, albedo: Vector3<f64>, emission: GridEmission) -> Self {
+        let emission = emission.with_bounds(self.bounds);
+        self.material = Arc::new(EmissiveVolume { phase_function: self.material.clone(), albedo, emission });
+        self
+    }
+
+    fn density_at(&self, p: &Point3<f64>) -> f64 {
+        self.density.lookup(&grid_coordinates(&self.bounds, p)) * self.density_scale
+    }
+
+    // Part of (t_min, t_max) in which the ray is inside the box
+    fn span_inside(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let (mut t_start, mut t_end) = (t_min, t_max);
+        for axis in 0..3 {
+            let inv_d = 1.0 / ray.direction[axis];
+            let mut t0 = (self.bounds.min[axis] - ray.origin[axis]) * inv_d;
+            let mut t1 = (self.bounds.max[axis] - ray.origin[axis]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_start = t_start.max(t0);
+            t_end = t_end.min(t1);
+        }
+        (t_start < t_end).then_some((t_start, t_end))
+    }
+
+    // Next tentative collision after t, with exponential steps at the majorant density
+    fn step(&self, ray: &Ray, t: f64) -> f64 {
+        t - (1.0 - random_f64()).ln() / (self.majorant * ray.direction.magnitude())
+    }
+}
+
+impl Hitable for GridMedium {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        if self.majorant <= 0.0 {
+            return None;
+        }
+        let (mut t, t_end) = self.span_inside(ray, t_min, t_max)?;
+        loop {
+            t = self.step(ray, t);
+            if t >= t_end {
+                return None;
+            }
+            if random_f64() * self.majorant < self.density_at(&ray.point_at_parameter(t)) {
+                return Some(medium_hit(ray, t, &self.material));
+            }
+        }
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> f64 {
+        if self.majorant <= 0.0 {
+            return 1.0;
+        }
+        let Some((mut t, t_end)) = self.span_inside(ray, t_min, t_max) else {
+            return 1.0;
+        };
+        let mut transmittance = 1.0;
+        loop {
+            t = self.step(ray, t);
+            if t >= t_end {
+                return transmittance;
+            }
+            transmittance *= 1.0 - self.density_at(&ray.point_at_parameter(t)) / self.majorant;
+            if transmittance < ROULETTE_TRANSMITTANCE {
+                let survival = transmittance.max(0.05);
+                if random_f64() >= survival {
+                    return 0.0;
+                }
+                transmittance /= survival;
+            }
+        }
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.bounds)
+    }
+}
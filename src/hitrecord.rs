@@ -1,10 +1,10 @@
 use std::rc::Rc;
 use std::marker::{Send, Sync};
 use std::sync::Arc;
-use nalgebra::{Vector3, Point3};
+use nalgebra::{Vector2, Vector3, Point3};
 
 use crate::aabb::AABB;
-use crate::{material::Material, ray::Ray};
+use crate::{material::Material, ray::Ray, spectrum::upsample};
 
 #[derive(Clone)]
 pub struct HitRecord {
@@ -12,10 +12,53 @@ pub struct HitRecord {
     pub p: Point3<f64>,
     pub normal: Vector3<f64>,
     pub material: Arc<dyn Material>,
+    // Surface texture coordinates at the hit point
+    pub uv: Vector2<f64>,
+    // Barycentric coordinates (b1, b2) of the hit, only set for triangles
+    pub barycentric: Option<Vector2<f64>>,
+    // Interpolated per-vertex colour, for meshes that carry one
+    pub color: Option<Vector3<f64>>,
+}
+impl HitRecord {
+    // Material base colour modulated by the vertex colour at the hit, if any, as it applies to
+    // the path's wavelength in spectral rendering
+    pub fn albedo(&self, base: Vector3<f64>) -> Vector3<f64> {
+        upsample(match self.color {
+            Some(color) => base.component_mul(&color),
+            None => base,
+        })
+    }
 }
 pub trait Hitable : Send + Sync{
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB>;
+    // Fraction of light that gets through the object between t_min and t_max along the ray:
+    // 0 or 1 for surfaces, in between for participating media. Estimates may be random, as
+    // long as they average to the true value.
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> f64 {
+        if self.hit(ray, t_min, t_max).is_some() {
+            0.0
+        } else {
+            1.0
+        }
+    }
+    // Solid angle density with which random() picks `direction` from `origin`; 0 for objects
+    // that cannot be sampled as lights
+    fn pdf_value(&self, _origin: &Point3<f64>, _direction: &Vector3<f64>) -> f64 {
+        0.0
+    }
+    // Unit direction from `origin` towards a point on the object, or None for a wasted sample
+    fn random(&self, _origin: &Point3<f64>) -> Option<Vector3<f64>> {
+        None
+    }
+    // Surface area, 0 for objects that cannot start light paths
+    fn area(&self) -> f64 {
+        0.0
+    }
+    // Point drawn uniformly over the surface and the normal there
+    fn sample_surface(&self) -> Option<(Point3<f64>, Vector3<f64>)> {
+        None
+    }
 }
 pub struct UnsafeSyncHitable {
     hitable: Box<dyn Hitable>,
@@ -0,0 +1,65 @@
+use crate::{Ray, hitrecord::{Hitable, HitRecord}, aabb::AABB};
+use std::sync::Arc;
+
+use nalgebra::{Matrix3, Matrix4, Point3};
+
+// Places a shared object in the world through an affine transform, by moving
+// rays into object space rather than copying the geometry
+pub struct Instance {
+    object: Arc<dyn Hitable>,
+    transform: Matrix4<f64>,
+    inverse: Matrix4<f64>,
+    normal_matrix: Matrix3<f64>,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hitable>, transform: Matrix4<f64>) -> Self {
+        let inverse = transform.try_inverse().expect("instance transform must be invertible");
+        let normal_matrix = inverse.fixed_slice::<3, 3>(0, 0).transpose();
+        Instance {
+            object,
+            transform,
+            inverse,
+            normal_matrix,
+        }
+    }
+}
+
+impl Hitable for Instance {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // The direction is not renormalised, so t means the same thing in both spaces
+        let local_ray = Ray::new(
+            self.inverse.transform_point(&ray.origin),
+            self.inverse.transform_vector(&ray.direction),
+        );
+        let mut hit = self.object.hit(&local_ray, t_min, t_max)?;
+        hit.p = self.transform.transform_point(&hit.p);
+        hit.normal = (self.normal_matrix * hit.normal).normalize();
+        Some(hit)
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let local_ray = Ray::new(
+            self.inverse.transform_point(&ray.origin),
+            self.inverse.transform_vector(&ray.direction),
+        );
+        self.object.transmittance(&local_ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        let local = self.object.bounding_box(t0, t1)?;
+        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for corner in 0..8 {
+            let local_corner = Point3::new(
+                if corner & 1 == 0 { local.min.x } else { local.max.x },
+                if corner & 2 == 0 { local.min.y } else { local.max.y },
+                if corner & 4 == 0 { local.min.z } else { local.max.z },
+            );
+            let p = self.transform.transform_point(&local_corner);
+            min = min.inf(&p);
+            max = max.sup(&p);
+        }
+        Some(AABB::new(min, max))
+    }
+}
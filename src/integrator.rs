@@ -0,0 +1,160 @@
+use nalgebra::Vector3;
+
+use crate::{
+    hitrecord::Hitable,
+    ray::Ray,
+    renderer::{MisHeuristic, RenderSettings},
+    scene::Scene,
+    util::{hit_light, random_f64, sample_lights},
+};
+
+// Light an integrator delivers to some other pixel than the one it was asked about, e.g. from a
+// light path that reaches the camera. Splats are summed into the image and averaged over the
+// samples per pixel along with the camera samples.
+pub struct Splat {
+    pub x: u32,
+    // Row from the top of the image
+    pub y: u32,
+    pub radiance: Vector3<f64>,
+}
+
+// Estimates the radiance arriving at the camera along a ray. `world` is the scene's objects in
+// whichever acceleration structure the renderer built.
+pub trait Integrator: Send + Sync {
+    fn radiance(&self, ray: &Ray, world: &dyn Hitable, scene: &Scene, splats: &mut Vec<Splat>) -> Vector3<f64>;
+    // Wavelength and its density that every sample must be rendered at in spectral rendering,
+    // for integrators that prepared light for one; None lets each sample draw its own
+    fn wavelength(&self) -> Option<(f64, f64)> {
+        None
+    }
+}
+
+// Unidirectional path tracer with next-event estimation, combined with BSDF sampling by MIS
+pub struct PathTracer {
+    max_depth: u32,
+    // Bounces before Russian roulette may end a path
+    roulette_depth: u32,
+    heuristic: MisHeuristic,
+}
+
+impl PathTracer {
+    pub fn new(settings: &RenderSettings) -> Self {
+        PathTracer {
+            max_depth: settings.max_depth,
+            roulette_depth: settings.roulette_depth,
+            heuristic: settings.mis,
+        }
+    }
+}
+
+impl Integrator for PathTracer {
+    fn radiance(&self, ray: &Ray, world: &dyn Hitable, scene: &Scene, _splats: &mut Vec<Splat>) -> Vector3<f64> {
+        let mut radiance = Vector3::new(0.0, 0.0, 0.0);
+        // Weight the path so far gives to radiance arriving along `ray`
+        let mut throughput = Vector3::new(1.0, 1.0, 1.0);
+        let mut ray = Ray::new(ray.origin, ray.direction);
+        // Density with which the previous vertex sampled `ray`, or None for camera rays and
+        // specular bounces. Lights reached by a sampled ray are weighted against light sampling.
+        let mut bsdf_pdf: Option<f64> = None;
+
+        for bounce in 0..self.max_depth {
+            // Lights are not part of the world, so only look for surfaces, and fog, in front of
+            // the nearest one
+            let light_hit = hit_light(&scene.lights, &ray, 0.001, f64::INFINITY);
+            let hit = world.hit(&ray, 0.001, light_hit.map_or(f64::INFINITY, |(_, t, _)| t));
+            if let (None, Some((light, _, emitted))) = (&hit, light_hit) {
+                let weight = bsdf_pdf.map_or(1.0, |pdf| self.heuristic.weight(pdf, light.pdf(&ray.origin, &ray.direction)));
+                radiance += throughput.component_mul(&emitted) * weight;
+                break;
+            }
+            let Some(hit_record) = hit else {
+                let background = scene.background.color(&ray.direction);
+                let weight = bsdf_pdf.map_or(1.0, |pdf| self.heuristic.weight(pdf, scene.background.pdf(&ray.direction)));
+                radiance += throughput.component_mul(&background) * weight;
+                break;
+            };
+
+            let material = &hit_record.material;
+            radiance += throughput.component_mul(&material.emitted(&ray, &hit_record));
+            let Some(scatter) = material.sample(&ray, &hit_record) else {
+                break;
+            };
+
+            // Next-event estimation for the smooth lobes, then continue the path by BSDF sampling
+            if material.lobes().is_smooth() {
+                radiance += throughput.component_mul(&sample_lights(&ray, &hit_record, world, scene, self.heuristic, |_| true));
+            }
+            throughput.component_mul_assign(&scatter.attenuation);
+
+            // Russian roulette: end paths that can carry little light, and scale the survivors up
+            // by the inverse of their survival chance so the estimate stays unbiased
+            if bounce >= self.roulette_depth {
+                let survival = throughput.max().min(0.95);
+                if survival <= 0.0 || random_f64() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+
+            bsdf_pdf = if scatter.is_specular() { None } else { Some(scatter.pdf) };
+            ray = scatter.scattered;
+        }
+        radiance
+    }
+}
+
+// Debug integrator: shading normals mapped to RGB, black where nothing is hit
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn radiance(&self, ray: &Ray, world: &dyn Hitable, _scene: &Scene, _splats: &mut Vec<Splat>) -> Vector3<f64> {
+        match world.hit(ray, 0.001, f64::INFINITY) {
+            Some(hit_record) => 0.5 * (hit_record.normal.try_normalize(0.0).unwrap_or_default() + Vector3::new(1.0, 1.0, 1.0)),
+            None => Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nalgebra::Point3;
+
+    use super::*;
+    use crate::{
+        background::Background,
+        camera::Camera,
+        light::Light,
+        medium::Fog,
+        phase::Isotropic,
+        scene::Accelerator,
+        util::{seed_rng, visibility},
+    };
+
+    #[test]
+    fn fog_dims_lights_seen_directly_as_much_as_sampled_ones() {
+        let camera = Camera::new(Point3::origin(), Point3::new(0.0, 0.0, -1.0), Vector3::new(0.0, 1.0, 0.0), 40.0, 1.0, 0.0, 1.0);
+        // Fog that only absorbs, so no light reaches the camera but straight from the light
+        let fog = Fog::new(0.2, Arc::new(Isotropic::new(Vector3::zeros())));
+        let scene = Scene::new(camera, Vec::new())
+            .with_light(Light::sphere(Point3::new(0.0, 0.0, -5.0), 1.0, Vector3::new(1.0, 1.0, 1.0), 1.0))
+            .with_background(Background::Solid(Vector3::zeros()))
+            .with_fog(fog);
+        let world = scene.build_world(Accelerator::List);
+
+        seed_rng(11);
+        let origin = Point3::origin();
+        let sample = scene.lights[0].sample(&origin).unwrap();
+        let sampled = sample.radiance * visibility(&*world, &origin, &sample.direction, sample.distance);
+
+        let path_tracer = PathTracer::new(&RenderSettings::default());
+        let ray = Ray::new(origin, sample.direction);
+        let samples = 100_000;
+        let seen = (0..samples)
+            .map(|_| path_tracer.radiance(&ray, &*world, &scene, &mut Vec::new()))
+            .sum::<Vector3<f64>>()
+            / samples as f64;
+        assert!((seen - sampled).abs().max() < 0.01 * sampled.max(), "seen {:?} sampled {:?}", seen, sampled);
+    }
+}
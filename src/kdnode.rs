@@ -68,6 +68,17 @@ impl Hitable for KdNode {
         }
     }
 
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> f64 {
+        if let Some(hitable) = &self.hitable {
+            return hitable.transmittance(ray, t_min, t_max);
+        }
+        let left = self.left.as_ref().map_or(1.0, |l| l.transmittance(ray, t_min, t_max));
+        if left == 0.0 {
+            return 0.0;
+        }
+        left * self.right.as_ref().map_or(1.0, |r| r.transmittance(ray, t_min, t_max))
+    }
+
     fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
         unimplemented!();
     }
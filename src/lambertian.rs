@@ -1,6 +1,14 @@
-use nalgebra::{Vector3, Unit};
+use std::f64::consts::PI;
 
-use crate::{material::Material, ray::Ray, hitrecord::HitRecord, util::random_unit_vector};
+use nalgebra::Vector3;
+
+use crate::{
+    frame::Frame,
+    material::{Lobes, Material, ScatterRecord},
+    ray::Ray,
+    hitrecord::HitRecord,
+    util::random_cosine_direction,
+};
 
 pub struct Lambertian {
     albedo: Vector3<f64>,
@@ -10,35 +18,35 @@ impl Lambertian {
     pub fn new(albedo: Vector3<f64>) -> Self {
         Lambertian { albedo }
     }
-    fn blinn_phong(&self, ray_in: &Ray, hit_record: &HitRecord, light_dir: &Unit<Vector3<f64>>) -> Vector3<f64> {
-        let view_dir = -ray_in.direction.normalize();
-        let halfway_dir = (light_dir.as_ref() + view_dir).normalize();
-
-        let ambient = self.albedo * 0.06;
-        let diffuse = self.albedo * hit_record.normal.dot(light_dir.as_ref()).max(0.01);
-        //let specular = Vector3::new(1.0, 1.0, 1.0) * hit_record.normal.dot(&halfway_dir).max(0.0).powf(30.0);
-
-        return ambient + diffuse ;//+ specular;
-    }
 }
 
 impl Material for Lambertian {
-    // fn scatter(&self, _: &Ray, hit_record: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
-    //     let target = hit_record.p + hit_record.normal + random_in_unit_sphere();
-    //     let scattered = Ray::new(hit_record.p, target - hit_record.p);
-    //     Some((self.albedo, scattered))
-    // }
-
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
-        let scatter_direction = &hit_record.normal + random_unit_vector(hit_record.normal);
-        let scattered = Ray::new(hit_record.p, scatter_direction);
-        //Some((self.albedo, scattered))
-        if scattered.direction.dot(&hit_record.normal) > 0.0 {
-            let light_dir = Unit::new_normalize(Vector3::new(1.0, -1.0, 1.0)); // example light direction
-            let color = self.blinn_phong(ray_in, hit_record, &light_dir);
-            Some((color, scattered))
-        } else {
-            None
+    // Cosine weighted sampling, so brdf * cos / pdf = (albedo / pi) * cos / (cos / pi) = albedo
+    fn sample(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
+        let frame = Frame::facing(hit_record.normal, &ray_in.direction);
+        let local = random_cosine_direction();
+        if local.z <= 0.0 {
+            return None;
         }
+        Some(ScatterRecord {
+            attenuation: hit_record.albedo(self.albedo),
+            scattered: Ray::new(hit_record.p, frame.to_world(&local)),
+            pdf: local.z / PI,
+            lobe: Lobes::DIFFUSE | Lobes::REFLECTION,
+            frame,
+        })
+    }
+
+    fn eval(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vector3<f64>) -> Vector3<f64> {
+        hit_record.albedo(self.albedo) * self.pdf(ray_in, hit_record, direction)
     }
-}
\ No newline at end of file
+
+    fn pdf(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vector3<f64>) -> f64 {
+        let frame = Frame::facing(hit_record.normal, &ray_in.direction);
+        (frame.cos_theta(&direction.normalize()) / PI).max(0.0)
+    }
+
+    fn lobes(&self) -> Lobes {
+        Lobes::DIFFUSE | Lobes::REFLECTION
+    }
+}
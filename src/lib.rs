@@ -0,0 +1,53 @@
+pub mod aabb;
+pub mod ray;
+pub mod dielectric;
+pub mod camera;
+pub mod material;
+pub mod lambertian;
+pub mod hitrecord;
+pub mod sphere;
+pub mod util;
+pub mod cylinder;
+pub mod metal;
+pub mod cube;
+pub mod light;
+pub mod bvhnode;
+pub mod cone;
+pub mod kdnode;
+pub mod kdtree;
+pub mod triangle;
+pub mod mesh;
+pub mod loaderror;
+pub mod obj;
+pub mod ply;
+pub mod stl;
+pub mod gltfimport;
+pub mod instance;
+pub mod scene;
+pub mod randomscene;
+pub mod renderer;
+pub mod diffuselight;
+pub mod background;
+pub mod blinphong;
+pub mod frame;
+pub mod quad;
+pub mod disk;
+pub mod distribution;
+pub mod envmap;
+pub mod sky;
+pub mod integrator;
+pub mod bdpt;
+pub mod photonmap;
+pub mod mlt;
+pub mod phase;
+pub mod medium;
+pub mod spectrum;
+pub mod voxelgrid;
+pub mod gridmedium;
+
+pub use hitrecord::{HitRecord, Hitable};
+pub use ray::Ray;
+pub use renderer::{Framebuffer, IntegratorKind, MisHeuristic, RenderSettings, Renderer};
+pub use background::Background;
+pub use integrator::Integrator;
+pub use scene::{Accelerator, Scene};
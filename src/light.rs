@@ -1,34 +1,402 @@
-use nalgebra::Point3;
+use std::{f64::consts::PI, sync::Arc};
 
-pub struct Light {
-    source:Point3<f64>,
-    radius:f64
+use nalgebra::{Point3, Vector3};
+
+use crate::{hitrecord::Hitable, ray::Ray, spectrum::upsample, util::{orthonormal_basis, random_cosine_direction, random_f64}};
+
+// Lights the integrator samples directly. Intensity scales the colour: radiant intensity for
+// point and spot lights, irradiance for directional lights and radiance for sphere lights.
+// Only sphere and area lights have a surface that rays can hit; the others are reached by
+// sampling alone.
+#[derive(Clone)]
+pub enum Light {
+    Point {
+        position: Point3<f64>,
+        color: Vector3<f64>,
+        intensity: f64,
+    },
+    // A point light restricted to a cone, fading out over the outer `falloff` of its angle
+    Spot {
+        position: Point3<f64>,
+        direction: Vector3<f64>,
+        color: Vector3<f64>,
+        intensity: f64,
+        cos_cone: f64,
+        cos_falloff_start: f64,
+    },
+    // Parallel light travelling along `direction`, like the sun
+    Directional {
+        direction: Vector3<f64>,
+        color: Vector3<f64>,
+        intensity: f64,
+    },
+    Sphere {
+        center: Point3<f64>,
+        radius: f64,
+        color: Vector3<f64>,
+        intensity: f64,
+    },
+    // An emissive object that can sample itself by solid angle (Quad, Disk); its emission
+    // comes from the object's material
+    Area {
+        object: Arc<dyn Hitable>,
+    },
+}
+
+// Direction towards a point chosen on a light, with the light arriving from it
+pub struct LightSample {
+    pub direction: Vector3<f64>,
+    // Infinite for directional lights
+    pub distance: f64,
+    pub radiance: Vector3<f64>,
+    // Solid angle density of the direction, 1 for delta lights
+    pub pdf: f64,
+}
+
+// A ray leaving a light, to start a path from the light's side
+pub struct Emission {
+    pub origin: Point3<f64>,
+    // Surface normal at the origin; zero for point and spot lights
+    pub normal: Vector3<f64>,
+    pub direction: Vector3<f64>,
+    pub radiance: Vector3<f64>,
+    // Density of the origin per unit area (1 for point and spot lights) and of the direction
+    // per unit solid angle
+    pub pdf_position: f64,
+    pub pdf_direction: f64,
 }
 
 
 impl Light {
-    // Constructor to create a new Light instance
-    pub fn new(source: Point3<f64>, radius: f64) -> Self {
-        Light { source, radius }
+    pub fn point(position: Point3<f64>, color: Vector3<f64>, intensity: f64) -> Self {
+        Light::Point { position, color, intensity }
+    }
+
+    // `cone_angle` is the half angle of the lit cone and `falloff` the part of it, measured in
+    // from the edge, over which the light fades out; both in degrees
+    pub fn spot(
+        position: Point3<f64>,
+        direction: Vector3<f64>,
+        color: Vector3<f64>,
+        intensity: f64,
+        cone_angle: f64,
+        falloff: f64,
+    ) -> Self {
+        let cone_angle = cone_angle.clamp(0.0, 180.0);
+        let falloff = falloff.clamp(0.0, cone_angle);
+        Light::Spot {
+            position,
+            direction: direction.normalize(),
+            color,
+            intensity,
+            cos_cone: cone_angle.to_radians().cos(),
+            cos_falloff_start: (cone_angle - falloff).to_radians().cos(),
+        }
+    }
+
+    pub fn directional(direction: Vector3<f64>, color: Vector3<f64>, intensity: f64) -> Self {
+        Light::Directional { direction: direction.normalize(), color, intensity }
+    }
+
+    // A radius of zero degenerates to a point light
+    pub fn sphere(center: Point3<f64>, radius: f64, color: Vector3<f64>, intensity: f64) -> Self {
+        if radius <= 0.0 {
+            Light::Point { position: center, color, intensity }
+        } else {
+            Light::Sphere { center, radius, color, intensity }
+        }
+    }
+
+    pub fn area(object: Arc<dyn Hitable>) -> Self {
+        Light::Area { object }
+    }
+
+    // Colour times intensity, in the units described on the enum; area lights get theirs
+    // from the material wherever they are hit, so they report black here
+    pub fn emission(&self) -> Vector3<f64> {
+        match self {
+            Light::Point { color, intensity, .. }
+            | Light::Spot { color, intensity, .. }
+            | Light::Directional { color, intensity, .. }
+            | Light::Sphere { color, intensity, .. } => upsample(color * *intensity),
+            Light::Area { .. } => Vector3::zeros(),
+        }
+    }
+
+    // Lights without area can only be found by light sampling, never by a BSDF sampled ray
+    pub fn is_delta(&self) -> bool {
+        !matches!(self, Light::Sphere { .. } | Light::Area { .. })
     }
 
-    // Getter method for the source position
-    pub fn source(&self) -> Point3<f64> {
-        self.source
+    // Directional lights are infinitely far away, so no path can start on them
+    pub fn is_infinite(&self) -> bool {
+        matches!(self, Light::Directional { .. })
     }
 
-    // Getter method for the radius
-    pub fn radius(&self) -> f64 {
-        self.radius
+    // Density with which sample() picks `direction` from `point`, given that it hits the light
+    pub fn pdf(&self, point: &Point3<f64>, direction: &Vector3<f64>) -> f64 {
+        match self {
+            Light::Sphere { center, radius, .. } => {
+                let distance_squared = (center - point).magnitude_squared();
+                if distance_squared <= radius * radius {
+                    return 0.0;
+                }
+                1.0 / (2.0 * PI * one_minus_cos_cone(*radius, distance_squared))
+            }
+            Light::Area { object } => object.pdf_value(point, direction),
+            _ => 0.0,
+        }
     }
 
-    // Method to update the source position
-    pub fn set_source(&mut self, new_source: Point3<f64>) {
-        self.source = new_source;
+    // Nearest t at which the ray reaches the light's surface, and the radiance it sees there
+    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, Vector3<f64>)> {
+        let (center, radius) = match self {
+            Light::Sphere { center, radius, .. } => (center, radius),
+            Light::Area { object } => {
+                let hit_record = object.hit(ray, t_min, t_max)?;
+                return Some((hit_record.t, hit_record.material.emitted(ray, &hit_record)));
+            }
+            _ => return None,
+        };
+        let oc = ray.origin - center;
+        let a = ray.direction.dot(&ray.direction);
+        let b = oc.dot(&ray.direction);
+        let c = oc.dot(&oc) - radius * radius;
+        let discriminant = b * b - a * c;
+        if discriminant <= 0.0 {
+            return None;
+        }
+        let t = (-b - discriminant.sqrt()) / a;
+        if t > t_min && t < t_max {
+            Some((t, self.emission()))
+        } else {
+            None
+        }
     }
 
-    // Method to update the radius
-    pub fn set_radius(&mut self, new_radius: f64) {
-        self.radius = new_radius;
+    // Pick a direction from `point` towards the light, or None if it cannot light the point
+    pub fn sample(&self, point: &Point3<f64>) -> Option<LightSample> {
+        match self {
+            Light::Point { position, .. } => {
+                let to_light = position - point;
+                let distance_squared = to_light.magnitude_squared();
+                if distance_squared <= 0.0 {
+                    return None;
+                }
+                let distance = distance_squared.sqrt();
+                Some(LightSample {
+                    direction: to_light / distance,
+                    distance,
+                    radiance: self.emission() / distance_squared,
+                    pdf: 1.0,
+                })
+            }
+            Light::Spot { position, direction, cos_cone, cos_falloff_start, .. } => {
+                let to_light = position - point;
+                let distance_squared = to_light.magnitude_squared();
+                if distance_squared <= 0.0 {
+                    return None;
+                }
+                let distance = distance_squared.sqrt();
+                let wi = to_light / distance;
+                let falloff = spot_falloff(-wi.dot(direction), *cos_cone, *cos_falloff_start);
+                if falloff <= 0.0 {
+                    return None;
+                }
+                Some(LightSample {
+                    direction: wi,
+                    distance,
+                    radiance: self.emission() * falloff / distance_squared,
+                    pdf: 1.0,
+                })
+            }
+            Light::Directional { direction, .. } => Some(LightSample {
+                direction: -direction,
+                distance: f64::INFINITY,
+                radiance: self.emission(),
+                pdf: 1.0,
+            }),
+            Light::Sphere { center, radius, .. } => {
+                // Sample uniformly inside the cone the sphere subtends, which is the visible part
+                // only, so no samples are wasted on the back
+                let to_center = center - point;
+                let distance_squared = to_center.magnitude_squared();
+                if distance_squared <= radius * radius {
+                    // Inside the light, every direction sees it and needs no sampling
+                    return None;
+                }
+                let distance = distance_squared.sqrt();
+                let one_minus_cos_max = one_minus_cos_cone(*radius, distance_squared);
+                let cos_theta = 1.0 - random_f64() * one_minus_cos_max;
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * random_f64();
+
+                let w = to_center / distance;
+                let (u, v) = orthonormal_basis(&w);
+                let direction = (u * phi.cos() * sin_theta + v * phi.sin() * sin_theta + w * cos_theta).normalize();
+                // Distance along the direction to the near side of the sphere
+                let b = distance * cos_theta;
+                let t = b - (radius * radius - distance_squared * sin_theta * sin_theta).max(0.0).sqrt();
+
+                Some(LightSample {
+                    direction,
+                    distance: t,
+                    radiance: self.emission(),
+                    pdf: 1.0 / (2.0 * PI * one_minus_cos_max),
+                })
+            }
+            Light::Area { object } => {
+                let direction = object.random(point)?;
+                let pdf = object.pdf_value(point, &direction);
+                let ray = Ray::new(*point, direction);
+                let hit_record = object.hit(&ray, 0.001, f64::INFINITY)?;
+                if pdf <= 0.0 {
+                    return None;
+                }
+                Some(LightSample {
+                    direction,
+                    distance: hit_record.t,
+                    radiance: hit_record.material.emitted(&ray, &hit_record),
+                    pdf,
+                })
+            }
+        }
+    }
+
+    // Pick a ray leaving the light, or None for directional lights and wasted samples
+    pub fn sample_emission(&self) -> Option<Emission> {
+        match self {
+            Light::Point { position, .. } => {
+                let z = 1.0 - 2.0 * random_f64();
+                let r = (1.0 - z * z).max(0.0).sqrt();
+                let phi = 2.0 * PI * random_f64();
+                Some(Emission {
+                    origin: *position,
+                    normal: Vector3::zeros(),
+                    direction: Vector3::new(r * phi.cos(), r * phi.sin(), z),
+                    radiance: self.emission(),
+                    pdf_position: 1.0,
+                    pdf_direction: 1.0 / (4.0 * PI),
+                })
+            }
+            Light::Spot { position, direction, cos_cone, cos_falloff_start, .. } => {
+                if *cos_cone >= 1.0 {
+                    return None;
+                }
+                let cos_theta = 1.0 - random_f64() * (1.0 - cos_cone);
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * random_f64();
+                let (u, v) = orthonormal_basis(direction);
+                Some(Emission {
+                    origin: *position,
+                    normal: Vector3::zeros(),
+                    direction: (u * phi.cos() + v * phi.sin()) * sin_theta + direction * cos_theta,
+                    radiance: self.emission() * spot_falloff(cos_theta, *cos_cone, *cos_falloff_start),
+                    pdf_position: 1.0,
+                    pdf_direction: 1.0 / (2.0 * PI * (1.0 - cos_cone)),
+                })
+            }
+            Light::Directional { .. } => None,
+            Light::Sphere { center, radius, .. } => {
+                let z = 1.0 - 2.0 * random_f64();
+                let r = (1.0 - z * z).max(0.0).sqrt();
+                let phi = 2.0 * PI * random_f64();
+                let normal = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+                let direction = cosine_around(&normal);
+                Some(Emission {
+                    origin: center + normal * *radius,
+                    normal,
+                    direction,
+                    radiance: self.emission(),
+                    pdf_position: 1.0 / (4.0 * PI * radius * radius),
+                    pdf_direction: normal.dot(&direction).max(0.0) / PI,
+                })
+            }
+            Light::Area { object } => {
+                let (origin, normal) = object.sample_surface()?;
+                // Either side may emit; one-sided emitters just return black from the back
+                let mut direction = cosine_around(&normal);
+                if random_f64() < 0.5 {
+                    direction = -direction;
+                }
+                let ray = Ray::new(origin + direction, -direction);
+                let hit_record = object.hit(&ray, 0.5, 1.5)?;
+                Some(Emission {
+                    origin,
+                    normal,
+                    direction,
+                    radiance: hit_record.material.emitted(&ray, &hit_record),
+                    pdf_position: 1.0 / object.area(),
+                    pdf_direction: normal.dot(&direction).abs() / (2.0 * PI),
+                })
+            }
+        }
+    }
+
+    // Density per unit area with which sample_emission() picks its origin; 1 for point and spot
+    // lights
+    pub fn emission_pdf_position(&self) -> f64 {
+        match self {
+            Light::Point { .. } | Light::Spot { .. } => 1.0,
+            Light::Directional { .. } => 0.0,
+            Light::Sphere { radius, .. } => 1.0 / (4.0 * PI * radius * radius),
+            Light::Area { object } => 1.0 / object.area(),
+        }
+    }
+
+    // Density per unit solid angle with which sample_emission() picks `direction` from an origin
+    // with surface normal `normal`
+    pub fn emission_pdf_direction(&self, normal: &Vector3<f64>, direction: &Vector3<f64>) -> f64 {
+        match self {
+            Light::Point { .. } => 1.0 / (4.0 * PI),
+            Light::Spot { direction: axis, cos_cone, .. } => {
+                if *cos_cone < 1.0 && direction.dot(axis) > *cos_cone {
+                    1.0 / (2.0 * PI * (1.0 - cos_cone))
+                } else {
+                    0.0
+                }
+            }
+            Light::Directional { .. } => 0.0,
+            Light::Sphere { .. } => normal.dot(direction).max(0.0) / PI,
+            Light::Area { .. } => normal.dot(direction).abs() / (2.0 * PI),
+        }
+    }
+
+    // Surface normal where `ray` reaches the light at `t`; zero for lights without a surface
+    pub fn normal_at(&self, ray: &Ray, t: f64) -> Vector3<f64> {
+        match self {
+            Light::Sphere { center, radius, .. } => (ray.point_at_parameter(t) - center) / *radius,
+            Light::Area { object } => object
+                .hit(ray, t * (1.0 - 1e-6), t * (1.0 + 1e-6))
+                .map_or(Vector3::zeros(), |hit_record| hit_record.normal),
+            _ => Vector3::zeros(),
+        }
     }
-}
\ No newline at end of file
+}
+
+// Cosine weighted direction in the hemisphere around `normal`
+fn cosine_around(normal: &Vector3<f64>) -> Vector3<f64> {
+    let (s, t) = orthonormal_basis(normal);
+    let local = random_cosine_direction();
+    s * local.x + t * local.y + normal * local.z
+}
+
+// How much of a spot light's intensity leaves at `cos_theta` from its axis
+fn spot_falloff(cos_theta: f64, cos_cone: f64, cos_falloff_start: f64) -> f64 {
+    if cos_theta <= cos_cone {
+        0.0
+    } else if cos_theta >= cos_falloff_start {
+        1.0
+    } else {
+        // Smoothstep across the soft edge of the cone
+        let x = (cos_theta - cos_cone) / (cos_falloff_start - cos_cone);
+        x * x * (3.0 - 2.0 * x)
+    }
+}
+
+// 1 - cos of the half angle a sphere subtends, without cancellation for small distant spheres
+fn one_minus_cos_cone(radius: f64, distance_squared: f64) -> f64 {
+    let sin2_max = radius * radius / distance_squared;
+    let cos_max = (1.0 - sin2_max).max(0.0).sqrt();
+    sin2_max / (1.0 + cos_max)
+}
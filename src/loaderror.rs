@@ -0,0 +1,47 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Error produced by the asset loaders, always naming the offending file
+#[derive(Debug)]
+pub enum LoadError {
+    Io { path: PathBuf, source: io::Error },
+    // A malformed line in a text format
+    Parse { path: PathBuf, line: usize, message: String },
+    // A structural problem that has no meaningful line number (binary files, missing references)
+    Format { path: PathBuf, message: String },
+}
+
+impl LoadError {
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        LoadError::Io { path: path.to_path_buf(), source }
+    }
+
+    pub fn parse(path: &Path, line: usize, message: impl Into<String>) -> Self {
+        LoadError::Parse { path: path.to_path_buf(), line, message: message.into() }
+    }
+
+    pub fn format(path: &Path, message: impl Into<String>) -> Self {
+        LoadError::Format { path: path.to_path_buf(), message: message.into() }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            LoadError::Parse { path, line, message } => write!(f, "{}:{}: {}", path.display(), line, message),
+            LoadError::Format { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -1,434 +1,228 @@
-pub mod aabb;
-pub mod ray;
-pub mod dielectric;
-pub mod camera;
-pub mod material;
-pub mod lambertian;
-pub mod hitrecord;
-pub mod sphere;
-pub mod util;
-pub mod cylinder;
-pub mod metal;
-pub mod cube;
-pub mod light;
-pub mod bvhnode;
-pub mod cone;
-pub mod kdnode;
-pub mod kdtree;
-use bvhnode::BVHNode;
-use camera::Camera;
-use cube::Cube;
-use dielectric::Dielectric;
-use hitrecord::{HitRecord, Hitable};
-use image::{ImageBuffer, Rgb};
-// use kdnode::KdNode;
-
-use kdnode::KdNode;
-use lambertian::Lambertian;
-use metal::Metal;
-use nalgebra::{Point3, Vector3, distance_squared, distance};
-// use rand::Rng;
-use sphere::Sphere;
-use util::{random_f64, ray_color, ray_color_dup};
-use std::collections::HashMap;
-use std::fs::File;
-use std::sync::{Arc, Mutex};
-
-// use std::io::{prelude::*, self};
-use std::rc::Rc;
-use std::io::Write;
-use crate::ray::Ray;
-use rand::{Rng, thread_rng};
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
-
-use rayon::iter::repeat;
-use rayon::prelude::*;
-
-
-
-
-
-
-
-
-
-        
-    //     fn main() {
-    //         // Image
-    //         let aspect_ratio = 16.0 / 9.0;
-    //         let image_width = 400;
-    //         let image_height = (image_width as f64 / aspect_ratio) as u32;
-    //         let samples_per_pixel = 150;
-    //         let max_depth = 60;
-        
-    //         // World
-    //         let world = random_scene();
-        
-    //         // Camera
-    //         let lookfrom = Point3::new(10.0, 4.0, 9.0);
-    //         let lookat = Point3::new(0.0, 0.0, 0.0);
-    //         let vup = Vector3::new(0.0, 1.0, 0.0);
-    //         let dist_to_focus = 10.0;
-    //         let aperture = 0.09;
-    //         let camera = Camera::new(
-    //             lookfrom,
-    //             lookat,
-    //             vup,
-    //             20.0,
-    //             aspect_ratio,
-    //             aperture,
-    //             dist_to_focus,
-    //         );
-        
-    //         //let mut image_data: Vec<u8> = Vec::<u8>::new();
-    //         let mut img = ImageBuffer::new(image_width, image_height);
-    //         //let mut row_data = Vec::<u8>::new();
-    //         for j in (0..image_height).rev() {
-    //             for i in 0..image_width {
-    //                 let mut pixel_color = Vector3::new(0.0, 0.0, 0.0);
-    //                 for _ in 0..samples_per_pixel {
-    //                     let u = (i as f64 + random_f64()) / (image_width - 1) as f64;
-    //                     let v = (j as f64 + random_f64()) / (image_height - 1) as f64;
-    //                     let ray = camera.get_ray(u, v);
-    //                     pixel_color += ray_color(&ray, &world, max_depth);
-    //                 }
-
-    //             // Apply gamma correction and write the pixel color
-    //             let scale = 1.0 / samples_per_pixel as f64;
-    //             let r = (pixel_color.x * scale).sqrt();
-    //             let g = (pixel_color.y * scale).sqrt();
-    //             let b = (pixel_color.z * scale).sqrt();
-
-    //             let ir = (255.99 * r.clamp(0.0, 0.999)) as u8;
-    //             let ig = (255.99 * g.clamp(0.0, 0.999)) as u8;
-    //             let ib = (255.99 * b.clamp(0.0, 0.999)) as u8;
-
-    //             // image_data.push(ir);
-    //             // image_data.push(ig);
-    //             // image_data.push(ib);
-    //             img.put_pixel(i, image_height - 1 - j, Rgb([ir, ig, ib]));
-    //         }
-    //         // let mut file = File::create("outputx.ppm").unwrap();
-    //         // file.write_all(format!("P6\n{} {}\n255\n", image_width, image_height).as_bytes())
-    //         //     .unwrap();
-    //         // file.write_all(&image_data).unwrap();
-    //         // let mut file = File::create("outputx.png").unwrap();
-            
-            
-    //     }
-    //     img.save("outputx.png").unwrap();
-    // }
-    fn main() {
-        // Image
-        let aspect_ratio = 16.0 / 9.0;
-        let image_width = 400;
-        let image_height = (image_width as f64 / aspect_ratio) as u32;
-        let samples_per_pixel = 200;
-        let max_depth: u32 = 5;
-    
-        // World
-        let world = random_scene();
-    
-        // Camera
-        let lookfrom = Point3::new(12.0, 6.0, 12.0);
-        
-        let lookat = Point3::new(0.0, 0.0, 0.0);
-        let vup = Vector3::new(0.0, 1.0, 0.0);
-        let dist_to_focus = 10.0;
-        let aperture = 0.09;
-        let camera = Camera::new(
-            lookfrom,
-            lookat,
-            vup,
-            20.0,
-            aspect_ratio,
-            aperture,
-            dist_to_focus,
-        );
-    
-         //let bs = Arc::new(Mutex::new(HashMap::<i32, i32>::new()));
-        let mut img: ImageBuffer<Rgb<u8>, Vec<_>> = ImageBuffer::new(image_width, image_height);
-        // for j in (0..image_height).rev() {
-        //     for i in 0..image_width {
-        //         let mut pixel_color = Vector3::new(0.0, 0.0, 0.0);
-        //         for _ in 0..samples_per_pixel {
-        //             let u = (i as f64 + random_f64()) / (image_width - 1) as f64;
-        //             let v = (j as f64 + random_f64()) / (image_height - 1) as f64;
-        //             let ray = camera.get_ray(u, v);
-        //             pixel_color += ray_color(&ray, &world, max_depth);
-        //         }
-        //         //println!("{}", pixel_color);
-        //         // Apply gamma correction and write the pixel color
-        //         let scale = 1.0 / samples_per_pixel as f64;
-        //         let r = (pixel_color.x * scale).sqrt();
-        //         let g = (pixel_color.y * scale).sqrt();
-        //         let b = (pixel_color.z * scale).sqrt();
-    
-        //         let ir = (255.99 * r.clamp(0.0, 0.999)) as u8;
-        //         let ig = (255.99 * g.clamp(0.0, 0.999)) as u8;
-        //         let ib = (255.99 * b.clamp(0.0, 0.999)) as u8;
-    
-        //         img.put_pixel(i, image_height - 1 - j, Rgb([ir, ig, ib]));
-               
-        //     }
-        // }
-        //let mut img = ImageBuffer::new(image_width, image_height);
-        //println!("built world");
-        let world_arc = Arc::new(world);
-
-let data: Vec<(u32, u32, Rgb<u8>)> = (0..image_height)
-    .into_par_iter()
-    .rev()
-    .flat_map(move |j| {
-        let world = Arc::clone(&world_arc);
-        (0..image_width)
-            .into_par_iter()
-            .map(move |i| {
-                let mut pixel_color = Vector3::new(0.0, 0.0, 0.0);
-                
-                for _ in 0..samples_per_pixel {
-                    let u = (i as f64 + random_f64()) / (image_width - 1) as f64;
-                    let v = (j as f64 + random_f64()) / (image_height - 1) as f64;
-                    let ray = camera.get_ray(u, v);
-                    //println!("here camera");
-                    pixel_color += ray_color_dup(&ray, &world, max_depth);
-                }
-
-                let scale = 1.0 / samples_per_pixel as f64;
-                let r = (pixel_color.x * scale).sqrt();
-                let g = (pixel_color.y * scale).sqrt();
-                let b = (pixel_color.z * scale).sqrt();
-
-                let ir = (255.99 * r.clamp(0.0, 0.999)) as u8;
-                let ig = (255.99 * g.clamp(0.0, 0.999)) as u8;
-                let ib = (255.99 * b.clamp(0.0, 0.999)) as u8;
-
-                (i, image_height - 1 - j, Rgb([ir, ig, ib]))
-            })
-    })
-    .collect();
-// let data: Vec<(u32, u32, Rgb<u8>)> = (0..image_height)
-//     .into_par_iter()
-//     .rev()
-//     .flat_map(move |j| {
-//         //let background_cache = Arc::clone(&bs); // add the `move` keyword here
-//         let world  = &world; // clone the Arc<BVHNode> here
-//         (0..image_width)
-//             .into_par_iter()
-//             .map(move |i| { // add the `move` keyword here
-//                 let mut pixel_color = Vector3::new(0.0, 0.0, 0.0);
-                
-//                 for _ in 0..samples_per_pixel {
-//                     let u = (i as f64 + random_f64()) / (image_width - 1) as f64;
-//                     let v = (j as f64 + random_f64()) / (image_height - 1) as f64;
-//                     let ray = camera.get_ray(u, v);
-//                     pixel_color += ray_color_dup(&ray, &world, max_depth);
-//                 }
-
-
-//                 let scale = 1.0 / samples_per_pixel as f64;
-//                 let r = (pixel_color.x * scale).sqrt();
-//                 let g = (pixel_color.y * scale).sqrt();
-//                 let b = (pixel_color.z * scale).sqrt();
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use image::ImageFormat;
+use rtracer::{
+    randomscene::random_scene, scene::load_scene, util::seed_rng, Accelerator, IntegratorKind, MisHeuristic,
+    RenderSettings, Renderer,
+};
+
+#[derive(Copy, Clone, ValueEnum)]
+enum AcceleratorArg {
+    /// Bounding volume hierarchy
+    Bvh,
+    /// Kd-tree
+    Kd,
+    /// Plain list, testing every object against every ray
+    List,
+}
 
-//                 let ir = (255.99 * r.clamp(0.0, 0.999)) as u8;
-//                 let ig = (255.99 * g.clamp(0.0, 0.999)) as u8;
-//                 let ib = (255.99 * b.clamp(0.0, 0.999)) as u8;
+#[derive(Copy, Clone, ValueEnum)]
+enum IntegratorArg {
+    /// Iterative path tracer with next-event estimation
+    Path,
+    /// Bidirectional path tracer, for caustics and lights that are hard to reach from the camera
+    Bdpt,
+    /// Progressive photon mapping, for caustics on diffuse surfaces
+    Photon,
+    /// Primary sample space Metropolis over the path tracer, for light through small openings
+    Mlt,
+    /// Shading normals as colours, for checking geometry
+    Normals,
+}
 
-//                 (i, image_height - 1 - j, Rgb([ir, ig, ib]))
-//             })
-//     })
-//     .collect();
+#[derive(Copy, Clone, ValueEnum)]
+enum MisArg {
+    /// Weight each strategy by its share of the summed densities
+    Balance,
+    /// Balance with squared densities, favouring whichever strategy is clearly better
+    Power,
+}
 
+#[derive(Copy, Clone, ValueEnum)]
+enum OutputFormat {
+    Png,
+    Jpeg,
+    Bmp,
+    Ppm,
+    Tga,
+    Tiff,
+}
 
-    for (i, j, pixel) in data {
-    img.put_pixel(i, j, pixel);
-    }
-        img.save("outputbvhx.png").unwrap();
+impl OutputFormat {
+    fn image_format(self) -> ImageFormat {
+        match self {
+            OutputFormat::Png => ImageFormat::Png,
+            OutputFormat::Jpeg => ImageFormat::Jpeg,
+            OutputFormat::Bmp => ImageFormat::Bmp,
+            OutputFormat::Ppm => ImageFormat::Pnm,
+            OutputFormat::Tga => ImageFormat::Tga,
+            OutputFormat::Tiff => ImageFormat::Tiff,
+        }
     }
-    
-            
-        
-        
-// Implement the Material trait for diffuse materials (Lambertian)
-
-
-// Helper function to generate random points in a unit sphere
-
-// Metal struct representing a metal material
-
-// Dielectric struct representing a dielectric material
-
-// Helper function to refract a vector
-
-
-fn random_vector3(min: f64, max: f64) -> Vector3<f64> {
-    let mut rng = rand::thread_rng();
-    Vector3::new(
-        rng.gen_range(min..max),
-        rng.gen_range(min..max),
-        rng.gen_range(min..max),
-    )
 }
-// fn random_scene() -> Vec<Box<dyn Hitable>> {
-//     let mut rng = rand::thread_rng();
-//     let mut world: Vec<Box<dyn Hitable>> = Vec::new();
 
-//     // Ground
-//     world.push(Box::new(Sphere::new(
-//         Point3::new(0.0, -1000.0, 0.0),
-//         1000.0,
-//         Arc::new(Lambertian::new(Vector3::new(0.5, 0.5, 0.5))),
-//     )));
-
-//     //Random small spheres
-//     for a in -11..11 {
-//         for b in -11..11 {
-//             let choose_mat: f64 = rng.gen_range(0.0..1.0);
-//             let center = Point3::new(
-//                 a as f64 + 0.9 * rng.gen_range(0.0..1.0),
-//                 0.2,
-//                 b as f64 + 0.9 * rng.gen_range(0.0..1.0),
-//             );
-
-//             if (center - Point3::new(4.0, 0.2, 0.0)).magnitude() > 0.9 {
-//                 if choose_mat < 0.8 {
-//                     // Lambertian material
-//                     let albedo = random_vector3(0.0, 1.0).component_mul(&random_vector3(0.0, 1.0));
-//                     world.push(Box::new(Sphere::new(
-//                         center,
-//                         0.2,
-//                         Arc::new(Lambertian::new(albedo)),
-//                     )));
-//                 } else if choose_mat < 0.95 {
-//                     // Metal material
-//                     let albedo = Vector3::new(
-//                         rng.gen_range(0.5..1.0),
-//                         rng.gen_range(0.5..1.0),
-//                         rng.gen_range(0.5..1.0),
-//                     );
-//                     let fuzz = rng.gen_range(0.0..0.5);
-//                     world.push(Box::new(Sphere::new(
-//                         center,
-//                         0.2,
-//                         Arc::new(Metal::new(albedo, fuzz)),
-//                     )));
-//                 } else {
-//                     // Dielectric material
-//                     world.push(Box::new(Sphere::new(
-//                         center,
-//                         0.2,
-//                         Arc::new(Dielectric::new(1.5)),
-//                     )));
-//                 }
-//             }
-//         }
-//     }
+#[derive(Parser)]
+#[command(name = "rtracer", about = "Render a TOML scene description, or the built-in random scene")]
+struct Args {
+    /// Scene description file; the random sphere scene is rendered when omitted
+    scene: Option<PathBuf>,
+    /// Output image path
+    #[arg(short, long, default_value = "outputbvhx.png")]
+    output: PathBuf,
+    /// Output format, inferred from the output extension when omitted
+    #[arg(short, long, value_enum)]
+    format: Option<OutputFormat>,
+    /// Image width in pixels (keeps the scene aspect ratio unless --height is also given)
+    #[arg(long)]
+    width: Option<u32>,
+    /// Image height in pixels (keeps the scene aspect ratio unless --width is also given)
+    #[arg(long)]
+    height: Option<u32>,
+    /// Samples per pixel
+    #[arg(short, long)]
+    spp: Option<u32>,
+    /// Maximum number of bounces per path
+    #[arg(short = 'd', long)]
+    max_depth: Option<u32>,
+    /// Bounces before Russian roulette may end a path
+    #[arg(long)]
+    roulette_depth: Option<u32>,
+    /// Worker threads, defaults to one per core
+    #[arg(short = 'j', long)]
+    threads: Option<usize>,
+    /// Random seed; the same seed and settings reproduce the same image
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Acceleration structure the objects are put into
+    #[arg(long, value_enum, default_value_t = AcceleratorArg::Kd)]
+    accel: AcceleratorArg,
+    /// Light transport algorithm
+    #[arg(long, value_enum, default_value_t = IntegratorArg::Path)]
+    integrator: IntegratorArg,
+    /// Heuristic for weighting light samples against BSDF samples
+    #[arg(long, value_enum, default_value_t = MisArg::Power)]
+    mis: MisArg,
+    /// Photon paths shot per pass with the photon integrator
+    #[arg(long)]
+    photons: Option<u32>,
+    /// Photon gather radius of the first pass, in scene units
+    #[arg(long)]
+    photon_radius: Option<f64>,
+    /// Independent paths that seed the Metropolis chains and set the image brightness
+    #[arg(long)]
+    bootstrap_samples: Option<u32>,
+    /// Metropolis chains the samples are split between
+    #[arg(long)]
+    chains: Option<u32>,
+    /// Standard deviation of a Metropolis small step, in primary sample space
+    #[arg(long)]
+    mutation_size: Option<f64>,
+    /// Chance of a Metropolis mutation starting a new independent path
+    #[arg(long)]
+    large_step_probability: Option<f64>,
+    /// Trace each path at a single wavelength, for dispersion in glass
+    #[arg(long)]
+    spectral: bool,
+}
 
-//     // Large spheres
-//     world.push(Box::new(Sphere::new(
-//         Point3::new(0.0, 1.0, 0.0),
-//         1.0,
-//         Arc::new(Dielectric::new(1.5)),
-//     )));
-//     world.push(Box::new(Sphere::new(
-//         Point3::new(-4.0, 1.0, 0.0),
-//         1.0,
-//         Arc::new(Lambertian::new(Vector3::new(0.4, 0.2, 0.1))),
-//         //Arc::new(Metal::new(Vector3::new(0.7, 0.6, 0.5), 0.0)),
-//     )));
-//     world.push(Box::new(Sphere::new(
-//         Point3::new(4.0, 1.0, 0.0),
-//         1.0,
-//         Arc::new(Metal::new(Vector3::new(0.7, 0.6, 0.5), 0.0)),
-//     )));
-//     BVHNode::from_objects(&mut world, 0, world.len());
-//     world
-// }
-fn random_scene() -> KdNode {
-    let mut rng = rand::thread_rng();
-    let mut world: Vec<Arc<dyn Hitable>> = Vec::new();
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}", message);
+    std::process::exit(1);
+}
 
-    // Ground
-    world.push(Arc::new(Sphere::new(
-        Point3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        Arc::new(Lambertian::new(Vector3::new(0.5, 0.5, 0.5))),
-    )));
+fn main() {
+    let args = Args::parse();
+    let seed = args.seed.unwrap_or_else(rand::random);
 
-    //Random small spheres
-    for a in -11..11 {
-        for b in -11..11 {
-            let choose_mat: f64 = rng.gen_range(0.0..1.0);
-            let center = Point3::new(
-                a as f64 + 0.9 * rng.gen_range(0.0..1.0),
-                0.2,
-                b as f64 + 0.9 * rng.gen_range(0.0..1.0),
-            );
+    let (scene, mut settings) = match &args.scene {
+        Some(path) => load_scene(path).unwrap_or_else(|e| fail(e)),
+        None => {
+            seed_rng(seed);
+            (random_scene(), RenderSettings::default())
+        }
+    };
 
-            if (center - Point3::new(4.0, 0.2, 0.0)).magnitude() > 0.9 {
-                if choose_mat < 0.8 {
-                    // Lambertian material
-                    let albedo = random_vector3(0.0, 1.0).component_mul(&random_vector3(0.0, 1.0));
-                    world.push(Arc::new(Sphere::new(
-                        center,
-                        0.2,
-                        Arc::new(Lambertian::new(albedo)),
-                    )));
-                } else if choose_mat < 0.95 {
-                    // Metal material
-                    let albedo = random_vector3(0.5, 1.0);
-                    let fuzz = rng.gen_range(0.0..0.5);
-                    world.push(Arc::new(Sphere::new(
-                        center,
-                        0.2,
-                        Arc::new(Metal::new(albedo, fuzz)),
-                    )));
-                } else {
-                    // Dielectric material
-                    world.push(Arc::new(Sphere::new(
-                        center,
-                        0.2,
-                        Arc::new(Dielectric::new(1.5)),
-                    )));
-                }
-            }
+    settings.resize(args.width, args.height);
+    if settings.width < 2 || settings.height < 2 {
+        fail("image must be at least 2x2 pixels");
+    }
+    if let Some(spp) = args.spp {
+        if spp == 0 {
+            fail("--spp must be greater than zero");
         }
+        settings.samples_per_pixel = spp;
     }
-
-    // Large spheres
-    // world.push(Arc::new(Sphere::new(
-    //     Point3::new(0.0, 1.0, 0.0),
-    //     1.0,
-    //     Arc::new(Dielectric::new(1.5)),
-    // )));
-    let mini = Point3::new(0.0, 0.0, 0.0); // Lower left back corner
-    let maxi = Point3::new(2.0, 2.0, 4.0); // Upper right front corner
-    world.push(Arc::new(Cube::new(
-        mini,
-        maxi,
-        Arc::new(Dielectric::new(1.5)),
-    )));
-    world.push(Arc::new(Sphere::new(
-        Point3::new(-4.0, 1.0, 0.0),
-        1.0,
-        Arc::new(Lambertian::new(Vector3::new(0.1, 0.2, 0.1))),
-    )));
-    world.push(Arc::new(Sphere::new(Point3::new(4.0, 1.0, 0.0),
-    1.0,
-    Arc::new(Metal::new(Vector3::new(0.7, 0.6, 0.5), 0.0)),
-)));
-
-// let time0: f64 = 0.0; 
-// let time1: f64 = 1.0;
-
-//  let kdtree =  KdTree::new(&mut  world, time0, time1);
-// let t0 = 0.0;
-//     let t1 = 1.0;
-//     let axis = 0;
-//     let kdtree = KdTree::build(&mut world, t0, t1, axis);
-    //print!("{}", world.len());
-    //dbg!(world.len());
-    let kdtree = KdNode::new(&mut world,7);
-    return kdtree;
-}
\ No newline at end of file
+    if let Some(max_depth) = args.max_depth {
+        settings.max_depth = max_depth;
+    }
+    if let Some(roulette_depth) = args.roulette_depth {
+        settings.roulette_depth = roulette_depth;
+    }
+    if let Some(photons) = args.photons {
+        if photons == 0 {
+            fail("--photons must be greater than zero");
+        }
+        settings.photons = photons;
+    }
+    if let Some(photon_radius) = args.photon_radius {
+        if !(photon_radius > 0.0 && photon_radius.is_finite()) {
+            fail("--photon-radius must be greater than zero");
+        }
+        settings.photon_radius = Some(photon_radius);
+    }
+    if let Some(bootstrap_samples) = args.bootstrap_samples {
+        if bootstrap_samples == 0 {
+            fail("--bootstrap-samples must be greater than zero");
+        }
+        settings.bootstrap_samples = bootstrap_samples;
+    }
+    if let Some(chains) = args.chains {
+        if chains == 0 {
+            fail("--chains must be greater than zero");
+        }
+        settings.chains = chains;
+    }
+    if let Some(mutation_size) = args.mutation_size {
+        if !(mutation_size > 0.0 && mutation_size.is_finite()) {
+            fail("--mutation-size must be greater than zero");
+        }
+        settings.mutation_size = mutation_size;
+    }
+    if let Some(large_step_probability) = args.large_step_probability {
+        if !(0.0..=1.0).contains(&large_step_probability) {
+            fail("--large-step-probability must be between 0 and 1");
+        }
+        settings.large_step_probability = large_step_probability;
+    }
+    if args.spectral {
+        settings.spectral = true;
+    }
+    settings.seed = seed;
+    settings.threads = args.threads;
+    settings.accelerator = match args.accel {
+        AcceleratorArg::Bvh => Accelerator::Bvh,
+        AcceleratorArg::Kd => Accelerator::Kd,
+        AcceleratorArg::List => Accelerator::List,
+    };
+    settings.integrator = match args.integrator {
+        IntegratorArg::Path => IntegratorKind::Path,
+        IntegratorArg::Bdpt => IntegratorKind::Bdpt,
+        IntegratorArg::Photon => IntegratorKind::Photon,
+        IntegratorArg::Mlt => IntegratorKind::Mlt,
+        IntegratorArg::Normals => IntegratorKind::Normals,
+    };
+    settings.mis = match args.mis {
+        MisArg::Balance => MisHeuristic::Balance,
+        MisArg::Power => MisHeuristic::Power,
+    };
+
+    let image = Renderer::new(settings).render(&scene).to_rgb8();
+    let saved = match args.format {
+        Some(format) => image.save_with_format(&args.output, format.image_format()),
+        None => image.save(&args.output),
+    };
+    if let Err(e) = saved {
+        fail(format!("{}: {}", args.output.display(), e));
+    }
+}
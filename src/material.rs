@@ -1,8 +1,91 @@
+use std::ops::BitOr;
+
 use nalgebra::Vector3;
 
-use crate::{ray::Ray, HitRecord};
+use crate::{frame::Frame, ray::Ray, HitRecord};
 use std::marker::{Send, Sync};
 
+// Set of BSDF lobes: how a lobe scatters (diffuse, glossy or specular) and to which side
+// of the surface (reflection or transmission)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Lobes(u8);
+
+impl Lobes {
+    pub const NONE: Lobes = Lobes(0);
+    pub const DIFFUSE: Lobes = Lobes(1);
+    pub const GLOSSY: Lobes = Lobes(1 << 1);
+    // A single outgoing direction; eval() and pdf() are zero for it
+    pub const SPECULAR: Lobes = Lobes(1 << 2);
+    pub const REFLECTION: Lobes = Lobes(1 << 3);
+    pub const TRANSMISSION: Lobes = Lobes(1 << 4);
+
+    pub fn contains(self, other: Lobes) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(self, other: Lobes) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn is_specular(self) -> bool {
+        self.intersects(Lobes::SPECULAR)
+    }
+
+    // Diffuse or glossy lobes can be evaluated for any direction, so lights can be sampled for them
+    pub fn is_smooth(self) -> bool {
+        self.intersects(Lobes(Lobes::DIFFUSE.0 | Lobes::GLOSSY.0))
+    }
+}
+
+impl BitOr for Lobes {
+    type Output = Lobes;
+
+    fn bitor(self, other: Lobes) -> Lobes {
+        Lobes(self.0 | other.0)
+    }
+}
+
+// One direction drawn by Material::sample
+pub struct ScatterRecord {
+    // brdf * cos / pdf for the sampled direction
+    pub attenuation: Vector3<f64>,
+    pub scattered: Ray,
+    // Solid angle density of the sampled direction; 0 for specular lobes
+    pub pdf: f64,
+    // The lobe the direction was drawn from
+    pub lobe: Lobes,
+    // Shading frame at the hit, with its normal on the side the ray arrived from
+    pub frame: Frame,
+}
+
+impl ScatterRecord {
+    pub fn is_specular(&self) -> bool {
+        self.lobe.is_specular()
+    }
+}
+
 pub trait Material : Send + Sync {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Vector3<f64>, Ray)>;
+    // Draw a scattered direction, or None when the ray is absorbed
+    fn sample(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord>;
+    // brdf * cos for light leaving along `direction`; zero for specular lobes
+    fn eval(&self, _ray_in: &Ray, _hit_record: &HitRecord, _direction: &Vector3<f64>) -> Vector3<f64> {
+        Vector3::new(0.0, 0.0, 0.0)
+    }
+    // Density with which sample() would choose `direction`, per unit solid angle
+    fn pdf(&self, _ray_in: &Ray, _hit_record: &HitRecord, _direction: &Vector3<f64>) -> f64 {
+        0.0
+    }
+    // Factor turning eval() and sample() weights into the ones for importance, i.e. light
+    // traced from the lights; they differ only where refraction bends rays into a new medium
+    fn importance_scale(&self, _ray_in: &Ray, _hit_record: &HitRecord, _direction: &Vector3<f64>) -> f64 {
+        1.0
+    }
+    // Every lobe sample() can return; integrators only sample lights for smooth lobes
+    fn lobes(&self) -> Lobes {
+        Lobes::NONE
+    }
+    // Radiance leaving the surface on its own, black for everything but lights
+    fn emitted(&self, _ray_in: &Ray, _hit_record: &HitRecord) -> Vector3<f64> {
+        Vector3::new(0.0, 0.0, 0.0)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,131 @@
+use std::sync::Arc;
+
+use nalgebra::{Vector2, Vector3};
+
+use crate::{
+    aabb::AABB,
+    hitrecord::{HitRecord, Hitable},
+    material::Material,
+    ray::Ray,
+    util::random_f64,
+};
+
+// Where a ray travelling from t_start scatters in a medium of the given density, or None
+// when it gets past t_end. Free flight distances are exponential with mean 1 / density, so
+// the chance of getting through a stretch of length d is the transmittance exp(-density * d).
+pub fn scatter_distance(ray: &Ray, density: f64, t_start: f64, t_end: f64) -> Option<f64> {
+    let distance = -(1.0 - random_f64()).ln() / density;
+    let t = t_start + distance / ray.direction.magnitude();
+    (t < t_end).then_some(t)
+}
+
+// Points scattered inside a medium have no surface, which a zero normal tells integrators
+pub fn medium_hit(ray: &Ray, t: f64, phase_function: &Arc<dyn Material>) -> HitRecord {
+    HitRecord {
+        t,
+        p: ray.point_at_parameter(t),
+        normal: Vector3::zeros(),
+        material: phase_function.clone(),
+        uv: Vector2::zeros(),
+        barycentric: None,
+        color: None,
+    }
+}
+
+// Smoke, mist or other participating medium of constant density filling a closed boundary
+// shape. Rays that enter it either pass through, or hit a point inside it at a random distance
+// where its phase function scatters them. Shadow rays are stopped the same way, which averages
+// out to the transmittance through the medium.
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hitable>,
+    // Extinction coefficient, per unit distance
+    density: f64,
+    phase_function: Arc<dyn Material>,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hitable>, density: f64, phase_function: Arc<dyn Material>) -> Self {
+        ConstantMedium { boundary, density, phase_function }
+    }
+}
+
+// Part of (t_min, t_max) in which the ray is inside `boundary`. The boundary must be convex:
+// the ray is taken to be inside between the first two points it crosses it at, on the whole
+// line through the ray.
+fn span_inside(boundary: &dyn Hitable, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+    let enter = boundary.hit(ray, f64::NEG_INFINITY, f64::INFINITY)?;
+    let exit = boundary.hit(ray, enter.t + 0.0001, f64::INFINITY)?;
+    let t_start = enter.t.max(t_min);
+    let t_end = exit.t.min(t_max);
+    (t_start < t_end).then_some((t_start, t_end))
+}
+
+impl Hitable for ConstantMedium {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (t_start, t_end) = span_inside(&*self.boundary, ray, t_min, t_max)?;
+        let t = scatter_distance(ray, self.density, t_start, t_end)?;
+        Some(medium_hit(ray, t, &self.phase_function))
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> f64 {
+        match span_inside(&*self.boundary, ray, t_min, t_max) {
+            Some((t_start, t_end)) => (-self.density * (t_end - t_start) * ray.direction.magnitude()).exp(),
+            None => 1.0,
+        }
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.boundary.bounding_box(t0, t1)
+    }
+}
+
+// Medium of constant density filling all the space between the surfaces of a scene, for
+// atmospheric haze. Rays that leave the scene are taken to do so unscattered, so the sky and
+// the environment stay clear, while surfaces and lights fade with distance.
+#[derive(Clone)]
+pub struct Fog {
+    density: f64,
+    phase_function: Arc<dyn Material>,
+}
+
+impl Fog {
+    pub fn new(density: f64, phase_function: Arc<dyn Material>) -> Self {
+        Fog { density, phase_function }
+    }
+
+    // The world seen through the fog
+    pub fn around(&self, world: Arc<dyn Hitable>) -> Foggy {
+        Foggy { world, fog: self.clone() }
+    }
+}
+
+pub struct Foggy {
+    world: Arc<dyn Hitable>,
+    fog: Fog,
+}
+
+impl Hitable for Foggy {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let surface = self.world.hit(ray, t_min, t_max);
+        let t_end = surface.as_ref().map_or(t_max, |hit_record| hit_record.t);
+        if t_end.is_infinite() {
+            return surface;
+        }
+        match scatter_distance(ray, self.fog.density, t_min, t_end) {
+            Some(t) => Some(medium_hit(ray, t, &self.fog.phase_function)),
+            None => surface,
+        }
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let through_world = self.world.transmittance(ray, t_min, t_max);
+        if t_max.is_infinite() {
+            return through_world;
+        }
+        through_world * (-self.fog.density * (t_max - t_min) * ray.direction.magnitude()).exp()
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.world.bounding_box(t0, t1)
+    }
+}
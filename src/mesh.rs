@@ -0,0 +1,355 @@
+use crate::{Ray, hitrecord::{Hitable, HitRecord}, aabb::AABB, triangle::{intersect_triangle, triangle_bounds}};
+use std::sync::Arc;
+
+use nalgebra::{Point3, Vector2, Vector3};
+
+use crate::material::Material;
+
+const MAX_LEAF_TRIANGLES: usize = 4;
+const SAH_BINS: usize = 12;
+// Beyond this depth splits are forced to be even so traversal fits in a fixed stack
+const MAX_SAH_DEPTH: usize = 64;
+const TRAVERSAL_STACK: usize = 128;
+
+// Flattened BVH node; interior nodes store the index of their second child,
+// the first child always directly follows its parent.
+#[derive(Copy, Clone)]
+struct MeshNode {
+    bounds: AABB,
+    offset: usize,
+    count: usize,
+    axis: usize,
+}
+
+pub struct TriangleMesh {
+    positions: Vec<Point3<f64>>,
+    normals: Vec<Vector3<f64>>,
+    uvs: Vec<Vector2<f64>>,
+    colors: Vec<Vector3<f64>>,
+    indices: Vec<[usize; 3]>,
+    material: Arc<dyn Material>,
+    nodes: Vec<MeshNode>,
+}
+
+impl TriangleMesh {
+    // `normals` and `uvs` are either empty or indexed like `positions`.
+    pub fn new(
+        positions: Vec<Point3<f64>>,
+        normals: Vec<Vector3<f64>>,
+        uvs: Vec<Vector2<f64>>,
+        indices: Vec<[usize; 3]>,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        assert!(normals.is_empty() || normals.len() == positions.len(), "mesh normal count must match vertex count");
+        assert!(uvs.is_empty() || uvs.len() == positions.len(), "mesh uv count must match vertex count");
+        assert!(
+            indices.iter().flatten().all(|&i| i < positions.len()),
+            "mesh index out of range"
+        );
+
+        let mut mesh = TriangleMesh {
+            positions,
+            normals,
+            uvs,
+            colors: Vec::new(),
+            indices,
+            material,
+            nodes: Vec::new(),
+        };
+        mesh.build_bvh();
+        mesh
+    }
+
+    // Per-vertex colours, multiplied into the material albedo at each hit
+    pub fn with_colors(mut self, colors: Vec<Vector3<f64>>) -> Self {
+        assert!(colors.is_empty() || colors.len() == self.positions.len(), "mesh colour count must match vertex count");
+        self.colors = colors;
+        self
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    fn triangle_bounds(&self, triangle: usize) -> AABB {
+        let [i0, i1, i2] = self.indices[triangle];
+        triangle_bounds(&self.positions[i0], &self.positions[i1], &self.positions[i2])
+    }
+
+    fn build_bvh(&mut self) {
+        if self.indices.is_empty() {
+            return;
+        }
+        let bounds: Vec<AABB> = (0..self.indices.len()).map(|i| self.triangle_bounds(i)).collect();
+        let centroids: Vec<Point3<f64>> = bounds.iter().map(|b| b.centroid()).collect();
+        let mut order: Vec<usize> = (0..self.indices.len()).collect();
+        let mut nodes = Vec::with_capacity(2 * self.indices.len() / MAX_LEAF_TRIANGLES + 1);
+        build_node(&mut nodes, &mut order, 0, 0, &bounds, &centroids);
+
+        self.indices = order.iter().map(|&i| self.indices[i]).collect();
+        self.nodes = nodes;
+    }
+
+    fn hit_triangle(&self, ray: &Ray, triangle: usize, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let [i0, i1, i2] = self.indices[triangle];
+        let (p0, p1, p2) = (&self.positions[i0], &self.positions[i1], &self.positions[i2]);
+        let (t, b0, b1, b2) = intersect_triangle(ray, p0, p1, p2, t_min, t_max)?;
+
+        let geometric_normal = (p1 - p0).cross(&(p2 - p0)).normalize();
+        let normal = if self.normals.is_empty() {
+            geometric_normal
+        } else {
+            let shading_normal =
+                (b0 * self.normals[i0] + b1 * self.normals[i1] + b2 * self.normals[i2]).normalize();
+            if shading_normal.dot(&geometric_normal) < 0.0 {
+                -shading_normal
+            } else {
+                shading_normal
+            }
+        };
+        let uv = if self.uvs.is_empty() {
+            Vector2::new(b1, b2)
+        } else {
+            b0 * self.uvs[i0] + b1 * self.uvs[i1] + b2 * self.uvs[i2]
+        };
+        let color = if self.colors.is_empty() {
+            None
+        } else {
+            Some(b0 * self.colors[i0] + b1 * self.colors[i1] + b2 * self.colors[i2])
+        };
+
+        Some(HitRecord {
+            t,
+            p: ray.point_at_parameter(t),
+            normal,
+            material: Arc::clone(&self.material),
+            uv,
+            barycentric: Some(Vector2::new(b1, b2)),
+            color,
+        })
+    }
+}
+
+fn enclose(items: &[usize], boxes: &[AABB]) -> AABB {
+    items[1..]
+        .iter()
+        .fold(boxes[items[0]], |acc, &i| AABB::surrounding_box(&acc, &boxes[i]))
+}
+
+fn build_node(
+    nodes: &mut Vec<MeshNode>,
+    order: &mut [usize],
+    offset: usize,
+    depth: usize,
+    bounds: &[AABB],
+    centroids: &[Point3<f64>],
+) -> usize {
+    let node_index = nodes.len();
+    let node_bounds = enclose(order, bounds);
+    nodes.push(MeshNode {
+        bounds: node_bounds,
+        offset,
+        count: order.len(),
+        axis: 0,
+    });
+    if order.len() <= MAX_LEAF_TRIANGLES {
+        return node_index;
+    }
+
+    let mut centroid_bounds = AABB::new(centroids[order[0]], centroids[order[0]]);
+    for &i in order.iter() {
+        centroid_bounds = AABB::surrounding_box(&centroid_bounds, &AABB::new(centroids[i], centroids[i]));
+    }
+    let axis = centroid_bounds.maximum_extent();
+    let (lo, hi) = (centroid_bounds.min[axis], centroid_bounds.max[axis]);
+
+    let mid = if hi - lo <= f64::EPSILON || depth >= MAX_SAH_DEPTH {
+        // All centroids coincide or the tree is too deep, fall back to an even split by count
+        order.len() / 2
+    } else {
+        match sah_split(order, bounds, centroids, axis, lo, hi, node_bounds.surface_area()) {
+            Some(bin) => {
+                let scale = SAH_BINS as f64 / (hi - lo);
+                let mut left = 0;
+                for i in 0..order.len() {
+                    let b = (((centroids[order[i]][axis] - lo) * scale) as usize).min(SAH_BINS - 1);
+                    if b <= bin {
+                        order.swap(i, left);
+                        left += 1;
+                    }
+                }
+                left
+            }
+            None => return node_index,
+        }
+    };
+
+    let (left, right) = order.split_at_mut(mid);
+    build_node(nodes, left, offset, depth + 1, bounds, centroids);
+    let second = build_node(nodes, right, offset + mid, depth + 1, bounds, centroids);
+    nodes[node_index].offset = second;
+    nodes[node_index].count = 0;
+    nodes[node_index].axis = axis;
+    node_index
+}
+
+// Binned surface area heuristic; returns the last bin of the left partition, or
+// None when keeping the triangles in a single leaf is cheaper.
+fn sah_split(
+    order: &[usize],
+    bounds: &[AABB],
+    centroids: &[Point3<f64>],
+    axis: usize,
+    lo: f64,
+    hi: f64,
+    parent_area: f64,
+) -> Option<usize> {
+    let scale = SAH_BINS as f64 / (hi - lo);
+    let mut bin_counts = [0usize; SAH_BINS];
+    let mut bin_bounds: [Option<AABB>; SAH_BINS] = [None; SAH_BINS];
+    for &i in order {
+        let b = (((centroids[i][axis] - lo) * scale) as usize).min(SAH_BINS - 1);
+        bin_counts[b] += 1;
+        bin_bounds[b] = Some(match bin_bounds[b] {
+            Some(existing) => AABB::surrounding_box(&existing, &bounds[i]),
+            None => bounds[i],
+        });
+    }
+
+    let mut best: Option<(usize, f64)> = None;
+    for split in 0..SAH_BINS - 1 {
+        let (mut left_box, mut right_box): (Option<AABB>, Option<AABB>) = (None, None);
+        let (mut left_count, mut right_count) = (0, 0);
+        for b in 0..SAH_BINS {
+            let (acc, count) = if b <= split {
+                (&mut left_box, &mut left_count)
+            } else {
+                (&mut right_box, &mut right_count)
+            };
+            *count += bin_counts[b];
+            if let Some(bin_box) = bin_bounds[b] {
+                *acc = Some(match *acc {
+                    Some(existing) => AABB::surrounding_box(&existing, &bin_box),
+                    None => bin_box,
+                });
+            }
+        }
+        if left_count == 0 || right_count == 0 {
+            continue;
+        }
+        let cost = 0.125
+            + (left_count as f64 * left_box.unwrap().surface_area()
+                + right_count as f64 * right_box.unwrap().surface_area())
+                / parent_area;
+        if best.is_none_or(|(_, c)| cost < c) {
+            best = Some((split, cost));
+        }
+    }
+
+    match best {
+        Some((split, cost)) if cost < order.len() as f64 || order.len() > 4 * MAX_LEAF_TRIANGLES => Some(split),
+        _ => None,
+    }
+}
+
+impl Hitable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let dir_is_negative = [ray.direction.x < 0.0, ray.direction.y < 0.0, ray.direction.z < 0.0];
+        let mut closest: Option<HitRecord> = None;
+        let mut closest_t = t_max;
+        let mut stack = [0usize; TRAVERSAL_STACK];
+        let mut stack_len = 1;
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let node = &self.nodes[stack[stack_len]];
+            if !node.bounds.hit(ray, t_min, closest_t) {
+                continue;
+            }
+            if node.count > 0 {
+                for triangle in node.offset..node.offset + node.count {
+                    if let Some(hit) = self.hit_triangle(ray, triangle, t_min, closest_t) {
+                        closest_t = hit.t;
+                        closest = Some(hit);
+                    }
+                }
+            } else {
+                // Push the far child first so the near child is visited next
+                let first = stack[stack_len] + 1;
+                let second = node.offset;
+                if dir_is_negative[node.axis] {
+                    stack[stack_len] = first;
+                    stack[stack_len + 1] = second;
+                } else {
+                    stack[stack_len] = second;
+                    stack[stack_len + 1] = first;
+                }
+                stack_len += 2;
+            }
+        }
+
+        closest
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        self.nodes.first().map(|root| root.bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::{lambertian::Lambertian, triangle::Triangle};
+
+    #[test]
+    fn mesh_hits_match_brute_force() {
+        let mut rng = SmallRng::seed_from_u64(5);
+        let mut point = |rng: &mut SmallRng| Point3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new(Vector3::new(0.5, 0.5, 0.5)));
+        // Small scattered triangles, enough for the BVH to split several times
+        let mut positions = Vec::new();
+        let mut indices = Vec::new();
+        for i in 0..500 {
+            let centre = point(&mut rng);
+            for _ in 0..3 {
+                positions.push(centre + point(&mut rng).coords * 0.1);
+            }
+            indices.push([3 * i, 3 * i + 1, 3 * i + 2]);
+        }
+        let triangles: Vec<Triangle> = indices
+            .iter()
+            .map(|&[a, b, c]| Triangle::new(positions[a], positions[b], positions[c], material.clone()))
+            .collect();
+        let mesh = TriangleMesh::new(positions, Vec::new(), Vec::new(), indices, material);
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = point(&mut rng) * 3.0;
+            let ray = Ray::new(origin, point(&mut rng) - origin);
+            let expected = triangles
+                .iter()
+                .filter_map(|triangle| triangle.hit(&ray, 0.001, f64::INFINITY))
+                .min_by(|a, b| a.t.total_cmp(&b.t));
+            let found = mesh.hit(&ray, 0.001, f64::INFINITY);
+            match (expected, found) {
+                (Some(expected), Some(found)) => {
+                    assert_eq!(expected.t, found.t);
+                    assert_eq!(expected.barycentric, found.barycentric);
+                    hits += 1;
+                }
+                (None, None) => {}
+                (expected, found) => panic!("brute force {:?}, mesh {:?}", expected.map(|h| h.t), found.map(|h| h.t)),
+            }
+        }
+        assert!(hits > 100);
+    }
+}
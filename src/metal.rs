@@ -1,6 +1,14 @@
-use nalgebra::{Vector3, Unit};
+use std::f64::consts::PI;
 
-use crate::{material::Material, hitrecord::HitRecord, util::{reflect, random_in_unit_sphere}, ray::Ray};
+use nalgebra::Vector3;
+
+use crate::{
+    frame::Frame,
+    material::{Lobes, Material, ScatterRecord},
+    hitrecord::HitRecord,
+    util::{reflect, random_in_unit_sphere},
+    ray::Ray,
+};
 
 pub struct Metal {
     albedo: Vector3<f64>,
@@ -14,34 +22,69 @@ impl Metal {
             fuzz: if fuzz < 1.0 { fuzz } else { 1.0 },
         }
     }
-    fn blinn_phong(&self, ray_in: &Ray, hit_record: &HitRecord, light_dir: &Unit<Vector3<f64>>) -> Vector3<f64> {
-        let view_dir = -ray_in.direction.normalize();
-        let halfway_dir = (light_dir.as_ref() + view_dir).normalize();
 
-        let ambient = self.albedo * 0.1;
-        let diffuse = self.albedo * hit_record.normal.dot(light_dir.as_ref()).max(0.0);
-        let specular = Vector3::new(1.0, 1.0, 1.0) * hit_record.normal.dot(&halfway_dir).max(0.0).powf(150.0);
+    fn lobe(&self) -> Lobes {
+        if self.fuzz > 0.0 {
+            Lobes::GLOSSY | Lobes::REFLECTION
+        } else {
+            Lobes::SPECULAR | Lobes::REFLECTION
+        }
+    }
 
-        return ambient + diffuse + specular;
+    // Density of normalize(reflected + fuzz * x) with x uniform in the unit ball: the ball
+    // volume swept along `direction`, integral of t^2 dt over the chord, over the ball volume
+    fn lobe_pdf(&self, reflected: &Vector3<f64>, direction: &Vector3<f64>) -> f64 {
+        let b = direction.dot(reflected);
+        let discriminant = b * b - (1.0 - self.fuzz * self.fuzz);
+        if discriminant <= 0.0 {
+            return 0.0;
+        }
+        let t_far = b + discriminant.sqrt();
+        if t_far <= 0.0 {
+            return 0.0;
+        }
+        let t_near = (b - discriminant.sqrt()).max(0.0);
+        (t_far.powi(3) - t_near.powi(3)) / (4.0 * PI * self.fuzz.powi(3))
     }
-    
 }
 
-// Helper function to reflect a vector
-
-
 // Implement the Material trait for Metal
 impl Material for Metal {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
-        let reflected = reflect(ray_in.direction.normalize(), hit_record.normal);
+    // Mirror reflection tinted by the albedo, jittered inside a sphere of radius fuzz
+    fn sample(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
+        let frame = Frame::facing(hit_record.normal, &ray_in.direction);
+        let reflected = reflect(ray_in.direction.normalize(), frame.n);
         let scattered = Ray::new(hit_record.p, reflected + self.fuzz * random_in_unit_sphere());
-        if scattered.direction.dot(&hit_record.normal) > 0.0 {
-            let light_dir = Unit::new_normalize(Vector3::new(1.0, 1.0, 1.0)); // example light direction
-            let color = self.blinn_phong(ray_in, hit_record, &light_dir);
-            Some((color, scattered))
-        } else {
-            None
+        // Fuzzed directions that end up below the surface are absorbed
+        if frame.cos_theta(&scattered.direction) <= 0.0 {
+            return None;
+        }
+        let lobe = self.lobe();
+        let pdf = if lobe.is_specular() { 0.0 } else { self.lobe_pdf(&reflected, &scattered.direction.normalize()) };
+        Some(ScatterRecord {
+            attenuation: hit_record.albedo(self.albedo),
+            scattered,
+            pdf,
+            lobe,
+            frame,
+        })
+    }
+
+    // The absorbed part of the lobe is lost energy, so brdf * cos is just albedo * pdf above the surface
+    fn eval(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vector3<f64>) -> Vector3<f64> {
+        hit_record.albedo(self.albedo) * self.pdf(ray_in, hit_record, direction)
+    }
+
+    fn pdf(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vector3<f64>) -> f64 {
+        let frame = Frame::facing(hit_record.normal, &ray_in.direction);
+        if self.lobe().is_specular() || frame.cos_theta(direction) <= 0.0 {
+            return 0.0;
         }
+        let reflected = reflect(ray_in.direction.normalize(), frame.n);
+        self.lobe_pdf(&reflected, &direction.normalize())
+    }
+
+    fn lobes(&self) -> Lobes {
+        self.lobe()
     }
-    
 }
@@ -0,0 +1,321 @@
+use std::{cell::RefCell, f64::consts::PI, rc::Rc};
+
+use nalgebra::Vector3;
+use rand::{rngs::SmallRng, Rng, RngCore, SeedableRng};
+use rayon::prelude::*;
+
+use crate::{
+    camera::Camera,
+    distribution::Distribution1D,
+    hitrecord::Hitable,
+    integrator::{Integrator, PathTracer},
+    renderer::{Framebuffer, RenderSettings},
+    scene::Scene,
+    spectrum::{sample_wavelength, set_wavelength, spectral_to_rgb},
+    util::{random_f64, set_random_source},
+};
+
+// How bright a path's radiance counts as for the chains, which visit paths in proportion to it.
+// Spectral samples turned into RGB can have negative channels and even zero luminance while
+// carrying light, so every channel counts by its magnitude.
+fn brightness(radiance: &Vector3<f64>) -> f64 {
+    radiance.iter().map(|c| c.abs()).sum()
+}
+
+// One coordinate of a point in primary sample space, with what it was before the current
+// mutation so a rejected proposal can be undone
+struct PrimarySample {
+    value: f64,
+    // Iteration the value was last changed in
+    last_modified: u64,
+    value_backup: f64,
+    modify_backup: u64,
+}
+
+// The sample vector a Metropolis chain walks through, following Kelemen et al. (2002) as done in
+// pbrt's MLTSampler. Coordinates are created lazily as the path tracer asks for more of them,
+// and catch up on the mutations they missed when first used.
+struct PrimarySampler {
+    rng: SmallRng,
+    // Standard deviation of the small steps
+    mutation_size: f64,
+    large_step_probability: f64,
+    samples: Vec<PrimarySample>,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    // Next coordinate the current evaluation of the path will use
+    index: usize,
+}
+
+impl PrimarySampler {
+    // Chains started from bootstrap sample `index` reproduce its path, as both begin with a
+    // large step from the same stream
+    fn new(settings: &RenderSettings, index: u64) -> Self {
+        PrimarySampler {
+            rng: SmallRng::seed_from_u64(settings.seed ^ index.wrapping_mul(0x94D0_49BB_1331_11EB)),
+            mutation_size: settings.mutation_size,
+            large_step_probability: settings.large_step_probability,
+            samples: Vec::new(),
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            index: 0,
+        }
+    }
+
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    fn reject(&mut self) {
+        for sample in &mut self.samples {
+            if sample.last_modified == self.iteration {
+                sample.value = sample.value_backup;
+                sample.last_modified = sample.modify_backup;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    fn next(&mut self) -> f64 {
+        // The vector is endless, with uniform values wherever the path has not looked yet
+        if self.index >= self.samples.len() {
+            let value = self.rng.gen();
+            self.samples.push(PrimarySample { value, last_modified: self.iteration, value_backup: value, modify_backup: self.iteration });
+        }
+        let sample = &mut self.samples[self.index];
+        self.index += 1;
+
+        // A large step since the coordinate was last used replaced it with a uniform value
+        if sample.last_modified < self.last_large_step {
+            sample.value = self.rng.gen();
+            sample.last_modified = self.last_large_step;
+        }
+        sample.value_backup = sample.value;
+        sample.modify_backup = sample.last_modified;
+        if self.large_step {
+            sample.value = self.rng.gen();
+        } else {
+            // The small steps it missed add up to one with their combined variance
+            let missed = (self.iteration - sample.last_modified) as f64;
+            let (u1, u2): (f64, f64) = (self.rng.gen(), self.rng.gen());
+            let normal = (-2.0 * (1.0 - u1).ln()).sqrt() * (2.0 * PI * u2).cos();
+            sample.value += normal * self.mutation_size * missed.sqrt();
+            sample.value -= sample.value.floor();
+        }
+        sample.last_modified = self.iteration;
+        sample.value
+    }
+}
+
+// Shares a chain's sampler with the thread's random source, so the path tracer draws from it
+#[derive(Clone)]
+struct SharedSampler(Rc<RefCell<PrimarySampler>>);
+
+impl RngCore for SharedSampler {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    // The coordinate in the top 53 bits, which is all rand uses to make a float
+    fn next_u64(&mut self) -> u64 {
+        let value = self.0.borrow_mut().next();
+        ((value * (1u64 << 53) as f64) as u64).min((1u64 << 53) - 1) << 11
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// Primary sample space Metropolis light transport (Kelemen et al. 2002) over the path tracer.
+// The path tracer's random numbers become coordinates of a point in the unit hypercube, which
+// Markov chains wander through, mostly by small steps, in proportion to the brightness of the
+// path they produce. Bright but hard to find paths, such as light coming through a small
+// opening, are then explored around once found instead of being lost as fireflies. A bootstrap
+// phase of independent paths estimates the image's overall brightness and seeds the chains.
+pub struct Metropolis {
+    path_tracer: PathTracer,
+    camera: Camera,
+    settings: RenderSettings,
+}
+
+impl Metropolis {
+    pub fn new(settings: &RenderSettings, camera: Camera) -> Self {
+        Metropolis {
+            path_tracer: PathTracer::new(settings),
+            camera,
+            settings: settings.clone(),
+        }
+    }
+
+    // Trace the path the current sample vector describes: the first two coordinates pick the
+    // position on the image, the next the wavelength in spectral rendering, and the path tracer
+    // uses the rest. Returns the pixel (column, row from the top) and the radiance.
+    fn sample_path(&self, world: &dyn Hitable, scene: &Scene) -> ((u32, u32), Vector3<f64>) {
+        let (width, height) = (self.settings.width, self.settings.height);
+        let x = random_f64() * width as f64;
+        let y = random_f64() * height as f64;
+        let wavelength = self.settings.is_spectral().then(|| sample_wavelength(random_f64()));
+        let ray = self.camera.get_ray(x / (width - 1) as f64, y / (height - 1) as f64);
+        set_wavelength(wavelength.map(|(lambda, _)| lambda));
+        let radiance = self.path_tracer.radiance(&ray, world, scene, &mut Vec::new());
+        set_wavelength(None);
+        let radiance = match wavelength {
+            Some((lambda, pdf)) => spectral_to_rgb(&radiance, lambda, pdf),
+            None => radiance,
+        };
+        let (i, j) = ((x as u32).min(width - 1), (y as u32).min(height - 1));
+        ((i, height - 1 - j), radiance)
+    }
+
+    fn evaluate(&self, sampler: &SharedSampler, world: &dyn Hitable, scene: &Scene) -> ((u32, u32), Vector3<f64>) {
+        set_random_source(Some(Box::new(sampler.clone())));
+        let sample = self.sample_path(world, scene);
+        set_random_source(None);
+        sample
+    }
+
+    pub fn render(&self, world: &dyn Hitable, scene: &Scene) -> Framebuffer {
+        let settings = &self.settings;
+        let (width, height) = (settings.width, settings.height);
+
+        let weights: Vec<f64> = (0..settings.bootstrap_samples)
+            .into_par_iter()
+            .map(|index| {
+                let sampler = SharedSampler(Rc::new(RefCell::new(PrimarySampler::new(settings, index as u64))));
+                let (_, radiance) = self.evaluate(&sampler, world, scene);
+                let weight = brightness(&radiance);
+                if weight.is_finite() { weight } else { 0.0 }
+            })
+            .collect();
+        // Average brightness of the image, which the chains' relative estimates are scaled by
+        let brightness = weights.iter().sum::<f64>() / weights.len() as f64;
+        if brightness <= 0.0 || !brightness.is_finite() {
+            return Framebuffer::new(width, height);
+        }
+        let bootstrap = Distribution1D::new(weights);
+
+        let mutations = settings.samples_per_pixel as u64 * width as u64 * height as u64;
+        let chains = settings.chains as u64;
+        let image = (0..chains)
+            .into_par_iter()
+            .fold(
+                || Framebuffer::new(width, height),
+                |mut image, chain| {
+                    let count = mutations / chains + u64::from(chain < mutations % chains);
+                    self.run_chain(chain, count, &bootstrap, world, scene, &mut image);
+                    image
+                },
+            )
+            .reduce(|| Framebuffer::new(width, height), Framebuffer::merge);
+
+        let scale = brightness / settings.samples_per_pixel as f64;
+        image.scaled(scale)
+    }
+
+    // Run `count` mutations of a chain started from a bootstrap path picked by brightness,
+    // adding each path's radiance over its brightness, weighted by the chance of being in it,
+    // to `image`
+    fn run_chain(&self, chain: u64, count: u64, bootstrap: &Distribution1D, world: &dyn Hitable, scene: &Scene, image: &mut Framebuffer) {
+        let mut rng = SmallRng::seed_from_u64(self.settings.seed ^ (chain + 1).wrapping_mul(0xBF58_476D_1CE4_E5B9));
+        let (_, _, index) = bootstrap.sample(rng.gen());
+        let sampler = SharedSampler(Rc::new(RefCell::new(PrimarySampler::new(&self.settings, index as u64))));
+        let (mut pixel, mut radiance) = self.evaluate(&sampler, world, scene);
+        let mut weight = brightness(&radiance);
+        if weight <= 0.0 {
+            return;
+        }
+
+        for _ in 0..count {
+            sampler.0.borrow_mut().start_iteration();
+            let (proposed_pixel, proposed) = self.evaluate(&sampler, world, scene);
+            let proposed_weight = brightness(&proposed);
+            let accept = if proposed_weight > 0.0 && proposed_weight.is_finite() {
+                (proposed_weight / weight).min(1.0)
+            } else {
+                0.0
+            };
+            // Both states contribute by their chance of being the next one
+            if accept > 0.0 {
+                image.add(proposed_pixel.0, proposed_pixel.1, proposed * accept / proposed_weight);
+            }
+            image.add(pixel.0, pixel.1, radiance * (1.0 - accept) / weight);
+            if rng.gen::<f64>() < accept {
+                (pixel, radiance, weight) = (proposed_pixel, proposed, proposed_weight);
+                sampler.0.borrow_mut().accept();
+            } else {
+                sampler.0.borrow_mut().reject();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nalgebra::Point3;
+
+    use super::*;
+    use crate::{
+        background::Background,
+        lambertian::Lambertian,
+        renderer::{IntegratorKind, Renderer},
+        sphere::Sphere,
+    };
+
+    // Mean colour of a small spectral render of an orange ball under a saturated blue sky
+    fn render(integrator: IntegratorKind) -> Vector3<f64> {
+        let camera = Camera::new(
+            Point3::new(0.0, 0.0, 3.0),
+            Point3::origin(),
+            Vector3::new(0.0, 1.0, 0.0),
+            40.0,
+            4.0 / 3.0,
+            0.0,
+            3.0,
+        );
+        let ball = Sphere::new(Point3::new(-0.4, -0.2, 0.0), 0.5, Arc::new(Lambertian::new(Vector3::new(0.9, 0.4, 0.05))));
+        let scene = Scene::new(camera, vec![Arc::new(ball)]).with_background(Background::Solid(Vector3::new(0.05, 0.4, 0.9)));
+        let settings = RenderSettings {
+            width: 16,
+            height: 12,
+            samples_per_pixel: 256,
+            max_depth: 4,
+            integrator,
+            bootstrap_samples: 20_000,
+            chains: 64,
+            spectral: true,
+            ..RenderSettings::default()
+        };
+        let image = Renderer::new(settings).render(&scene);
+        image.pixels().iter().sum::<Vector3<f64>>() / image.pixels().len() as f64
+    }
+
+    #[test]
+    fn spectral_metropolis_matches_path_tracing() {
+        let path = render(IntegratorKind::Path);
+        let metropolis = render(IntegratorKind::Mlt);
+        // Spectral samples are noisy per channel, so the error is measured against the total
+        let error = (metropolis - path).abs().max() / path.sum();
+        assert!(error < 0.05, "metropolis {:?} path {:?}", metropolis, path);
+    }
+}
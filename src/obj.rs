@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+
+use nalgebra::{Point3, Vector2, Vector3};
+
+use crate::{
+    dielectric::Dielectric, diffuselight::DiffuseLight, hitrecord::Hitable, lambertian::Lambertian, loaderror::LoadError,
+    material::Material, mesh::TriangleMesh, metal::Metal,
+};
+
+// Material description read from a .mtl file, before it is mapped onto one of our materials
+#[derive(Clone, Debug)]
+pub struct MtlMaterial {
+    pub name: String,
+    pub diffuse: Vector3<f64>,
+    pub specular: Vector3<f64>,
+    pub emission: Vector3<f64>,
+    pub shininess: f64,
+    pub ior: f64,
+    pub dissolve: f64,
+    pub illum: u32,
+}
+
+impl MtlMaterial {
+    pub fn new(name: &str) -> Self {
+        MtlMaterial {
+            name: name.to_string(),
+            diffuse: Vector3::new(0.8, 0.8, 0.8),
+            specular: Vector3::zeros(),
+            emission: Vector3::zeros(),
+            shininess: 0.0,
+            ior: 1.0,
+            dissolve: 1.0,
+            illum: 2,
+        }
+    }
+
+    // Map the illumination model onto the closest material we can render: anything with Ke
+    // becomes a light, transparent models glass, mirror models metal, everything else is diffuse.
+    pub fn to_material(&self) -> Arc<dyn Material> {
+        if self.emission.max() > 0.0 {
+            return Arc::new(DiffuseLight::new(self.emission));
+        }
+        let transparent = matches!(self.illum, 4 | 6 | 7 | 9) || self.dissolve < 1.0;
+        let reflective = matches!(self.illum, 3 | 5 | 8);
+        if transparent {
+            let ior = if self.ior > 1.0 { self.ior } else { 1.5 };
+            Arc::new(Dielectric::new(ior))
+        } else if reflective {
+            // Phong exponent to an approximate roughness, which is what fuzz models
+            let fuzz = (2.0 / (self.shininess + 2.0)).sqrt();
+            let albedo = if self.specular.max() > 0.0 { self.specular } else { self.diffuse };
+            Arc::new(Metal::new(albedo, fuzz))
+        } else {
+            Arc::new(Lambertian::new(self.diffuse))
+        }
+    }
+}
+
+// One triangle mesh per group/material pair of the .obj file
+pub struct ObjMesh {
+    pub name: String,
+    pub material: Option<String>,
+    pub mesh: Arc<TriangleMesh>,
+}
+
+pub struct ObjModel {
+    pub meshes: Vec<ObjMesh>,
+    pub materials: HashMap<String, MtlMaterial>,
+}
+
+impl ObjModel {
+    pub fn hitables(&self) -> Vec<Arc<dyn Hitable>> {
+        self.meshes
+            .iter()
+            .map(|m| m.mesh.clone() as Arc<dyn Hitable>)
+            .collect()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.meshes.iter().map(|m| m.mesh.triangle_count()).sum()
+    }
+}
+
+#[derive(Copy, Clone)]
+struct FaceVertex {
+    v: usize,
+    vt: Option<usize>,
+    vn: Option<usize>,
+}
+
+struct FaceBucket {
+    name: String,
+    material: Option<String>,
+    triangles: Vec<([FaceVertex; 3], u32)>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum NormalKey {
+    Given(usize),
+    Smooth(u32),
+    Flat(usize),
+}
+
+fn parse_floats<const N: usize>(
+    path: &Path,
+    line: usize,
+    keyword: &str,
+    tokens: &mut SplitWhitespace,
+) -> Result<[f64; N], LoadError> {
+    let mut values = [0.0; N];
+    for value in values.iter_mut() {
+        let token = tokens
+            .next()
+            .ok_or_else(|| LoadError::parse(path, line, format!("'{}' expects {} numbers", keyword, N)))?;
+        *value = token
+            .parse()
+            .map_err(|_| LoadError::parse(path, line, format!("invalid number '{}' in '{}'", token, keyword)))?;
+    }
+    Ok(values)
+}
+
+fn parse_vector(path: &Path, line: usize, keyword: &str, tokens: &mut SplitWhitespace) -> Result<Vector3<f64>, LoadError> {
+    let [x, y, z] = parse_floats::<3>(path, line, keyword, tokens)?;
+    Ok(Vector3::new(x, y, z))
+}
+
+fn parse_scalar(path: &Path, line: usize, keyword: &str, tokens: &mut SplitWhitespace) -> Result<f64, LoadError> {
+    let [x] = parse_floats::<1>(path, line, keyword, tokens)?;
+    Ok(x)
+}
+
+// Strip comments; returns None for blank lines
+fn content(raw: &str) -> Option<&str> {
+    let text = raw.split('#').next().unwrap_or("").trim();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+pub fn load_mtl(path: impl AsRef<Path>) -> Result<HashMap<String, MtlMaterial>, LoadError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|e| LoadError::io(path, e))?;
+    let mut materials = HashMap::new();
+    let mut current: Option<MtlMaterial> = None;
+
+    for (index, raw) in source.lines().enumerate() {
+        let line = index + 1;
+        let Some(text) = content(raw) else { continue };
+        let mut tokens = text.split_whitespace();
+        let keyword = tokens.next().unwrap();
+
+        if keyword == "newmtl" {
+            let name = tokens.collect::<Vec<_>>().join(" ");
+            if name.is_empty() {
+                return Err(LoadError::parse(path, line, "'newmtl' without a name"));
+            }
+            if let Some(done) = current.replace(MtlMaterial::new(&name)) {
+                materials.insert(done.name.clone(), done);
+            }
+            continue;
+        }
+
+        let material = match current.as_mut() {
+            Some(material) => material,
+            None if keyword.starts_with("map_") || keyword == "bump" || keyword == "disp" => continue,
+            None => return Err(LoadError::parse(path, line, format!("'{}' before any 'newmtl'", keyword))),
+        };
+        match keyword {
+            "Kd" => material.diffuse = parse_vector(path, line, keyword, &mut tokens)?,
+            "Ks" => material.specular = parse_vector(path, line, keyword, &mut tokens)?,
+            "Ke" => material.emission = parse_vector(path, line, keyword, &mut tokens)?,
+            "Ns" => material.shininess = parse_scalar(path, line, keyword, &mut tokens)?,
+            "Ni" => material.ior = parse_scalar(path, line, keyword, &mut tokens)?,
+            "d" => material.dissolve = parse_scalar(path, line, keyword, &mut tokens)?,
+            "Tr" => material.dissolve = 1.0 - parse_scalar(path, line, keyword, &mut tokens)?,
+            "illum" => {
+                let token = tokens.next().unwrap_or("");
+                material.illum = token
+                    .parse()
+                    .map_err(|_| LoadError::parse(path, line, format!("invalid illumination model '{}'", token)))?;
+            }
+            // Ambient colour, transmission filter and texture maps have no equivalent here
+            _ => {}
+        }
+    }
+    if let Some(done) = current {
+        materials.insert(done.name.clone(), done);
+    }
+    Ok(materials)
+}
+
+fn resolve_index(path: &Path, line: usize, token: &str, count: usize, kind: &str) -> Result<usize, LoadError> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| LoadError::parse(path, line, format!("invalid {} index '{}'", kind, token)))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else if index < 0 {
+        count as i64 + index
+    } else {
+        return Err(LoadError::parse(path, line, format!("{} index 0 is not allowed", kind)));
+    };
+    if resolved < 0 || resolved as usize >= count {
+        return Err(LoadError::parse(
+            path,
+            line,
+            format!("{} index {} out of range ({} defined so far)", kind, index, count),
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+// Loads a Wavefront OBJ file and the MTL libraries it names. Faces before any 'usemtl' get
+// `default_material`; naming a material no library defines is an error.
+pub fn load_obj(path: impl AsRef<Path>, default_material: Arc<dyn Material>) -> Result<ObjModel, LoadError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|e| LoadError::io(path, e))?;
+
+    let mut positions: Vec<Point3<f64>> = Vec::new();
+    let mut normals: Vec<Vector3<f64>> = Vec::new();
+    let mut uvs: Vec<Vector2<f64>> = Vec::new();
+    let mut materials: HashMap<String, MtlMaterial> = HashMap::new();
+
+    let mut buckets: Vec<FaceBucket> = Vec::new();
+    let mut bucket_lookup: HashMap<(String, Option<String>), usize> = HashMap::new();
+    let mut group = String::from("default");
+    let mut current_material: Option<String> = None;
+    let mut smoothing_group = 0u32;
+
+    for (index, raw) in source.lines().enumerate() {
+        let line = index + 1;
+        let Some(text) = content(raw) else { continue };
+        let mut tokens = text.split_whitespace();
+        let keyword = tokens.next().unwrap();
+
+        match keyword {
+            "v" => {
+                let [x, y, z] = parse_floats::<3>(path, line, keyword, &mut tokens)?;
+                positions.push(Point3::new(x, y, z));
+            }
+            "vn" => normals.push(parse_vector(path, line, keyword, &mut tokens)?),
+            "vt" => {
+                let [u] = parse_floats::<1>(path, line, keyword, &mut tokens)?;
+                let v = match tokens.next() {
+                    Some(token) => token
+                        .parse()
+                        .map_err(|_| LoadError::parse(path, line, format!("invalid number '{}' in 'vt'", token)))?,
+                    None => 0.0,
+                };
+                uvs.push(Vector2::new(u, v));
+            }
+            "f" => {
+                let mut corners = Vec::with_capacity(4);
+                for token in tokens {
+                    let mut parts = token.split('/');
+                    let v = resolve_index(path, line, parts.next().unwrap_or(""), positions.len(), "vertex")?;
+                    let vt = match parts.next() {
+                        Some("") | None => None,
+                        Some(t) => Some(resolve_index(path, line, t, uvs.len(), "texture coordinate")?),
+                    };
+                    let vn = match parts.next() {
+                        Some("") | None => None,
+                        Some(n) => Some(resolve_index(path, line, n, normals.len(), "normal")?),
+                    };
+                    corners.push(FaceVertex { v, vt, vn });
+                }
+                if corners.len() < 3 {
+                    return Err(LoadError::parse(path, line, "face needs at least three vertices"));
+                }
+
+                let key = (group.clone(), current_material.clone());
+                let bucket = *bucket_lookup.entry(key).or_insert_with(|| {
+                    buckets.push(FaceBucket {
+                        name: group.clone(),
+                        material: current_material.clone(),
+                        triangles: Vec::new(),
+                    });
+                    buckets.len() - 1
+                });
+                // Polygons are triangulated as a fan around the first corner
+                for i in 1..corners.len() - 1 {
+                    buckets[bucket]
+                        .triangles
+                        .push(([corners[0], corners[i], corners[i + 1]], smoothing_group));
+                }
+            }
+            "g" | "o" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                group = if name.is_empty() { String::from("default") } else { name };
+            }
+            "s" => {
+                let token = tokens.next().unwrap_or("off");
+                smoothing_group = match token {
+                    "off" => 0,
+                    _ => token
+                        .parse()
+                        .map_err(|_| LoadError::parse(path, line, format!("invalid smoothing group '{}'", token)))?,
+                };
+            }
+            "usemtl" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                if !name.is_empty() && !materials.contains_key(&name) {
+                    return Err(LoadError::parse(path, line, format!("material '{}' is not defined by any 'mtllib'", name)));
+                }
+                current_material = if name.is_empty() { None } else { Some(name) };
+            }
+            "mtllib" => {
+                let directory = path.parent().unwrap_or_else(|| Path::new(""));
+                for library in tokens {
+                    materials.extend(load_mtl(directory.join(library))?);
+                }
+            }
+            // Free-form geometry, lines and points are not renderable
+            _ => {}
+        }
+    }
+
+    let mut converted: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    let mut meshes = Vec::with_capacity(buckets.len());
+    for bucket in buckets {
+        let material = match bucket.material.as_ref().and_then(|name| materials.get(name)) {
+            Some(mtl) => converted
+                .entry(mtl.name.clone())
+                .or_insert_with(|| mtl.to_material())
+                .clone(),
+            None => default_material.clone(),
+        };
+        let mesh = build_mesh(&bucket, &positions, &normals, &uvs, material);
+        meshes.push(ObjMesh {
+            name: bucket.name,
+            material: bucket.material,
+            mesh: Arc::new(mesh),
+        });
+    }
+
+    Ok(ObjModel { meshes, materials })
+}
+
+fn build_mesh(
+    bucket: &FaceBucket,
+    positions: &[Point3<f64>],
+    normals: &[Vector3<f64>],
+    uvs: &[Vector2<f64>],
+    material: Arc<dyn Material>,
+) -> TriangleMesh {
+    let has_uvs = bucket.triangles.iter().any(|(c, _)| c.iter().any(|f| f.vt.is_some()));
+    let has_normals = bucket
+        .triangles
+        .iter()
+        .any(|(c, s)| *s != 0 || c.iter().any(|f| f.vn.is_some()));
+
+    // Area-weighted face normals accumulated per (position, smoothing group)
+    let mut smooth_normals: HashMap<(usize, u32), Vector3<f64>> = HashMap::new();
+    for (corners, group) in &bucket.triangles {
+        if *group == 0 {
+            continue;
+        }
+        let [a, b, c] = corners.map(|f| positions[f.v]);
+        let face_normal = (b - a).cross(&(c - a));
+        for corner in corners.iter().filter(|f| f.vn.is_none()) {
+            *smooth_normals.entry((corner.v, *group)).or_insert_with(Vector3::zeros) += face_normal;
+        }
+    }
+
+    let mut vertex_lookup: HashMap<(usize, Option<usize>, Option<NormalKey>), usize> = HashMap::new();
+    let mut mesh_positions = Vec::new();
+    let mut mesh_normals = Vec::new();
+    let mut mesh_uvs = Vec::new();
+    let mut indices = Vec::with_capacity(bucket.triangles.len());
+
+    for (triangle, (corners, group)) in bucket.triangles.iter().enumerate() {
+        let [a, b, c] = corners.map(|f| positions[f.v]);
+        let face_normal = (b - a).cross(&(c - a));
+        let mut triangle_indices = [0; 3];
+        for (slot, corner) in corners.iter().enumerate() {
+            let normal_key = if !has_normals {
+                None
+            } else if let Some(vn) = corner.vn {
+                Some(NormalKey::Given(vn))
+            } else if *group != 0 {
+                Some(NormalKey::Smooth(*group))
+            } else {
+                Some(NormalKey::Flat(triangle))
+            };
+            let key = (corner.v, corner.vt, normal_key);
+            triangle_indices[slot] = *vertex_lookup.entry(key).or_insert_with(|| {
+                mesh_positions.push(positions[corner.v]);
+                if has_uvs {
+                    mesh_uvs.push(corner.vt.map_or_else(Vector2::zeros, |vt| uvs[vt]));
+                }
+                if let Some(normal_key) = normal_key {
+                    let normal = match normal_key {
+                        NormalKey::Given(vn) => normals[vn],
+                        NormalKey::Smooth(g) => smooth_normals[&(corner.v, g)],
+                        NormalKey::Flat(_) => face_normal,
+                    };
+                    let length = normal.norm();
+                    mesh_normals.push(if length > 0.0 { normal / length } else { Vector3::y() });
+                }
+                mesh_positions.len() - 1
+            });
+        }
+        indices.push(triangle_indices);
+    }
+
+    TriangleMesh::new(mesh_positions, mesh_normals, mesh_uvs, indices, material)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lambertian::Lambertian, util::temp_file};
+
+    fn grey() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Vector3::new(0.5, 0.5, 0.5)))
+    }
+
+    fn line_of(error: LoadError) -> usize {
+        match error {
+            LoadError::Parse { line, .. } => line,
+            other => panic!("expected a parse error, got {}", other),
+        }
+    }
+
+    #[test]
+    fn loads_groups_materials_and_polygons() {
+        temp_file("good.mtl", "newmtl red\nKd 0.8 0.1 0.1\n\nnewmtl lamp\nKe 4 4 4\n");
+        let path = temp_file(
+            "good.obj",
+            "mtllib good.mtl\n\
+             v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\n\
+             g quad\nusemtl red\nf 1 2 3 4\n\
+             g tip\nusemtl lamp\nf 1 -1 2\n",
+        );
+        let model = load_obj(&path, grey()).unwrap();
+        assert_eq!(model.triangle_count(), 3);
+        let names: Vec<(&str, Option<&str>)> = model.meshes.iter().map(|m| (m.name.as_str(), m.material.as_deref())).collect();
+        assert_eq!(names, [("quad", Some("red")), ("tip", Some("lamp"))]);
+        assert_eq!(model.materials["red"].diffuse, Vector3::new(0.8, 0.1, 0.1));
+    }
+
+    #[test]
+    fn reports_the_line_of_a_bad_number() {
+        let path = temp_file("bad_number.obj", "v 0 0 0\nv 1 0 0\n# comment\nv 1 x 0\nf 1 2 3\n");
+        assert_eq!(line_of(load_obj(&path, grey()).err().unwrap()), 4);
+    }
+
+    #[test]
+    fn reports_the_line_of_an_unknown_material() {
+        temp_file("known.mtl", "newmtl red\nKd 0.8 0.1 0.1\n");
+        let path = temp_file("unknown_material.obj", "mtllib known.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nusemtl blue\nf 1 2 3\n");
+        assert_eq!(line_of(load_obj(&path, grey()).err().unwrap()), 5);
+    }
+
+    #[test]
+    fn reports_the_line_of_a_malformed_mtl() {
+        let path = temp_file("bad.mtl", "newmtl red\nKd 0.8 0.1\n");
+        assert_eq!(line_of(load_mtl(&path).err().unwrap()), 2);
+    }
+}
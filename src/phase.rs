@@ -0,0 +1,122 @@
+use std::f64::consts::PI;
+
+use nalgebra::Vector3;
+
+use crate::{
+    frame::Frame,
+    hitrecord::HitRecord,
+    material::{Lobes, Material, ScatterRecord},
+    ray::Ray,
+    util::random_f64,
+};
+
+// Phase functions scatter light inside participating media. They act as materials of the
+// points media report as hits, which have no surface: eval() is the albedo times the phase
+// function with no cosine, and directions are measured from the one the light was travelling
+// in. Their lobes are glossy so integrators sample lights from inside the medium, and both
+// reflection and transmission as light can leave in any direction.
+fn phase_lobes() -> Lobes {
+    Lobes::GLOSSY | Lobes::REFLECTION | Lobes::TRANSMISSION
+}
+
+// Scatter record for the local direction `local` about the incoming ray's direction
+fn scatter(ray_in: &Ray, hit_record: &HitRecord, local: Vector3<f64>, attenuation: Vector3<f64>, pdf: f64) -> ScatterRecord {
+    let frame = Frame::new(ray_in.direction);
+    ScatterRecord {
+        attenuation,
+        scattered: Ray::new(hit_record.p, frame.to_world(&local)),
+        pdf,
+        lobe: phase_lobes(),
+        frame,
+    }
+}
+
+// Scatters equally in every direction
+pub struct Isotropic {
+    albedo: Vector3<f64>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Vector3<f64>) -> Self {
+        Isotropic { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn sample(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
+        let cos_theta = 1.0 - 2.0 * random_f64();
+        let local = direction_about_z(cos_theta);
+        Some(scatter(ray_in, hit_record, local, hit_record.albedo(self.albedo), 1.0 / (4.0 * PI)))
+    }
+
+    fn eval(&self, _ray_in: &Ray, hit_record: &HitRecord, _direction: &Vector3<f64>) -> Vector3<f64> {
+        hit_record.albedo(self.albedo) / (4.0 * PI)
+    }
+
+    fn pdf(&self, _ray_in: &Ray, _hit_record: &HitRecord, _direction: &Vector3<f64>) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn lobes(&self) -> Lobes {
+        phase_lobes()
+    }
+}
+
+// Henyey-Greenstein phase function: `g` between -1 and 1 is the mean cosine of the scattering
+// angle, positive for media like haze that mostly scatter forward, negative for backward
+pub struct HenyeyGreenstein {
+    albedo: Vector3<f64>,
+    g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(albedo: Vector3<f64>, g: f64) -> Self {
+        HenyeyGreenstein { albedo, g }
+    }
+
+    fn phase(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+    }
+
+    fn cos_theta(ray_in: &Ray, direction: &Vector3<f64>) -> f64 {
+        ray_in.direction.normalize().dot(&direction.normalize())
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    // Inverts the phase function's distribution of cos(theta), so sampling is exact and the
+    // attenuation is just the albedo
+    fn sample(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
+        let g = self.g;
+        let xi = random_f64();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * xi
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let local = direction_about_z(cos_theta);
+        Some(scatter(ray_in, hit_record, local, hit_record.albedo(self.albedo), self.phase(cos_theta)))
+    }
+
+    fn eval(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vector3<f64>) -> Vector3<f64> {
+        hit_record.albedo(self.albedo) * self.phase(HenyeyGreenstein::cos_theta(ray_in, direction))
+    }
+
+    fn pdf(&self, ray_in: &Ray, _hit_record: &HitRecord, direction: &Vector3<f64>) -> f64 {
+        self.phase(HenyeyGreenstein::cos_theta(ray_in, direction))
+    }
+
+    fn lobes(&self) -> Lobes {
+        phase_lobes()
+    }
+}
+
+// Unit direction at angle acos(cos_theta) from +z, uniformly around it
+fn direction_about_z(cos_theta: f64) -> Vector3<f64> {
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * random_f64();
+    Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
@@ -0,0 +1,292 @@
+use std::f64::consts::PI;
+
+use nalgebra::{Point3, Vector3};
+use rayon::prelude::*;
+
+use crate::{
+    camera::Camera,
+    frame::Frame,
+    hitrecord::{HitRecord, Hitable},
+    integrator::{Integrator, Splat},
+    light::Light,
+    material::Lobes,
+    ray::Ray,
+    renderer::{MisHeuristic, RenderSettings},
+    scene::Scene,
+    spectrum::set_wavelength,
+    util::{hit_light, random_f64, sample_lights, seed_rng},
+};
+
+// How fast the gather radius shrinks from pass to pass; smaller values shrink it faster
+const ALPHA: f64 = 2.0 / 3.0;
+// Initial gather radius, in pixels on the plane through the camera's look-at point, when the
+// settings leave it open
+const INITIAL_RADIUS_PIXELS: f64 = 4.0;
+// Photons on surfaces turned further away than this from the gather point's are left out, so
+// light does not leak round corners
+const MIN_NORMAL_COSINE: f64 = 0.9;
+// Photon paths traced per parallel job, each with its own random stream
+const PHOTONS_PER_JOB: u32 = 4096;
+
+// Light that reached a diffuse surface after at least one bounce
+#[derive(Clone, Copy)]
+pub struct Photon {
+    pub position: Point3<f64>,
+    // Surface normal on the side the photon arrived from
+    pub normal: Vector3<f64>,
+    // Unit direction back towards where the photon came from
+    pub direction: Vector3<f64>,
+    // Flux carried, for one photon path out of all those emitted
+    pub power: Vector3<f64>,
+}
+
+// Photons in a kd-tree over their positions, split at the median along x, y and z in turn like
+// KdNode. Every node holds the photon it was split at.
+pub struct PhotonNode {
+    photon: Photon,
+    axis: usize,
+    left: Option<Box<PhotonNode>>,
+    right: Option<Box<PhotonNode>>,
+}
+
+impl PhotonNode {
+    pub fn new(photons: &mut [Photon], depth: u32) -> Option<Self> {
+        if photons.is_empty() {
+            return None;
+        }
+        let axis = (depth % 3) as usize;
+        let middle = photons.len() / 2;
+        photons.select_nth_unstable_by(middle, |a, b| a.position[axis].total_cmp(&b.position[axis]));
+        let (left, rest) = photons.split_at_mut(middle);
+        let (photon, right) = rest.split_first_mut().unwrap();
+        Some(PhotonNode {
+            photon: *photon,
+            axis,
+            left: PhotonNode::new(left, depth + 1).map(Box::new),
+            right: PhotonNode::new(right, depth + 1).map(Box::new),
+        })
+    }
+
+    pub fn from_photons(photons: Vec<Photon>) -> Option<Self> {
+        let mut photons = photons;
+        PhotonNode::new(&mut photons, 0)
+    }
+
+    // Call `f` for every photon within sqrt(radius_squared) of `point`
+    pub fn for_each_within(&self, point: &Point3<f64>, radius_squared: f64, f: &mut impl FnMut(&Photon)) {
+        if (self.photon.position - point).magnitude_squared() <= radius_squared {
+            f(&self.photon);
+        }
+        let offset = point[self.axis] - self.photon.position[self.axis];
+        let (near, far) = if offset < 0.0 { (&self.left, &self.right) } else { (&self.right, &self.left) };
+        if let Some(near) = near {
+            near.for_each_within(point, radius_squared, f);
+        }
+        if offset * offset <= radius_squared {
+            if let Some(far) = far {
+                far.for_each_within(point, radius_squared, f);
+            }
+        }
+    }
+}
+
+// Progressive photon mapping in the probabilistic form of Knaus and Zwicker (2011): every pass
+// shoots a fresh photon map and renders one sample per pixel, gathering photons within a radius
+// that shrinks slowly from pass to pass, so the average of the passes converges. Camera paths
+// are traced as in the path tracer up to the first diffuse surface, which takes direct light
+// by light sampling and all further light from the photons around it, so caustics seen on
+// diffuse surfaces come out clean. Photons start only on lights with a position, so light
+// arriving from the sky, the environment or directional lights is not carried past that
+// surface.
+pub struct PhotonMapper {
+    max_depth: u32,
+    roulette_depth: u32,
+    heuristic: MisHeuristic,
+    photons: Option<PhotonNode>,
+    // Photon paths emitted, including those that left no photons
+    paths: u32,
+    radius: f64,
+    // The pass's wavelength and its density, in spectral rendering
+    wavelength: Option<(f64, f64)>,
+}
+
+impl PhotonMapper {
+    // Shoot the photons for `pass`, counted from 0, at `wavelength` (with its density) in
+    // spectral rendering
+    pub fn new(
+        settings: &RenderSettings,
+        camera: &Camera,
+        world: &dyn Hitable,
+        scene: &Scene,
+        pass: u32,
+        wavelength: Option<(f64, f64)>,
+    ) -> Self {
+        let initial_radius = settings
+            .photon_radius
+            .unwrap_or_else(|| INITIAL_RADIUS_PIXELS * camera.pixel_footprint(settings.width));
+        let radius_squared = (1..=pass).fold(initial_radius * initial_radius, |r2, i| r2 * (i as f64 + ALPHA) / (i as f64 + 1.0));
+        let mut mapper = PhotonMapper {
+            max_depth: settings.max_depth,
+            roulette_depth: settings.roulette_depth,
+            heuristic: settings.mis,
+            photons: None,
+            paths: settings.photons,
+            radius: radius_squared.sqrt(),
+            wavelength,
+        };
+
+        let lights: Vec<&Light> = scene.lights.iter().filter(|light| !light.is_infinite()).collect();
+        if lights.is_empty() {
+            return mapper;
+        }
+        let jobs = settings.photons.div_ceil(PHOTONS_PER_JOB);
+        let photons: Vec<Photon> = (0..jobs)
+            .into_par_iter()
+            .flat_map_iter(|job| {
+                let stream = (pass as u64) << 32 | job as u64;
+                seed_rng(settings.seed ^ stream.wrapping_mul(0xD1B5_4A32_D192_ED03));
+                let count = PHOTONS_PER_JOB.min(settings.photons - job * PHOTONS_PER_JOB);
+                let mut photons = Vec::new();
+                set_wavelength(wavelength.map(|(lambda, _)| lambda));
+                for _ in 0..count {
+                    mapper.trace_photon(world, &lights, &mut photons);
+                }
+                set_wavelength(None);
+                photons
+            })
+            .collect();
+        mapper.photons = PhotonNode::from_photons(photons);
+        mapper
+    }
+
+    // Follow one photon path from a light, leaving a photon on every diffuse surface it reaches
+    // after its first bounce; light arriving directly is found by light sampling instead
+    fn trace_photon(&self, world: &dyn Hitable, lights: &[&Light], photons: &mut Vec<Photon>) {
+        let light_choice = 1.0 / lights.len() as f64;
+        let light = lights[((random_f64() * lights.len() as f64) as usize).min(lights.len() - 1)];
+        let Some(emission) = light.sample_emission() else {
+            return;
+        };
+        if emission.pdf_position <= 0.0 || emission.pdf_direction <= 0.0 {
+            return;
+        }
+        // Point and spot lights have no surface to be seen at an angle
+        let cosine = if emission.normal == Vector3::zeros() { 1.0 } else { emission.normal.dot(&emission.direction).abs() };
+        let mut power = emission.radiance * cosine / (light_choice * emission.pdf_position * emission.pdf_direction);
+        let mut ray = Ray::new(emission.origin, emission.direction);
+
+        for bounce in 0..self.max_depth {
+            if power == Vector3::zeros() {
+                break;
+            }
+            let Some(hit_record) = world.hit(&ray, 0.001, f64::INFINITY) else {
+                break;
+            };
+            let material = &hit_record.material;
+            if bounce > 0 && material.lobes().intersects(Lobes::DIFFUSE) {
+                photons.push(Photon {
+                    position: hit_record.p,
+                    normal: Frame::facing(hit_record.normal, &ray.direction).n,
+                    direction: -ray.direction.normalize(),
+                    power,
+                });
+            }
+            let Some(scatter) = material.sample(&ray, &hit_record) else {
+                break;
+            };
+            power.component_mul_assign(&(scatter.attenuation * material.importance_scale(&ray, &hit_record, &scatter.scattered.direction)));
+            if bounce >= self.roulette_depth {
+                let survival = power.max().min(0.95);
+                if survival <= 0.0 || random_f64() >= survival {
+                    break;
+                }
+                power /= survival;
+            }
+            ray = scatter.scattered;
+        }
+    }
+
+    // Radiance reflected towards the ray's origin by the photons around the hit
+    fn estimate(&self, ray: &Ray, hit_record: &HitRecord) -> Vector3<f64> {
+        let Some(photons) = &self.photons else {
+            return Vector3::zeros();
+        };
+        let normal = Frame::facing(hit_record.normal, &ray.direction).n;
+        let mut reflected = Vector3::new(0.0, 0.0, 0.0);
+        photons.for_each_within(&hit_record.p, self.radius * self.radius, &mut |photon| {
+            if photon.normal.dot(&normal) < MIN_NORMAL_COSINE {
+                return;
+            }
+            // eval() includes the cosine the photon's flux was already projected by
+            let cosine = normal.dot(&photon.direction);
+            if cosine > 0.0 {
+                let f = hit_record.material.eval(ray, hit_record, &photon.direction) / cosine;
+                reflected += f.component_mul(&photon.power);
+            }
+        });
+        reflected / (PI * self.radius * self.radius * self.paths as f64)
+    }
+}
+
+impl Integrator for PhotonMapper {
+    fn radiance(&self, ray: &Ray, world: &dyn Hitable, scene: &Scene, _splats: &mut Vec<Splat>) -> Vector3<f64> {
+        let mut radiance = Vector3::new(0.0, 0.0, 0.0);
+        let mut throughput = Vector3::new(1.0, 1.0, 1.0);
+        let mut ray = Ray::new(ray.origin, ray.direction);
+        let mut bsdf_pdf: Option<f64> = None;
+        // Once the photons have been gathered, the path goes one bounce further only to find the
+        // lights its light samples were weighted against
+        let mut gathered = false;
+
+        for bounce in 0..self.max_depth {
+            // Lights are not part of the world, so only look for surfaces, and fog, in front of
+            // the nearest one
+            let light_hit = hit_light(&scene.lights, &ray, 0.001, f64::INFINITY);
+            let hit = world.hit(&ray, 0.001, light_hit.map_or(f64::INFINITY, |(_, t, _)| t));
+            if let (None, Some((light, _, emitted))) = (&hit, light_hit) {
+                let weight = bsdf_pdf.map_or(1.0, |pdf| self.heuristic.weight(pdf, light.pdf(&ray.origin, &ray.direction)));
+                radiance += throughput.component_mul(&emitted) * weight;
+                break;
+            }
+            let Some(hit_record) = hit else {
+                let background = scene.background.color(&ray.direction);
+                let weight = bsdf_pdf.map_or(1.0, |pdf| self.heuristic.weight(pdf, scene.background.pdf(&ray.direction)));
+                radiance += throughput.component_mul(&background) * weight;
+                break;
+            };
+
+            let material = &hit_record.material;
+            radiance += throughput.component_mul(&material.emitted(&ray, &hit_record));
+            if gathered {
+                break;
+            }
+            let Some(scatter) = material.sample(&ray, &hit_record) else {
+                break;
+            };
+            if material.lobes().is_smooth() {
+                radiance += throughput.component_mul(&sample_lights(&ray, &hit_record, world, scene, self.heuristic, |_| true));
+            }
+            if material.lobes().intersects(Lobes::DIFFUSE) {
+                radiance += throughput.component_mul(&self.estimate(&ray, &hit_record));
+                gathered = true;
+            }
+            throughput.component_mul_assign(&scatter.attenuation);
+
+            if bounce >= self.roulette_depth {
+                let survival = throughput.max().min(0.95);
+                if survival <= 0.0 || random_f64() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+
+            bsdf_pdf = if scatter.is_specular() { None } else { Some(scatter.pdf) };
+            ray = scatter.scattered;
+        }
+        radiance
+    }
+
+    fn wavelength(&self) -> Option<(f64, f64)> {
+        self.wavelength
+    }
+}
@@ -0,0 +1,415 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use nalgebra::{Point3, Vector2, Vector3};
+
+use crate::{loaderror::LoadError, material::Material, mesh::TriangleMesh, util::srgb_to_linear};
+
+#[derive(Copy, Clone, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Copy, Clone)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    // Scale that maps integer colour channels onto [0, 1]
+    fn color_scale(self) -> f64 {
+        match self {
+            ScalarType::U8 => 1.0 / 255.0,
+            ScalarType::U16 => 1.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+enum PropertyKind {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// Reads scalars from the body, one element instance at a time
+struct BodyReader<'a> {
+    path: &'a Path,
+    format: PlyFormat,
+    bytes: &'a [u8],
+    offset: usize,
+    line: usize,
+    tokens: Vec<&'a str>,
+    token: usize,
+}
+
+impl<'a> BodyReader<'a> {
+    fn begin_instance(&mut self) -> Result<(), LoadError> {
+        if self.format != PlyFormat::Ascii {
+            return Ok(());
+        }
+        loop {
+            if self.offset >= self.bytes.len() {
+                return Err(LoadError::parse(self.path, self.line, "unexpected end of file"));
+            }
+            let end = self.bytes[self.offset..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(self.bytes.len(), |p| self.offset + p);
+            let text = std::str::from_utf8(&self.bytes[self.offset..end])
+                .map_err(|_| LoadError::parse(self.path, self.line + 1, "invalid UTF-8 in ASCII body"))?;
+            self.offset = end + 1;
+            self.line += 1;
+            self.tokens = text.split_whitespace().collect();
+            self.token = 0;
+            if !self.tokens.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn read(&mut self, ty: ScalarType) -> Result<f64, LoadError> {
+        match self.format {
+            PlyFormat::Ascii => {
+                let token = *self
+                    .tokens
+                    .get(self.token)
+                    .ok_or_else(|| LoadError::parse(self.path, self.line, "too few values for element"))?;
+                self.token += 1;
+                token
+                    .parse()
+                    .map_err(|_| LoadError::parse(self.path, self.line, format!("invalid number '{}'", token)))
+            }
+            PlyFormat::BinaryLittleEndian | PlyFormat::BinaryBigEndian => {
+                let size = ty.size();
+                let Some(raw) = self.bytes.get(self.offset..self.offset + size) else {
+                    return Err(LoadError::format(self.path, format!("unexpected end of data at byte {}", self.offset)));
+                };
+                self.offset += size;
+                let mut buf = [0u8; 8];
+                buf[..size].copy_from_slice(raw);
+                if self.format == PlyFormat::BinaryBigEndian {
+                    buf[..size].reverse();
+                }
+                Ok(match ty {
+                    ScalarType::I8 => buf[0] as i8 as f64,
+                    ScalarType::U8 => buf[0] as f64,
+                    ScalarType::I16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+                    ScalarType::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+                    ScalarType::I32 => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+                    ScalarType::U32 => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+                    ScalarType::F32 => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+                    ScalarType::F64 => f64::from_le_bytes(buf),
+                })
+            }
+        }
+    }
+}
+
+fn parse_header(path: &Path, bytes: &[u8]) -> Result<(PlyFormat, Vec<Element>, usize, usize), LoadError> {
+    let mut offset = 0;
+    let mut line = 0;
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+
+    loop {
+        let end = bytes[offset..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map(|p| offset + p)
+            .ok_or_else(|| LoadError::parse(path, line + 1, "header is missing 'end_header'"))?;
+        let text = String::from_utf8_lossy(&bytes[offset..end]);
+        let text = text.trim();
+        offset = end + 1;
+        line += 1;
+
+        let mut tokens = text.split_whitespace();
+        let keyword = tokens.next().unwrap_or("");
+        if line == 1 {
+            if keyword != "ply" {
+                return Err(LoadError::parse(path, line, "not a PLY file (missing 'ply' magic)"));
+            }
+            continue;
+        }
+        match keyword {
+            "format" => {
+                format = Some(match tokens.next() {
+                    Some("ascii") => PlyFormat::Ascii,
+                    Some("binary_little_endian") => PlyFormat::BinaryLittleEndian,
+                    Some("binary_big_endian") => PlyFormat::BinaryBigEndian,
+                    other => {
+                        return Err(LoadError::parse(
+                            path,
+                            line,
+                            format!("unsupported format '{}'", other.unwrap_or("")),
+                        ))
+                    }
+                });
+            }
+            "element" => {
+                let name = tokens
+                    .next()
+                    .ok_or_else(|| LoadError::parse(path, line, "'element' without a name"))?;
+                let count = tokens
+                    .next()
+                    .and_then(|c| c.parse().ok())
+                    .ok_or_else(|| LoadError::parse(path, line, format!("invalid count for element '{}'", name)))?;
+                elements.push(Element { name: name.to_string(), count, properties: Vec::new() });
+            }
+            "property" => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| LoadError::parse(path, line, "'property' before any 'element'"))?;
+                let words: Vec<&str> = tokens.collect();
+                let unknown = |ty: &str| LoadError::parse(path, line, format!("unknown property type '{}'", ty));
+                let property = match words.as_slice() {
+                    ["list", count, item, name] => Property {
+                        name: name.to_string(),
+                        kind: PropertyKind::List {
+                            count: ScalarType::parse(count).ok_or_else(|| unknown(count))?,
+                            item: ScalarType::parse(item).ok_or_else(|| unknown(item))?,
+                        },
+                    },
+                    [ty, name] => Property {
+                        name: name.to_string(),
+                        kind: PropertyKind::Scalar(ScalarType::parse(ty).ok_or_else(|| unknown(ty))?),
+                    },
+                    _ => return Err(LoadError::parse(path, line, "malformed 'property' line")),
+                };
+                element.properties.push(property);
+            }
+            "end_header" => break,
+            "comment" | "obj_info" | "" => {}
+            other => return Err(LoadError::parse(path, line, format!("unexpected header keyword '{}'", other))),
+        }
+    }
+
+    let format = format.ok_or_else(|| LoadError::format(path, "header has no 'format' line"))?;
+    Ok((format, elements, offset, line))
+}
+
+// Loads an ASCII or binary PLY mesh. Vertex colours, when present, tint the material's albedo,
+// so pass a white material to use them as the albedo directly.
+pub fn load_ply(path: impl AsRef<Path>, material: Arc<dyn Material>) -> Result<TriangleMesh, LoadError> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|e| LoadError::io(path, e))?;
+    let (format, elements, body_offset, header_lines) = parse_header(path, &bytes)?;
+
+    let mut reader = BodyReader {
+        path,
+        format,
+        bytes: &bytes,
+        offset: body_offset,
+        line: header_lines,
+        tokens: Vec::new(),
+        token: 0,
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    let mut indices: Vec<[usize; 3]> = Vec::new();
+    let mut polygon = Vec::new();
+
+    for element in &elements {
+        let is_vertex = element.name == "vertex";
+        let is_face = element.name == "face";
+        let slot = |names: &[&str]| element.properties.iter().position(|p| names.contains(&p.name.as_str()));
+        let xyz = [slot(&["x"]), slot(&["y"]), slot(&["z"])];
+        let normal = [slot(&["nx"]), slot(&["ny"]), slot(&["nz"])];
+        let uv = [
+            slot(&["u", "s", "texture_u", "texture_s"]),
+            slot(&["v", "t", "texture_v", "texture_t"]),
+        ];
+        let rgb = [
+            slot(&["red", "diffuse_red", "r"]),
+            slot(&["green", "diffuse_green", "g"]),
+            slot(&["blue", "diffuse_blue", "b"]),
+        ];
+        let face_list = slot(&["vertex_indices", "vertex_index"]);
+
+        if is_vertex && xyz.iter().any(|s| s.is_none()) {
+            return Err(LoadError::format(path, "vertex element is missing x, y or z"));
+        }
+        if is_face && face_list.is_none() {
+            return Err(LoadError::format(path, "face element has no 'vertex_indices' list"));
+        }
+
+        let mut values = vec![0.0; element.properties.len()];
+        for _ in 0..element.count {
+            reader.begin_instance()?;
+            for (p, property) in element.properties.iter().enumerate() {
+                match property.kind {
+                    PropertyKind::Scalar(ty) => {
+                        values[p] = reader.read(ty)? * if rgb.contains(&Some(p)) { ty.color_scale() } else { 1.0 };
+                    }
+                    PropertyKind::List { count, item } => {
+                        let n = reader.read(count)? as usize;
+                        // Other lists, such as per-corner texture coordinates, are read past
+                        let capture = is_face && face_list == Some(p);
+                        if capture {
+                            polygon.clear();
+                        }
+                        for _ in 0..n {
+                            let value = reader.read(item)?;
+                            if capture {
+                                if value < 0.0 {
+                                    return Err(LoadError::format(path, format!("negative vertex index {}", value)));
+                                }
+                                polygon.push(value as usize);
+                            }
+                        }
+                    }
+                }
+            }
+
+            if is_vertex {
+                let get = |s: Option<usize>| values[s.unwrap()];
+                positions.push(Point3::new(get(xyz[0]), get(xyz[1]), get(xyz[2])));
+                if normal.iter().all(|s| s.is_some()) {
+                    normals.push(Vector3::new(get(normal[0]), get(normal[1]), get(normal[2])).normalize());
+                }
+                if uv.iter().all(|s| s.is_some()) {
+                    uvs.push(Vector2::new(get(uv[0]), get(uv[1])));
+                }
+                if rgb.iter().all(|s| s.is_some()) {
+                    colors.push(Vector3::new(get(rgb[0]), get(rgb[1]), get(rgb[2])).map(srgb_to_linear));
+                }
+            } else if is_face {
+                if polygon.len() < 3 {
+                    return Err(match format {
+                        PlyFormat::Ascii => LoadError::parse(path, reader.line, "face needs at least three vertices"),
+                        _ => LoadError::format(path, format!("face {} has fewer than three vertices", indices.len())),
+                    });
+                }
+                for i in 1..polygon.len() - 1 {
+                    indices.push([polygon[0], polygon[i], polygon[i + 1]]);
+                }
+            }
+        }
+    }
+
+    if indices.is_empty() {
+        return Err(LoadError::format(path, "file has no faces to render"));
+    }
+    if let Some(bad) = indices.iter().flatten().find(|&&i| i >= positions.len()) {
+        return Err(LoadError::format(
+            path,
+            format!("face references vertex {} but only {} vertices exist", bad, positions.len()),
+        ));
+    }
+
+    Ok(TriangleMesh::new(positions, normals, uvs, indices, material).with_colors(colors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lambertian::Lambertian, util::temp_file};
+
+    fn grey() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Vector3::new(0.5, 0.5, 0.5)))
+    }
+
+    fn line_of(error: LoadError) -> usize {
+        match error {
+            LoadError::Parse { line, .. } => line,
+            other => panic!("expected a parse error, got {}", other),
+        }
+    }
+
+    const HEADER: &str = "ply\nformat ascii 1.0\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+                          property uchar red\nproperty uchar green\nproperty uchar blue\n\
+                          element face 1\nproperty list uchar int vertex_indices\nproperty list uchar float texcoord\nend_header\n";
+
+    #[test]
+    fn loads_faces_with_more_than_one_list() {
+        let source = format!(
+            "{}0 0 0 255 0 0\n1 0 0 255 0 0\n1 1 0 255 0 0\n0 1 0 255 0 0\n4 0 1 2 3 8 0 0 1 0 1 1 0 1\n",
+            HEADER
+        );
+        let mesh = load_ply(temp_file("two_lists.ply", source), grey()).unwrap();
+        assert_eq!((mesh.vertex_count(), mesh.triangle_count()), (4, 2));
+    }
+
+    #[test]
+    fn loads_binary_little_endian() {
+        let mut bytes = b"ply\nformat binary_little_endian 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+                          property float z\nelement face 1\nproperty list uchar uint vertex_indices\nend_header\n"
+            .to_vec();
+        for value in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.push(3);
+        for index in [0u32, 1, 2] {
+            bytes.extend_from_slice(&index.to_le_bytes());
+        }
+        let mesh = load_ply(temp_file("binary.ply", bytes), grey()).unwrap();
+        assert_eq!((mesh.vertex_count(), mesh.triangle_count()), (3, 1));
+    }
+
+    #[test]
+    fn reports_the_line_of_a_short_face() {
+        let source = format!("{}0 0 0 0 0 0\n1 0 0 0 0 0\n1 1 0 0 0 0\n0 1 0 0 0 0\n2 0 1 0\n", HEADER);
+        assert_eq!(line_of(load_ply(temp_file("short_face.ply", source), grey()).err().unwrap()), 18);
+    }
+
+    #[test]
+    fn reports_the_line_of_a_bad_header() {
+        let source = "ply\nformat ascii 1.0\nelement vertex 1\nproperty half x\nend_header\n";
+        assert_eq!(line_of(load_ply(temp_file("bad_header.ply", source), grey()).err().unwrap()), 4);
+    }
+
+    #[test]
+    fn file_without_faces_is_rejected() {
+        let source = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\n\
+                      element face 0\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n";
+        let path = temp_file("no_faces.ply", source);
+        let error = load_ply(&path, grey()).err().expect("an empty mesh has nothing to render");
+        assert!(matches!(error, LoadError::Format { .. }));
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{Ray, hitrecord::{Hitable, HitRecord}, aabb::AABB, util::random_f64};
+use std::{f64::consts::PI, sync::Arc};
+
+use nalgebra::{Point3, Vector2, Vector3};
+
+use crate::material::Material;
+
+// Parallelogram spanned by the edges `u` and `v` from `corner`. The front face, where the
+// normal u x v points, matters for one-sided emitters.
+pub struct Quad {
+    corner: Point3<f64>,
+    u: Vector3<f64>,
+    v: Vector3<f64>,
+    normal: Vector3<f64>,
+    // Dual basis: alpha = w.(p x v) and beta = w.(u x p) give the planar coordinates of p
+    w: Vector3<f64>,
+    area: f64,
+    material: Arc<dyn Material>,
+}
+
+impl Quad {
+    pub fn new(corner: Point3<f64>, u: Vector3<f64>, v: Vector3<f64>, material: Arc<dyn Material>) -> Self {
+        let n = u.cross(&v);
+        Quad {
+            corner,
+            u,
+            v,
+            normal: n.normalize(),
+            w: n / n.dot(&n),
+            area: n.magnitude(),
+            material,
+        }
+    }
+
+    // Rectangles get exact solid angle sampling, other parallelograms fall back to area sampling
+    fn is_rectangle(&self) -> bool {
+        self.u.dot(&self.v).abs() <= 1e-9 * self.u.magnitude() * self.v.magnitude()
+    }
+
+    fn intersect(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+        let denom = self.normal.dot(&ray.direction);
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let t = self.normal.dot(&(self.corner - ray.origin)) / denom;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+        let planar = ray.point_at_parameter(t) - self.corner;
+        let alpha = self.w.dot(&planar.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        Some((t, alpha, beta))
+    }
+}
+
+impl Hitable for Quad {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (t, alpha, beta) = self.intersect(ray, t_min, t_max)?;
+        Some(HitRecord {
+            t,
+            p: ray.point_at_parameter(t),
+            normal: self.normal,
+            material: Arc::clone(&self.material),
+            uv: Vector2::new(alpha, beta),
+            barycentric: None,
+            color: None,
+        })
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        let corners = [self.corner, self.corner + self.u, self.corner + self.v, self.corner + self.u + self.v];
+        let min = corners.iter().fold(corners[0], |a, c| a.inf(c));
+        let max = corners.iter().fold(corners[0], |a, c| a.sup(c));
+        // Axis aligned quads are flat along one axis
+        Some(AABB::new(min, max).pad(1e-4))
+    }
+
+    fn pdf_value(&self, origin: &Point3<f64>, direction: &Vector3<f64>) -> f64 {
+        let ray = Ray::new(*origin, *direction);
+        let Some((t, _, _)) = self.intersect(&ray, 0.001, f64::INFINITY) else {
+            return 0.0;
+        };
+        if self.is_rectangle() {
+            if let Some(rectangle) = SphericalRectangle::new(origin, &self.corner, &self.u, &self.v) {
+                return 1.0 / rectangle.solid_angle;
+            }
+        }
+        let distance_squared = t * t * direction.magnitude_squared();
+        let cosine = (self.normal.dot(direction) / direction.magnitude()).abs();
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: &Point3<f64>) -> Option<Vector3<f64>> {
+        let target = if self.is_rectangle() {
+            SphericalRectangle::new(origin, &self.corner, &self.u, &self.v)?.sample(random_f64(), random_f64())
+        } else {
+            self.corner + random_f64() * self.u + random_f64() * self.v
+        };
+        let direction = target - origin;
+        if direction.magnitude_squared() > 0.0 { Some(direction.normalize()) } else { None }
+    }
+
+    fn area(&self) -> f64 {
+        self.area
+    }
+
+    fn sample_surface(&self) -> Option<(Point3<f64>, Vector3<f64>)> {
+        Some((self.corner + random_f64() * self.u + random_f64() * self.v, self.normal))
+    }
+}
+
+// A rectangle projected onto the unit sphere around a viewpoint, for sampling directions
+// uniformly in solid angle (Urena, Fajardo and King 2013)
+pub struct SphericalRectangle {
+    origin: Point3<f64>,
+    // Local frame: x and y along the edges, z towards the rectangle's plane from behind
+    x: Vector3<f64>,
+    y: Vector3<f64>,
+    z: Vector3<f64>,
+    x0: f64,
+    x1: f64,
+    y0: f64,
+    y1: f64,
+    z0: f64,
+    b0: f64,
+    b1: f64,
+    // Sum of the two interior angles at the far edge, g2 + g3 in the paper
+    g23: f64,
+    pub solid_angle: f64,
+}
+
+// Angle between two unit vectors, accurate near 0 and pi
+fn angle_between(a: &Vector3<f64>, b: &Vector3<f64>) -> f64 {
+    if a.dot(b) < 0.0 {
+        PI - 2.0 * ((a + b).magnitude() / 2.0).clamp(-1.0, 1.0).asin()
+    } else {
+        2.0 * ((b - a).magnitude() / 2.0).clamp(-1.0, 1.0).asin()
+    }
+}
+
+impl SphericalRectangle {
+    // `u` and `v` must be perpendicular. None when the viewpoint lies in the rectangle's plane.
+    pub fn new(origin: &Point3<f64>, corner: &Point3<f64>, u: &Vector3<f64>, v: &Vector3<f64>) -> Option<Self> {
+        let (u_length, v_length) = (u.magnitude(), v.magnitude());
+        let x = u / u_length;
+        let y = v / v_length;
+        let mut z = x.cross(&y);
+        let d = corner - origin;
+        let (x0, y0, mut z0) = (d.dot(&x), d.dot(&y), d.dot(&z));
+        if z0 > 0.0 {
+            z = -z;
+            z0 = -z0;
+        }
+        if z0.abs() < 1e-9 {
+            return None;
+        }
+        let (x1, y1) = (x0 + u_length, y0 + v_length);
+
+        let v00 = Vector3::new(x0, y0, z0);
+        let v01 = Vector3::new(x0, y1, z0);
+        let v10 = Vector3::new(x1, y0, z0);
+        let v11 = Vector3::new(x1, y1, z0);
+        let n0 = v00.cross(&v10).normalize();
+        let n1 = v10.cross(&v11).normalize();
+        let n2 = v11.cross(&v01).normalize();
+        let n3 = v01.cross(&v00).normalize();
+        let g0 = angle_between(&-n0, &n1);
+        let g1 = angle_between(&-n1, &n2);
+        let g2 = angle_between(&-n2, &n3);
+        let g3 = angle_between(&-n3, &n0);
+        let solid_angle = g0 + g1 + g2 + g3 - 2.0 * PI;
+        if solid_angle.is_nan() || solid_angle <= 0.0 {
+            return None;
+        }
+        Some(SphericalRectangle {
+            origin: *origin,
+            x,
+            y,
+            z,
+            x0,
+            x1,
+            y0,
+            y1,
+            z0,
+            b0: n0.z,
+            b1: n2.z,
+            g23: g2 + g3,
+            solid_angle,
+        })
+    }
+
+    // Point on the rectangle for the uniform random numbers (s, t)
+    pub fn sample(&self, s: f64, t: f64) -> Point3<f64> {
+        // Pick the x coordinate by the solid angle to its left, then y along the chosen column
+        let au = s * self.solid_angle - self.g23;
+        let fu = (au.cos() * self.b0 - self.b1) / au.sin();
+        let cu = (fu.signum() / (fu * fu + self.b0 * self.b0).sqrt()).clamp(-1.0 + 1e-12, 1.0 - 1e-12);
+        let xu = (-(cu * self.z0) / (1.0 - cu * cu).max(0.0).sqrt()).clamp(self.x0, self.x1);
+        let dd = (xu * xu + self.z0 * self.z0).sqrt();
+        let h0 = self.y0 / (dd * dd + self.y0 * self.y0).sqrt();
+        let h1 = self.y1 / (dd * dd + self.y1 * self.y1).sqrt();
+        let hv = h0 + t * (h1 - h0);
+        let yv = if hv * hv < 1.0 - 1e-9 { hv * dd / (1.0 - hv * hv).sqrt() } else { self.y1 };
+        self.origin + self.x * xu + self.y * yv + self.z * self.z0
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use nalgebra::{Point3, Vector3};
+use rand::Rng;
+
+use crate::{
+    camera::Camera, cube::Cube, dielectric::Dielectric, hitrecord::Hitable, lambertian::Lambertian, metal::Metal,
+    renderer::RenderSettings, scene::Scene, sphere::Sphere, util::{random_f64, with_rng},
+};
+
+fn random_vector3(min: f64, max: f64) -> Vector3<f64> {
+    with_rng(|rng| Vector3::new(
+        rng.gen_range(min..max),
+        rng.gen_range(min..max),
+        rng.gen_range(min..max),
+    ))
+}
+// The random sphere field rendered when no scene file is given, with a glass cube in the middle
+pub fn random_scene() -> Scene {
+    let mut world: Vec<Arc<dyn Hitable>> = Vec::new();
+
+    // Ground
+    world.push(Arc::new(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Vector3::new(0.5, 0.5, 0.5))),
+    )));
+
+    //Random small spheres
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat: f64 = random_f64();
+            let center = Point3::new(
+                a as f64 + 0.9 * random_f64(),
+                0.2,
+                b as f64 + 0.9 * random_f64(),
+            );
+
+            if (center - Point3::new(4.0, 0.2, 0.0)).magnitude() > 0.9 {
+                if choose_mat < 0.8 {
+                    // Lambertian material
+                    let albedo = random_vector3(0.0, 1.0).component_mul(&random_vector3(0.0, 1.0));
+                    world.push(Arc::new(Sphere::new(
+                        center,
+                        0.2,
+                        Arc::new(Lambertian::new(albedo)),
+                    )));
+                } else if choose_mat < 0.95 {
+                    // Metal material
+                    let albedo = random_vector3(0.5, 1.0);
+                    let fuzz = 0.5 * random_f64();
+                    world.push(Arc::new(Sphere::new(
+                        center,
+                        0.2,
+                        Arc::new(Metal::new(albedo, fuzz)),
+                    )));
+                } else {
+                    // Dielectric material
+                    world.push(Arc::new(Sphere::new(
+                        center,
+                        0.2,
+                        Arc::new(Dielectric::new(1.5)),
+                    )));
+                }
+            }
+        }
+    }
+
+    // Large spheres
+    // world.push(Arc::new(Sphere::new(
+    //     Point3::new(0.0, 1.0, 0.0),
+    //     1.0,
+    //     Arc::new(Dielectric::new(1.5)),
+    // )));
+    let mini = Point3::new(0.0, 0.0, 0.0); // Lower left back corner
+    let maxi = Point3::new(2.0, 2.0, 4.0); // Upper right front corner
+    world.push(Arc::new(Cube::new(
+        mini,
+        maxi,
+        Arc::new(Dielectric::new(1.5)),
+    )));
+    world.push(Arc::new(Sphere::new(
+        Point3::new(-4.0, 1.0, 0.0),
+        1.0,
+        Arc::new(Lambertian::new(Vector3::new(0.1, 0.2, 0.1))),
+    )));
+    world.push(Arc::new(Sphere::new(Point3::new(4.0, 1.0, 0.0),
+    1.0,
+    Arc::new(Metal::new(Vector3::new(0.7, 0.6, 0.5), 0.0)),
+)));
+
+// let time0: f64 = 0.0; 
+// let time1: f64 = 1.0;
+
+//  let kdtree =  KdTree::new(&mut  world, time0, time1);
+// let t0 = 0.0;
+//     let t1 = 1.0;
+//     let axis = 0;
+//     let kdtree = KdTree::build(&mut world, t0, t1, axis);
+    //print!("{}", world.len());
+    //dbg!(world.len());
+    let lookfrom = Point3::new(12.0, 6.0, 12.0);
+    let lookat = Point3::new(0.0, 0.0, 0.0);
+    let vup = Vector3::new(0.0, 1.0, 0.0);
+    let dist_to_focus = 10.0;
+    let aperture = 0.09;
+    let camera = Camera::new(
+        lookfrom,
+        lookat,
+        vup,
+        20.0,
+        RenderSettings::default().aspect_ratio(),
+        aperture,
+        dist_to_focus,
+    );
+    Scene::new(camera, world)
+}
\ No newline at end of file
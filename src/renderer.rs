@@ -0,0 +1,353 @@
+use image::{ImageBuffer, Rgb, RgbImage};
+use nalgebra::Vector3;
+use rayon::prelude::*;
+
+use crate::{
+    bdpt::Bdpt,
+    camera::Camera,
+    hitrecord::Hitable,
+    integrator::{Integrator, Normals, PathTracer},
+    mlt::Metropolis,
+    photonmap::PhotonMapper,
+    scene::{Accelerator, Scene},
+    spectrum::{sample_wavelength, set_wavelength, spectral_to_rgb},
+    util::{random_f64, seed_rng},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IntegratorKind {
+    // Iterative path tracer with next-event estimation (PathTracer)
+    Path,
+    // Bidirectional path tracer, for caustics and lights that are hard to reach from the camera
+    Bdpt,
+    // Progressive photon mapping, for caustics on diffuse surfaces
+    Photon,
+    // Primary sample space Metropolis over the path tracer, for light through small openings
+    Mlt,
+    // Shading normals as colours, for checking geometry
+    Normals,
+}
+
+// How light sampling and BSDF sampling share the direct light reaching a surface
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MisHeuristic {
+    Balance,
+    // Balance with squared pdfs; favours whichever strategy is clearly better
+    Power,
+}
+
+impl MisHeuristic {
+    // Weight of a sample drawn with density `pdf` when `other_pdf` could also have produced it
+    pub fn weight(self, pdf: f64, other_pdf: f64) -> f64 {
+        let (a, b) = match self {
+            MisHeuristic::Balance => (pdf, other_pdf),
+            MisHeuristic::Power => (pdf * pdf, other_pdf * other_pdf),
+        };
+        if a + b > 0.0 {
+            a / (a + b)
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: u32,
+    pub max_depth: u32,
+    // Bounces before Russian roulette may end a path
+    pub roulette_depth: u32,
+    // The same seed and settings reproduce the same image
+    pub seed: u64,
+    pub accelerator: Accelerator,
+    pub integrator: IntegratorKind,
+    pub mis: MisHeuristic,
+    // Photon paths shot per pass when photon mapping
+    pub photons: u32,
+    // Photon gather radius of the first pass; None picks one from the size of a pixel
+    pub photon_radius: Option<f64>,
+    // Independent paths that estimate the image brightness and seed the Metropolis chains
+    pub bootstrap_samples: u32,
+    // Metropolis chains run side by side; all samples per pixel are split between them
+    pub chains: u32,
+    // Standard deviation of a Metropolis small step in primary sample space
+    pub mutation_size: f64,
+    // Chance of a Metropolis mutation being an independent new path instead of a small step
+    pub large_step_probability: f64,
+    // Worker threads; None uses the global rayon pool
+    pub threads: Option<usize>,
+    // Follow every path at a single wavelength, upsampling colours to spectra, and turn the
+    // result into RGB through the CIE matching functions; needed for dispersion
+    pub spectral: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            width: 400,
+            height: 225,
+            samples_per_pixel: 200,
+            max_depth: 50,
+            roulette_depth: 3,
+            seed: 0,
+            accelerator: Accelerator::Kd,
+            integrator: IntegratorKind::Path,
+            mis: MisHeuristic::Power,
+            photons: 100_000,
+            photon_radius: None,
+            bootstrap_samples: 100_000,
+            chains: 1000,
+            mutation_size: 0.01,
+            large_step_probability: 0.3,
+            threads: None,
+            spectral: false,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn aspect_ratio(&self) -> f64 {
+        self.width as f64 / self.height as f64
+    }
+
+    // Whether paths carry wavelengths; shading normals are not light, so stay in RGB
+    pub fn is_spectral(&self) -> bool {
+        self.spectral && self.integrator != IntegratorKind::Normals
+    }
+
+    // Change the width and/or height, deriving a missing one from the current aspect ratio
+    pub fn resize(&mut self, width: Option<u32>, height: Option<u32>) {
+        let aspect_ratio = self.aspect_ratio();
+        match (width, height) {
+            (Some(width), Some(height)) => {
+                self.width = width;
+                self.height = height;
+            }
+            (Some(width), None) => {
+                self.width = width;
+                self.height = (width as f64 / aspect_ratio).round() as u32;
+            }
+            (None, Some(height)) => {
+                self.width = (height as f64 * aspect_ratio).round() as u32;
+                self.height = height;
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+// Averaged linear radiance per pixel, stored row by row from the top of the image
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<Vector3<f64>>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Vector3::zeros(); (width * height) as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[Vector3<f64>] {
+        &self.pixels
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Vector3<f64> {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: Vector3<f64>) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    pub fn add(&mut self, x: u32, y: u32, color: Vector3<f64>) {
+        self.pixels[(y * self.width + x) as usize] += color;
+    }
+
+    // Pixel by pixel sum with a buffer of the same size
+    pub fn merge(mut self, other: Framebuffer) -> Framebuffer {
+        self.pixels.iter_mut().zip(other.pixels).for_each(|(p, q)| *p += q);
+        self
+    }
+
+    pub fn scaled(mut self, scale: f64) -> Framebuffer {
+        self.pixels.iter_mut().for_each(|p| *p *= scale);
+        self
+    }
+
+    // Gamma 2 encode and quantise to 8 bits per channel
+    pub fn to_rgb8(&self) -> RgbImage {
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let color = self.get(x, y);
+            let encode = |c: f64| (255.99 * c.sqrt().clamp(0.0, 0.999)) as u8;
+            Rgb([encode(color.x), encode(color.y), encode(color.z)])
+        })
+    }
+}
+
+pub struct Renderer {
+    settings: RenderSettings,
+}
+
+impl Renderer {
+    pub fn new(settings: RenderSettings) -> Self {
+        Renderer { settings }
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    pub fn render(&self, scene: &Scene) -> Framebuffer {
+        match self.settings.threads {
+            Some(threads) => rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .expect("failed to create render thread pool")
+                .install(|| self.render_pixels(scene)),
+            None => self.render_pixels(scene),
+        }
+    }
+
+    // The integrator for one pass over the image; only photon mapping renders more than one.
+    // `wavelength` and its density are what the whole pass is rendered at, if it has one.
+    pub fn integrator(
+        &self,
+        camera: &Camera,
+        world: &dyn Hitable,
+        scene: &Scene,
+        pass: u32,
+        wavelength: Option<(f64, f64)>,
+    ) -> Box<dyn Integrator> {
+        match self.settings.integrator {
+            IntegratorKind::Path => Box::new(PathTracer::new(&self.settings)),
+            IntegratorKind::Bdpt => Box::new(Bdpt::new(&self.settings, *camera)),
+            IntegratorKind::Photon => Box::new(PhotonMapper::new(&self.settings, camera, world, scene, pass, wavelength)),
+            // Metropolis drives the path tracer itself rather than rendering pixel by pixel
+            IntegratorKind::Mlt => Box::new(PathTracer::new(&self.settings)),
+            IntegratorKind::Normals => Box::new(Normals),
+        }
+    }
+
+    fn render_pixels(&self, scene: &Scene) -> Framebuffer {
+        let settings = &self.settings;
+        let camera = scene.camera.with_aspect_ratio(settings.aspect_ratio());
+        let world = scene.build_world(settings.accelerator);
+        if settings.integrator == IntegratorKind::Mlt {
+            return Metropolis::new(settings, camera).render(&*world, scene);
+        }
+
+        // Photon mapping shoots new photons for every sample per pixel, everything else takes
+        // all samples in one pass
+        let (passes, samples) = match settings.integrator {
+            IntegratorKind::Photon => (settings.samples_per_pixel, 1),
+            _ => (1, settings.samples_per_pixel),
+        };
+        let mut pixels = vec![Vector3::zeros(); (settings.width * settings.height) as usize];
+        for pass in 0..passes {
+            // The photons of a pass can only light camera paths of their own wavelength, so
+            // spectral photon mapping gives each pass one, spread evenly over the passes
+            let wavelength = (settings.is_spectral() && settings.integrator == IntegratorKind::Photon)
+                .then(|| sample_wavelength((pass as f64 + 0.5) / passes as f64));
+            let integrator = self.integrator(&camera, &*world, scene, pass, wavelength);
+            let seed = settings.seed ^ (pass as u64).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            let sums = self.render_pass(scene, &camera, &*world, &*integrator, samples, seed);
+            pixels.iter_mut().zip(sums).for_each(|(p, sum)| *p += sum);
+        }
+        let samples = settings.samples_per_pixel as f64;
+        Framebuffer {
+            width: settings.width,
+            height: settings.height,
+            pixels: pixels.into_iter().map(|p| p / samples).collect(),
+        }
+    }
+
+    // Sum of `samples` estimates for every pixel, plus the splats that landed on it. Spectral
+    // rendering draws a wavelength for each sample, unless the integrator fixes one for the pass.
+    fn render_pass(
+        &self,
+        scene: &Scene,
+        camera: &Camera,
+        world: &dyn Hitable,
+        integrator: &dyn Integrator,
+        samples: u32,
+        seed: u64,
+    ) -> Vec<Vector3<f64>> {
+        let (image_width, image_height) = (self.settings.width, self.settings.height);
+
+        // Each worker keeps its own buffer for splats, created on the first one it receives
+        let (mut rows, splats) = (0..image_height)
+            .into_par_iter()
+            .fold(
+                || (Vec::new(), None::<Framebuffer>),
+                |(mut rows, mut splat_buffer), row| {
+                    // Rows are stored top down, the camera's v axis points up
+                    let j = image_height - 1 - row;
+                    let mut splats = Vec::new();
+                    let pixels: Vec<Vector3<f64>> = (0..image_width)
+                        .map(|i| {
+                            seed_rng(seed ^ (j as u64 * image_width as u64 + i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+                            let mut pixel_color = Vector3::new(0.0, 0.0, 0.0);
+                            for _ in 0..samples {
+                                let u = (i as f64 + random_f64()) / (image_width - 1) as f64;
+                                let v = (j as f64 + random_f64()) / (image_height - 1) as f64;
+                                let ray = camera.get_ray(u, v);
+                                let wavelength = integrator
+                                    .wavelength()
+                                    .or_else(|| self.settings.is_spectral().then(|| sample_wavelength(random_f64())));
+                                set_wavelength(wavelength.map(|(lambda, _)| lambda));
+                                let radiance = integrator.radiance(&ray, world, scene, &mut splats);
+                                set_wavelength(None);
+                                let to_rgb = |radiance: Vector3<f64>| match wavelength {
+                                    Some((lambda, pdf)) => spectral_to_rgb(&radiance, lambda, pdf),
+                                    None => radiance,
+                                };
+                                pixel_color += to_rgb(radiance);
+                                if !splats.is_empty() {
+                                    let buffer = splat_buffer.get_or_insert_with(|| Framebuffer::new(image_width, image_height));
+                                    for splat in splats.drain(..) {
+                                        buffer.add(splat.x, splat.y, to_rgb(splat.radiance));
+                                    }
+                                }
+                            }
+                            pixel_color
+                        })
+                        .collect();
+                    rows.push((row, pixels));
+                    (rows, splat_buffer)
+                },
+            )
+            .reduce(
+                || (Vec::new(), None),
+                |(mut rows, a), (more_rows, b)| {
+                    rows.extend(more_rows);
+                    let splats = match (a, b) {
+                        (Some(a), Some(b)) => Some(a.merge(b)),
+                        (a, b) => a.or(b),
+                    };
+                    (rows, splats)
+                },
+            );
+        rows.sort_by_key(|(row, _)| *row);
+
+        let mut pixels: Vec<Vector3<f64>> = rows.into_iter().flat_map(|(_, pixels)| pixels).collect();
+        if let Some(splats) = splats {
+            pixels.iter_mut().zip(splats.pixels).for_each(|(p, splat)| *p += splat);
+        }
+        pixels
+    }
+}
@@ -0,0 +1,1087 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use nalgebra::{Matrix4, Point3, Rotation3, Vector3};
+use serde::Deserialize;
+
+use crate::{
+    aabb::AABB, background::Background, blinphong::BlinnPhong, bvhnode::BVHNode, camera::Camera, cone::Cone, cube::Cube,
+    cylinder::Cylinder, dielectric::{Dielectric, Dispersion}, diffuselight::DiffuseLight, disk::Disk, envmap::EnvironmentMap,
+    gltfimport::load_gltf, gridmedium::{GridEmission, GridMedium}, hitrecord::Hitable, instance::Instance, kdnode::KdNode,
+    lambertian::Lambertian, light::Light, loaderror::LoadError, material::Material, medium::{ConstantMedium, Fog},
+    metal::Metal, quad::Quad, obj::load_obj, phase::{HenyeyGreenstein, Isotropic}, ply::load_ply, renderer::RenderSettings,
+    sky::PhysicalSky, spectrum::{LAMBDA_MAX, LAMBDA_MIN}, sphere::Sphere, stl::load_stl, triangle::Triangle, util::orthonormal_basis,
+    voxelgrid::load_grid,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Accelerator {
+    Bvh,
+    Kd,
+    // Plain list, every object is tested against every ray
+    List,
+}
+
+pub struct Scene {
+    pub camera: Camera,
+    pub objects: Vec<Arc<dyn Hitable>>,
+    pub lights: Vec<Light>,
+    // What rays that escape the scene see
+    pub background: Background,
+    // Haze between the surfaces, if any
+    pub fog: Option<Fog>,
+}
+
+impl Scene {
+    pub fn new(camera: Camera, objects: Vec<Arc<dyn Hitable>>) -> Self {
+        Scene {
+            camera,
+            objects,
+            lights: Vec::new(),
+            background: Background::default(),
+            fog: None,
+        }
+    }
+
+    pub fn with_light(mut self, light: Light) -> Self {
+        self.lights.push(light);
+        self
+    }
+
+    pub fn with_background(mut self, background: Background) -> Self {
+        self.background = background;
+        self
+    }
+
+    pub fn with_fog(mut self, fog: Fog) -> Self {
+        self.fog = Some(fog);
+        self
+    }
+
+    // Put the objects into an acceleration structure for rendering, seen through the fog
+    pub fn build_world(&self, accelerator: Accelerator) -> Arc<dyn Hitable> {
+        let world: Arc<dyn Hitable> = if self.objects.is_empty() {
+            Arc::new(Vec::<Arc<dyn Hitable>>::new())
+        } else {
+            match accelerator {
+                Accelerator::Bvh => BVHNode::new(self.objects.clone(), 0.0, 0.0),
+                Accelerator::Kd => Arc::new(KdNode::from_objects(self.objects.clone())),
+                Accelerator::List => Arc::new(self.objects.clone()),
+            }
+        };
+        match &self.fog {
+            Some(fog) => Arc::new(fog.around(world)),
+            None => world,
+        }
+    }
+}
+
+// Layout of the TOML scene description
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    camera: CameraDesc,
+    #[serde(default)]
+    render: RenderDesc,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    lights: Vec<LightDesc>,
+    background: Option<BackgroundDesc>,
+    fog: Option<FogDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    look_from: [f64; 3],
+    look_at: [f64; 3],
+    #[serde(default = "default_vup")]
+    vup: [f64; 3],
+    #[serde(default = "default_vfov")]
+    vfov: f64,
+    #[serde(default)]
+    aperture: f64,
+    focus_distance: Option<f64>,
+}
+
+fn default_vup() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_vfov() -> f64 {
+    20.0
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RenderDesc {
+    width: Option<u32>,
+    height: Option<u32>,
+    aspect_ratio: Option<f64>,
+    samples_per_pixel: Option<u32>,
+    max_depth: Option<u32>,
+    roulette_depth: Option<u32>,
+    photons: Option<u32>,
+    photon_radius: Option<f64>,
+    bootstrap_samples: Option<u32>,
+    chains: Option<u32>,
+    mutation_size: Option<f64>,
+    large_step_probability: Option<f64>,
+    spectral: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: [f64; 3],
+    },
+    Metal {
+        albedo: [f64; 3],
+        #[serde(default)]
+        fuzz: f64,
+    },
+    // `tint` is the colour light keeps after `tint_distance` inside; the outside defaults to
+    // clear air, but can be another dielectric for interfaces such as liquid against glass.
+    // Either `ior` or `dispersion` gives the index of refraction.
+    Dielectric {
+        ior: Option<f64>,
+        dispersion: Option<DispersionDesc>,
+        #[serde(default = "default_color")]
+        tint: [f64; 3],
+        #[serde(default = "default_scale")]
+        tint_distance: f64,
+        #[serde(default)]
+        roughness: f64,
+        #[serde(default = "default_scale")]
+        outside_ior: f64,
+        #[serde(default = "default_color")]
+        outside_tint: [f64; 3],
+    },
+    DiffuseLight {
+        emit: [f64; 3],
+        #[serde(default = "default_two_sided")]
+        two_sided: bool,
+    },
+    // Preview shading from a fixed light direction, not physically based
+    BlinnPhong {
+        albedo: [f64; 3],
+        #[serde(default = "default_light_dir")]
+        light_dir: [f64; 3],
+        #[serde(default = "default_ambient")]
+        ambient: f64,
+        #[serde(default)]
+        specular: [f64; 3],
+        #[serde(default = "default_shininess")]
+        shininess: f64,
+    },
+}
+
+fn default_two_sided() -> bool {
+    true
+}
+
+fn default_light_dir() -> [f64; 3] {
+    [1.0, -1.0, 1.0]
+}
+
+fn default_ambient() -> f64 {
+    0.1
+}
+
+fn default_shininess() -> f64 {
+    32.0
+}
+
+fn default_elevation() -> f64 {
+    45.0
+}
+
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+fn default_ground() -> [f64; 3] {
+    [0.3, 0.3, 0.3]
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BackgroundDesc {
+    // Sun elevation and azimuth (clockwise from -z towards +x) in degrees
+    Sky {
+        #[serde(default = "default_elevation")]
+        elevation: f64,
+        #[serde(default)]
+        azimuth: f64,
+        #[serde(default = "default_turbidity")]
+        turbidity: f64,
+        #[serde(default = "default_ground")]
+        ground: [f64; 3],
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+    Solid {
+        color: [f64; 3],
+    },
+    // Equirectangular .hdr or .exr; rotation in degrees about the up axis
+    Environment {
+        path: PathBuf,
+        #[serde(default)]
+        rotation: f64,
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct TransformDesc {
+    #[serde(default)]
+    translate: [f64; 3],
+    // Euler angles in degrees, applied in X, Y, Z order
+    #[serde(default)]
+    rotate: [f64; 3],
+    scale: Option<ScaleDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleDesc {
+    Uniform(f64),
+    Axes([f64; 3]),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: String,
+        transform: Option<TransformDesc>,
+    },
+    Cube {
+        min: [f64; 3],
+        max: [f64; 3],
+        material: String,
+        transform: Option<TransformDesc>,
+    },
+    Cylinder {
+        base: [f64; 3],
+        height: f64,
+        radius: f64,
+        material: String,
+        transform: Option<TransformDesc>,
+    },
+    Cone {
+        apex: [f64; 3],
+        height: f64,
+        radius: f64,
+        material: String,
+        transform: Option<TransformDesc>,
+    },
+    Triangle {
+        vertices: [[f64; 3]; 3],
+        material: String,
+        transform: Option<TransformDesc>,
+    },
+    // Parallelogram from `corner` along the edges `u` and `v`, facing along u x v
+    Quad {
+        corner: [f64; 3],
+        u: [f64; 3],
+        v: [f64; 3],
+        material: String,
+        transform: Option<TransformDesc>,
+    },
+    Disk {
+        center: [f64; 3],
+        normal: [f64; 3],
+        radius: f64,
+        material: String,
+        transform: Option<TransformDesc>,
+    },
+    // .obj, .ply or .stl, chosen by extension; `material` is used where the file names none
+    Mesh {
+        path: PathBuf,
+        material: Option<String>,
+        transform: Option<TransformDesc>,
+    },
+    Gltf {
+        path: PathBuf,
+        material: Option<String>,
+        transform: Option<TransformDesc>,
+    },
+    // Smoke filling a sphere or box; the transform moves the boundary
+    Medium {
+        boundary: BoundaryDesc,
+        density: f64,
+        #[serde(default = "default_color")]
+        albedo: [f64; 3],
+        // Henyey-Greenstein asymmetry, 0 for isotropic scattering
+        #[serde(default)]
+        anisotropy: f64,
+        transform: Option<TransformDesc>,
+    },
+    // Density grid from a .vol file, scaled by `density`, filling the box recorded in the file
+    // unless `min` and `max` give another. An optional temperature grid over the same box
+    // makes it glow with black body colours.
+    Volume {
+        path: PathBuf,
+        #[serde(default = "default_scale")]
+        density: f64,
+        #[serde(default = "default_color")]
+        albedo: [f64; 3],
+        #[serde(default)]
+        anisotropy: f64,
+        min: Option<[f64; 3]>,
+        max: Option<[f64; 3]>,
+        temperature: Option<PathBuf>,
+        // Kelvin per grid unit, and kelvin added after scaling
+        #[serde(default = "default_scale")]
+        temperature_scale: f64,
+        #[serde(default)]
+        temperature_offset: f64,
+        #[serde(default = "default_intensity")]
+        emission: f64,
+    },
+}
+
+// Coefficients for wavelengths in micrometres, as glass catalogues give them
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum DispersionDesc {
+    Cauchy { a: f64, b: f64 },
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BoundaryDesc {
+    Sphere { center: [f64; 3], radius: f64 },
+    Cube { min: [f64; 3], max: [f64; 3] },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FogDesc {
+    density: f64,
+    #[serde(default = "default_color")]
+    albedo: [f64; 3],
+    #[serde(default)]
+    anisotropy: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum LightDesc {
+    Point {
+        position: [f64; 3],
+        #[serde(default = "default_color")]
+        color: [f64; 3],
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+    // Angles in degrees: half angle of the cone and the soft edge inside it
+    Spot {
+        position: [f64; 3],
+        look_at: [f64; 3],
+        #[serde(default = "default_color")]
+        color: [f64; 3],
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+        cone_angle: f64,
+        #[serde(default)]
+        falloff: f64,
+    },
+    // `direction` is the way the light travels, e.g. [0, -1, 0] straight down
+    Directional {
+        direction: [f64; 3],
+        #[serde(default = "default_color")]
+        color: [f64; 3],
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        #[serde(default = "default_color")]
+        color: [f64; 3],
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+}
+
+fn default_color() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn point(v: [f64; 3]) -> Point3<f64> {
+    Point3::new(v[0], v[1], v[2])
+}
+
+fn vector(v: [f64; 3]) -> Vector3<f64> {
+    Vector3::new(v[0], v[1], v[2])
+}
+
+impl TransformDesc {
+    fn matrix(&self) -> Matrix4<f64> {
+        let scale = match self.scale {
+            Some(ScaleDesc::Uniform(s)) => Vector3::new(s, s, s),
+            Some(ScaleDesc::Axes(s)) => vector(s),
+            None => Vector3::new(1.0, 1.0, 1.0),
+        };
+        let [rx, ry, rz] = self.rotate.map(f64::to_radians);
+        let rotation = Rotation3::from_axis_angle(&Vector3::z_axis(), rz)
+            * Rotation3::from_axis_angle(&Vector3::y_axis(), ry)
+            * Rotation3::from_axis_angle(&Vector3::x_axis(), rx);
+        Matrix4::new_translation(&vector(self.translate)) * rotation.to_homogeneous() * Matrix4::new_nonuniform_scaling(&scale)
+    }
+}
+
+struct Builder<'a> {
+    path: &'a Path,
+    directory: PathBuf,
+    aspect_ratio: f64,
+    materials: BTreeMap<String, Arc<dyn Material>>,
+    // Names of the diffuse_light materials
+    emissive: BTreeSet<String>,
+}
+
+impl<'a> Builder<'a> {
+    // Emissive quads and disks become area lights that are sampled directly. They sample their
+    // own shape, so their transforms are applied to the shape rather than through an instance.
+    fn is_area_light(&self, desc: &ObjectDesc) -> bool {
+        match desc {
+            ObjectDesc::Quad { material, .. } | ObjectDesc::Disk { material, .. } => self.emissive.contains(material),
+            _ => false,
+        }
+    }
+
+    fn material(&self, key: &str, name: &str) -> Result<Arc<dyn Material>, LoadError> {
+        self.materials
+            .get(name)
+            .cloned()
+            .ok_or_else(|| LoadError::format(self.path, format!("{}: unknown material '{}'", key, name)))
+    }
+
+    fn optional_material(&self, key: &str, name: &Option<String>) -> Result<Arc<dyn Material>, LoadError> {
+        match name {
+            Some(name) => self.material(key, name),
+            None => Ok(Arc::new(Lambertian::new(Vector3::new(0.5, 0.5, 0.5)))),
+        }
+    }
+
+    fn object(&self, key: &str, desc: &ObjectDesc) -> Result<Vec<Arc<dyn Hitable>>, LoadError> {
+        let material_key = format!("{}.material", key);
+        let (objects, transform): (Vec<Arc<dyn Hitable>>, &Option<TransformDesc>) = match desc {
+            ObjectDesc::Sphere { center, radius, material, transform } => (
+                vec![Arc::new(Sphere::new(point(*center), *radius, self.material(&material_key, material)?))],
+                transform,
+            ),
+            ObjectDesc::Cube { min, max, material, transform } => (
+                vec![Arc::new(Cube::new(point(*min), point(*max), self.material(&material_key, material)?))],
+                transform,
+            ),
+            ObjectDesc::Cylinder { base, height, radius, material, transform } => (
+                vec![Arc::new(Cylinder::new(point(*base), *height, *radius, self.material(&material_key, material)?))],
+                transform,
+            ),
+            ObjectDesc::Cone { apex, height, radius, material, transform } => (
+                vec![Arc::new(Cone::new(point(*apex), *height, *radius, self.material(&material_key, material)?))],
+                transform,
+            ),
+            ObjectDesc::Triangle { vertices, material, transform } => (
+                vec![Arc::new(Triangle::new(
+                    point(vertices[0]),
+                    point(vertices[1]),
+                    point(vertices[2]),
+                    self.material(&material_key, material)?,
+                ))],
+                transform,
+            ),
+            ObjectDesc::Quad { corner, u, v, material, transform } => {
+                if vector(*u).cross(&vector(*v)).magnitude() == 0.0 {
+                    return Err(LoadError::format(self.path, format!("{}.v: must not be parallel to u", key)));
+                }
+                let quad_material = self.material(&material_key, material)?;
+                if let (true, Some(matrix)) = (self.is_area_light(desc), self.matrix(key, transform)?) {
+                    let (corner, u, v) = transform_quad(&matrix, point(*corner), vector(*u), vector(*v));
+                    return Ok(vec![Arc::new(Quad::new(corner, u, v, quad_material))]);
+                }
+                (vec![Arc::new(Quad::new(point(*corner), vector(*u), vector(*v), quad_material))], transform)
+            }
+            ObjectDesc::Disk { center, normal, radius, material, transform } => {
+                if vector(*normal).magnitude() == 0.0 {
+                    return Err(LoadError::format(self.path, format!("{}.normal: must not be zero", key)));
+                }
+                if *radius <= 0.0 {
+                    return Err(LoadError::format(self.path, format!("{}.radius: must be greater than zero", key)));
+                }
+                let disk_material = self.material(&material_key, material)?;
+                if let (true, Some(matrix)) = (self.is_area_light(desc), self.matrix(key, transform)?) {
+                    let (center, normal, radius) = transform_disk(&matrix, point(*center), vector(*normal), *radius).ok_or_else(|| {
+                        LoadError::format(self.path, format!("{}.transform: a disk light can only be moved, rotated and scaled evenly", key))
+                    })?;
+                    return Ok(vec![Arc::new(Disk::new(center, normal, radius, disk_material))]);
+                }
+                (vec![Arc::new(Disk::new(point(*center), vector(*normal), *radius, disk_material))], transform)
+            }
+            ObjectDesc::Mesh { path, material, transform } => {
+                let file = self.directory.join(path);
+                let fallback = self.optional_material(&material_key, material)?;
+                let extension = file.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+                let objects: Vec<Arc<dyn Hitable>> = match extension.as_str() {
+                    "obj" => load_obj(&file, fallback)?.hitables(),
+                    "ply" => vec![Arc::new(load_ply(&file, fallback)?)],
+                    "stl" => vec![Arc::new(load_stl(&file, fallback)?)],
+                    _ => {
+                        return Err(LoadError::format(
+                            self.path,
+                            format!("{}.path: unsupported mesh format '{}'", key, file.display()),
+                        ))
+                    }
+                };
+                (objects, transform)
+            }
+            ObjectDesc::Gltf { path, material, transform } => {
+                let file = self.directory.join(path);
+                let fallback = self.optional_material(&material_key, material)?;
+                (load_gltf(&file, self.aspect_ratio, fallback)?.objects, transform)
+            }
+            ObjectDesc::Medium { boundary, density, albedo, anisotropy, transform } => {
+                let phase_function = phase_function(self.path, key, *density, *albedo, *anisotropy)?;
+                // The boundary is never shaded, only used to find where rays are inside it
+                let unseen: Arc<dyn Material> = Arc::new(Lambertian::new(Vector3::zeros()));
+                let boundary: Arc<dyn Hitable> = match boundary {
+                    BoundaryDesc::Sphere { center, radius } => Arc::new(Sphere::new(point(*center), *radius, unseen)),
+                    BoundaryDesc::Cube { min, max } => Arc::new(Cube::new(point(*min), point(*max), unseen)),
+                };
+                // Transformed in world space, so the density stays per unit of world distance
+                let boundary = self.transformed(key, vec![boundary], transform)?.remove(0);
+                return Ok(vec![Arc::new(ConstantMedium::new(boundary, *density, phase_function))]);
+            }
+            ObjectDesc::Volume {
+                path,
+                density,
+                albedo,
+                anisotropy,
+                min,
+                max,
+                temperature,
+                temperature_scale,
+                temperature_offset,
+                emission,
+            } => {
+                let phase_function = phase_function(self.path, key, *density, *albedo, *anisotropy)?;
+                let mut medium = GridMedium::new(load_grid(self.directory.join(path))?, *density, phase_function);
+                match (min, max) {
+                    (Some(min), Some(max)) => {
+                        if (0..3).any(|a| min[a] >= max[a]) {
+                            return Err(LoadError::format(self.path, format!("{}.max: must be greater than min", key)));
+                        }
+                        medium = medium.with_bounds(AABB::new(point(*min), point(*max)));
+                    }
+                    (None, None) => {}
+                    _ => return Err(LoadError::format(self.path, format!("{}: min and max must be given together", key))),
+                }
+                if let Some(temperature) = temperature {
+                    if *emission < 0.0 {
+                        return Err(LoadError::format(self.path, format!("{}.emission: must not be negative", key)));
+                    }
+                    let grid = load_grid(self.directory.join(temperature))?;
+                    let emission = GridEmission::new(grid, *temperature_scale, *temperature_offset, *emission);
+                    medium = medium.with_emission(vector(*albedo), emission);
+                }
+                return Ok(vec![Arc::new(medium)]);
+            }
+        };
+
+        self.transformed(key, objects, transform)
+    }
+
+    fn matrix(&self, key: &str, transform: &Option<TransformDesc>) -> Result<Option<Matrix4<f64>>, LoadError> {
+        let Some(transform) = transform else {
+            return Ok(None);
+        };
+        let matrix = transform.matrix();
+        if matrix.try_inverse().is_none() {
+            return Err(LoadError::format(self.path, format!("{}.transform: transform is not invertible", key)));
+        }
+        Ok(Some(matrix))
+    }
+
+    fn transformed(
+        &self,
+        key: &str,
+        objects: Vec<Arc<dyn Hitable>>,
+        transform: &Option<TransformDesc>,
+    ) -> Result<Vec<Arc<dyn Hitable>>, LoadError> {
+        Ok(match self.matrix(key, transform)? {
+            Some(matrix) => objects
+                .into_iter()
+                .map(|object| Arc::new(Instance::new(object, matrix)) as Arc<dyn Hitable>)
+                .collect(),
+            None => objects,
+        })
+    }
+}
+
+// Corner and edges of a quad moved by `matrix`. A mirroring transform would turn the quad's
+// front to the back, so the edges are swapped to keep it facing the way an instance would.
+fn transform_quad(matrix: &Matrix4<f64>, corner: Point3<f64>, u: Vector3<f64>, v: Vector3<f64>) -> (Point3<f64>, Vector3<f64>, Vector3<f64>) {
+    let (u, v) = (matrix.transform_vector(&u), matrix.transform_vector(&v));
+    let corner = matrix.transform_point(&corner);
+    if matrix.fixed_slice::<3, 3>(0, 0).determinant() < 0.0 {
+        (corner, v, u)
+    } else {
+        (corner, u, v)
+    }
+}
+
+// Centre, normal and radius of a disk moved by `matrix`, or None when the transform stretches
+// or shears it into an ellipse
+fn transform_disk(matrix: &Matrix4<f64>, center: Point3<f64>, normal: Vector3<f64>, radius: f64) -> Option<(Point3<f64>, Vector3<f64>, f64)> {
+    let (s, t) = orthonormal_basis(&normal.normalize());
+    let (s, t) = (matrix.transform_vector(&s), matrix.transform_vector(&t));
+    let (s_length, t_length) = (s.magnitude(), t.magnitude());
+    if (s_length - t_length).abs() > 1e-9 * s_length || s.dot(&t).abs() > 1e-9 * s_length * t_length {
+        return None;
+    }
+    let linear = matrix.fixed_slice::<3, 3>(0, 0).into_owned();
+    let normal = linear.try_inverse()?.transpose() * normal;
+    Some((matrix.transform_point(&center), normal, radius * s_length))
+}
+
+// Phase function of a medium of the given density, after checking its parameters
+fn phase_function(path: &Path, key: &str, density: f64, albedo: [f64; 3], anisotropy: f64) -> Result<Arc<dyn Material>, LoadError> {
+    if density <= 0.0 {
+        return Err(LoadError::format(path, format!("{}.density: must be greater than zero", key)));
+    }
+    if anisotropy <= -1.0 || anisotropy >= 1.0 {
+        return Err(LoadError::format(path, format!("{}.anisotropy: must be between -1 and 1", key)));
+    }
+    Ok(if anisotropy == 0.0 {
+        Arc::new(Isotropic::new(vector(albedo)))
+    } else {
+        Arc::new(HenyeyGreenstein::new(vector(albedo), anisotropy))
+    })
+}
+
+fn convert_material(path: &Path, key: &str, desc: &MaterialDesc) -> Result<Arc<dyn Material>, LoadError> {
+    Ok(match desc {
+        MaterialDesc::Lambertian { albedo } => Arc::new(Lambertian::new(vector(*albedo))),
+        MaterialDesc::Metal { albedo, fuzz } => Arc::new(Metal::new(vector(*albedo), *fuzz)),
+        MaterialDesc::Dielectric { ior, dispersion, tint, tint_distance, roughness, outside_ior, outside_tint } => {
+            let invalid = |field: &str, message: &str| LoadError::format(path, format!("{}.{}: {}", key, field, message));
+            let dispersion = match dispersion {
+                Some(DispersionDesc::Cauchy { a, b }) => Some(Dispersion::Cauchy { a: *a, b: *b }),
+                Some(DispersionDesc::Sellmeier { b, c }) => Some(Dispersion::Sellmeier { b: *b, c: *c }),
+                None => None,
+            };
+            let glass = match (ior, dispersion) {
+                (Some(_), Some(_)) => return Err(invalid("ior", "give either ior or dispersion, not both")),
+                (None, None) => return Err(invalid("ior", "missing, give either ior or dispersion")),
+                (Some(ior), None) if *ior <= 0.0 => return Err(invalid("ior", "must be greater than zero")),
+                (Some(ior), None) => Dielectric::new(*ior),
+                (None, Some(dispersion)) => {
+                    // Spectral rendering looks the index up anywhere in the visible range
+                    let mut visible = (LAMBDA_MIN as u32..=LAMBDA_MAX as u32).step_by(5).map(|lambda| dispersion.ior(lambda as f64));
+                    if visible.any(|n| !n.is_finite() || n <= 0.0) {
+                        return Err(invalid("dispersion", "must give a positive index of refraction from 360 to 830nm"));
+                    }
+                    Dielectric::new(1.0).with_dispersion(dispersion)
+                }
+            };
+            if *outside_ior <= 0.0 {
+                return Err(invalid("outside_ior", "must be greater than zero"));
+            }
+            if *tint_distance <= 0.0 {
+                return Err(invalid("tint_distance", "must be greater than zero"));
+            }
+            if !(0.0..=1.0).contains(roughness) {
+                return Err(invalid("roughness", "must be between 0 and 1"));
+            }
+            for (field, color) in [("tint", tint), ("outside_tint", outside_tint)] {
+                if color.iter().any(|c| *c <= 0.0 || *c > 1.0) {
+                    return Err(invalid(field, "components must be greater than 0 and at most 1"));
+                }
+            }
+            Arc::new(
+                glass
+                    .with_tint(vector(*tint), *tint_distance)
+                    .with_outside(*outside_ior, vector(*outside_tint), *tint_distance)
+                    .with_roughness(*roughness),
+            )
+        }
+        MaterialDesc::DiffuseLight { emit, two_sided: true } => Arc::new(DiffuseLight::new(vector(*emit))),
+        MaterialDesc::DiffuseLight { emit, two_sided: false } => Arc::new(DiffuseLight::new(vector(*emit)).one_sided()),
+        MaterialDesc::BlinnPhong { albedo, light_dir, ambient, specular, shininess } => Arc::new(
+            BlinnPhong::new(vector(*albedo), vector(*light_dir))
+                .with_ambient(*ambient)
+                .with_specular(vector(*specular), *shininess),
+        ),
+    })
+}
+
+fn convert_light(path: &Path, key: &str, desc: &LightDesc) -> Result<Light, LoadError> {
+    let invalid = |field: &str, message: &str| LoadError::format(path, format!("{}.{}: {}", key, field, message));
+    Ok(match desc {
+        LightDesc::Point { position, color, intensity } => Light::point(point(*position), vector(*color), *intensity),
+        LightDesc::Spot { position, look_at, color, intensity, cone_angle, falloff } => {
+            if position == look_at {
+                return Err(invalid("look_at", "must differ from position"));
+            }
+            if !(0.0..=180.0).contains(cone_angle) {
+                return Err(invalid("cone_angle", "must be between 0 and 180 degrees"));
+            }
+            if !(0.0..=*cone_angle).contains(falloff) {
+                return Err(invalid("falloff", "must be between 0 and cone_angle"));
+            }
+            Light::spot(
+                point(*position),
+                point(*look_at) - point(*position),
+                vector(*color),
+                *intensity,
+                *cone_angle,
+                *falloff,
+            )
+        }
+        LightDesc::Directional { direction, color, intensity } => {
+            if vector(*direction).magnitude() == 0.0 {
+                return Err(invalid("direction", "must not be zero"));
+            }
+            Light::directional(vector(*direction), vector(*color), *intensity)
+        }
+        LightDesc::Sphere { center, radius, color, intensity } => {
+            if *radius < 0.0 {
+                return Err(invalid("radius", "must not be negative"));
+            }
+            Light::sphere(point(*center), *radius, vector(*color), *intensity)
+        }
+    })
+}
+
+fn convert_background(path: &Path, directory: &Path, desc: &Option<BackgroundDesc>) -> Result<Background, LoadError> {
+    let invalid = |field: &str, message: &str| LoadError::format(path, format!("background.{}: {}", field, message));
+    Ok(match desc {
+        None => Background::default(),
+        Some(BackgroundDesc::Sky { elevation, azimuth, turbidity, ground, intensity }) => {
+            if !(0.0..=90.0).contains(elevation) {
+                return Err(invalid("elevation", "must be between 0 and 90 degrees"));
+            }
+            if !(2.0..=10.0).contains(turbidity) {
+                return Err(invalid("turbidity", "must be between 2 and 10"));
+            }
+            Background::Sky(Arc::new(
+                PhysicalSky::new(*elevation, *azimuth, *turbidity).with_ground(vector(*ground)).with_intensity(*intensity),
+            ))
+        }
+        Some(BackgroundDesc::Solid { color }) => Background::Solid(vector(*color)),
+        Some(BackgroundDesc::Environment { path, rotation, intensity }) => Background::Environment(Arc::new(
+            EnvironmentMap::load(directory.join(path))?.with_rotation(*rotation).with_intensity(*intensity),
+        )),
+    })
+}
+
+fn settings(path: &Path, desc: &RenderDesc) -> Result<RenderSettings, LoadError> {
+    let defaults = RenderSettings::default();
+    let invalid = |key: &str| LoadError::format(path, format!("render.{}: must be greater than zero", key));
+    let aspect_ratio = desc.aspect_ratio.unwrap_or(defaults.aspect_ratio());
+    if aspect_ratio <= 0.0 {
+        return Err(invalid("aspect_ratio"));
+    }
+    let (width, height) = match (desc.width, desc.height) {
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) => (w, (w as f64 / aspect_ratio).round() as u32),
+        (None, Some(h)) => ((h as f64 * aspect_ratio).round() as u32, h),
+        (None, None) => (defaults.width, (defaults.width as f64 / aspect_ratio).round() as u32),
+    };
+    if width == 0 {
+        return Err(invalid("width"));
+    }
+    if height == 0 {
+        return Err(invalid("height"));
+    }
+    let samples_per_pixel = desc.samples_per_pixel.unwrap_or(defaults.samples_per_pixel);
+    if samples_per_pixel == 0 {
+        return Err(invalid("samples_per_pixel"));
+    }
+    let photons = desc.photons.unwrap_or(defaults.photons);
+    if photons == 0 {
+        return Err(invalid("photons"));
+    }
+    if desc.photon_radius.is_some_and(|radius| !(radius > 0.0 && radius.is_finite())) {
+        return Err(invalid("photon_radius"));
+    }
+    let bootstrap_samples = desc.bootstrap_samples.unwrap_or(defaults.bootstrap_samples);
+    if bootstrap_samples == 0 {
+        return Err(invalid("bootstrap_samples"));
+    }
+    let chains = desc.chains.unwrap_or(defaults.chains);
+    if chains == 0 {
+        return Err(invalid("chains"));
+    }
+    let mutation_size = desc.mutation_size.unwrap_or(defaults.mutation_size);
+    if !(mutation_size > 0.0 && mutation_size.is_finite()) {
+        return Err(invalid("mutation_size"));
+    }
+    let large_step_probability = desc.large_step_probability.unwrap_or(defaults.large_step_probability);
+    if !(0.0..=1.0).contains(&large_step_probability) {
+        return Err(LoadError::format(path, "render.large_step_probability: must be between 0 and 1"));
+    }
+    Ok(RenderSettings {
+        width,
+        height,
+        samples_per_pixel,
+        max_depth: desc.max_depth.unwrap_or(defaults.max_depth),
+        roulette_depth: desc.roulette_depth.unwrap_or(defaults.roulette_depth),
+        photons,
+        photon_radius: desc.photon_radius.or(defaults.photon_radius),
+        bootstrap_samples,
+        chains,
+        mutation_size,
+        large_step_probability,
+        spectral: desc.spectral.unwrap_or(defaults.spectral),
+        ..defaults
+    })
+}
+
+// Parse a TOML scene description and build its camera, objects, lights and render settings.
+// Relative asset paths are resolved against the directory of the scene file.
+pub fn load_scene(path: impl AsRef<Path>) -> Result<(Scene, RenderSettings), LoadError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|e| LoadError::io(path, e))?;
+    let deserializer = toml::Deserializer::new(&source);
+    let file: SceneFile = serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let key = e.path().to_string();
+        let inner = e.into_inner();
+        let line = inner.span().map_or(0, |span| source[..span.start].matches('\n').count() + 1);
+        let message = inner.message().trim_end().to_string();
+        if key == "." {
+            LoadError::parse(path, line, message)
+        } else {
+            LoadError::parse(path, line, format!("{}: {}", key, message))
+        }
+    })?;
+
+    let settings = settings(path, &file.render)?;
+
+    let camera_desc = &file.camera;
+    let look_from = point(camera_desc.look_from);
+    let look_at = point(camera_desc.look_at);
+    if look_from == look_at {
+        return Err(LoadError::format(path, "camera.look_at: must differ from camera.look_from"));
+    }
+    let camera = Camera::new(
+        look_from,
+        look_at,
+        vector(camera_desc.vup),
+        camera_desc.vfov,
+        settings.aspect_ratio(),
+        camera_desc.aperture,
+        camera_desc.focus_distance.unwrap_or_else(|| (look_from - look_at).magnitude()),
+    );
+
+    let builder = Builder {
+        path,
+        directory: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        aspect_ratio: settings.aspect_ratio(),
+        materials: file
+            .materials
+            .iter()
+            .map(|(name, desc)| Ok((name.clone(), convert_material(path, &format!("materials.{}", name), desc)?)))
+            .collect::<Result<_, LoadError>>()?,
+        emissive: file
+            .materials
+            .iter()
+            .filter(|(_, desc)| matches!(desc, MaterialDesc::DiffuseLight { .. }))
+            .map(|(name, _)| name.clone())
+            .collect(),
+    };
+
+    let mut objects = Vec::new();
+    let mut lights = Vec::new();
+    for (index, desc) in file.objects.iter().enumerate() {
+        let built = builder.object(&format!("objects[{}]", index), desc)?;
+        // Area lights stay in the world as well, to block light from elsewhere; integrators look
+        // for lights first and only for surfaces in front of them, so they are not seen twice
+        if builder.is_area_light(desc) {
+            lights.extend(built.iter().cloned().map(Light::area));
+        }
+        objects.extend(built);
+    }
+    if objects.is_empty() && lights.is_empty() {
+        return Err(LoadError::format(path, "objects: scene contains no objects"));
+    }
+
+    for (index, desc) in file.lights.iter().enumerate() {
+        lights.push(convert_light(path, &format!("lights[{}]", index), desc)?);
+    }
+
+    let fog = match &file.fog {
+        Some(desc) => Some(Fog::new(desc.density, phase_function(path, "fog", desc.density, desc.albedo, desc.anisotropy)?)),
+        None => None,
+    };
+
+    Ok((
+        Scene {
+            camera,
+            objects,
+            lights,
+            background: convert_background(path, &builder.directory, &file.background)?,
+            fog,
+        },
+        settings,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ray::Ray,
+        util::{temp_file, visibility},
+    };
+
+    const SCENE: &str = r#"[camera]
+look_from = [0.0, 1.0, 5.0]
+look_at = [0.0, 0.0, 0.0]
+vfov = 40.0
+
+[render]
+width = 64
+aspect_ratio = 2.0
+
+[materials.clay]
+type = "lambertian"
+albedo = [0.7, 0.4, 0.3]
+
+[[objects]]
+type = "sphere"
+center = [0.0, 0.0, 0.0]
+radius = 1.0
+material = "clay"
+
+[[objects]]
+type = "mesh"
+path = "scene_triangle.obj"
+material = "clay"
+"#;
+
+    fn line_of(error: LoadError) -> usize {
+        match error {
+            LoadError::Parse { line, .. } => line,
+            other => panic!("expected a parse error, got {}", other),
+        }
+    }
+
+    // A 2 x 2 panel light at height 3, facing down, moved there by its transform, with `extra`
+    // appended to the file
+    fn panel_scene(name: &str, extra: &str) -> PathBuf {
+        let source = format!(
+            r#"{}
+[materials.lamp]
+type = "diffuse_light"
+emit = [4.0, 4.0, 4.0]
+
+[[objects]]
+type = "quad"
+corner = [-0.5, 0.0, -0.5]
+u = [1.0, 0.0, 0.0]
+v = [0.0, 0.0, 1.0]
+material = "lamp"
+transform = {{ translate = [0.0, 3.0, 0.0], scale = 2.0 }}
+{}"#,
+            SCENE, extra
+        );
+        temp_file(name, source)
+    }
+
+    #[test]
+    fn transformed_quad_lights_are_sampled_where_they_were_moved() {
+        let (scene, _) = load_scene(panel_scene("panel.toml", "")).unwrap();
+        assert_eq!(scene.lights.len(), 1);
+        let sample = scene.lights[0].sample(&Point3::origin()).unwrap();
+        assert!(sample.direction.y > 0.0 && sample.distance >= 3.0);
+        assert!(sample.distance < (3.0f64 * 3.0 + 2.0).sqrt() + 1e-9);
+        let down = Ray::new(Point3::new(0.9, 10.0, 0.9), Vector3::new(0.0, -1.0, 0.0));
+        let (t, _) = scene.lights[0].hit(&down, 0.001, f64::INFINITY).unwrap();
+        assert!((t - 7.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn panel_lights_cast_shadows() {
+        let (scene, _) = load_scene(panel_scene("shadowed.toml", "")).unwrap();
+        let world = scene.build_world(Accelerator::Bvh);
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        assert_eq!(visibility(&*world, &Point3::new(0.0, 2.0, 0.0), &up, 2.0), 0.0);
+        assert_eq!(visibility(&*world, &Point3::new(3.0, 2.0, 0.0), &up, 2.0), 1.0);
+    }
+
+    #[test]
+    fn stretched_disk_lights_are_rejected() {
+        let disk = r#"
+[[objects]]
+type = "disk"
+center = [0.0, 2.0, 0.0]
+normal = [0.0, -1.0, 0.0]
+radius = 0.5
+material = "lamp"
+transform = { scale = [2.0, 1.0, 1.0] }
+"#;
+        let error = load_scene(panel_scene("stretched.toml", disk)).err().unwrap();
+        assert!(error.to_string().contains("objects[3].transform"), "{}", error);
+
+        let turned = disk.replace("scale = [2.0, 1.0, 1.0]", "rotate = [90.0, 0.0, 0.0], scale = [2.0, 1.0, 2.0]");
+        let (scene, _) = load_scene(panel_scene("turned.toml", &turned)).unwrap();
+        assert_eq!(scene.lights.len(), 2);
+    }
+
+    #[test]
+    fn shipped_scenes_load() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+        for entry in fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "toml") {
+                if let Err(error) = load_scene(&path) {
+                    panic!("{}", error);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn loads_objects_with_assets_beside_the_scene() {
+        temp_file("scene_triangle.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n");
+        let (scene, settings) = load_scene(temp_file("good.toml", SCENE)).unwrap();
+        assert_eq!(scene.objects.len(), 2);
+        assert_eq!((settings.width, settings.height), (64, 32));
+    }
+
+    #[test]
+    fn reports_the_line_of_a_mistyped_value() {
+        let path = temp_file("mistyped.toml", SCENE.replace("width = 64", "width = \"wide\""));
+        let error = load_scene(path).err().unwrap();
+        assert!(error.to_string().contains("render.width"), "{}", error);
+        assert_eq!(line_of(error), 7);
+
+        // Objects are told apart by their type, so their fields are reported at the table
+        let path = temp_file("mistyped_object.toml", SCENE.replace("radius = 1.0", "radius = \"big\""));
+        assert_eq!(line_of(load_scene(path).err().unwrap()), 14);
+    }
+}
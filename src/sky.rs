@@ -0,0 +1,198 @@
+use std::f64::consts::PI;
+
+use nalgebra::{Unit, Vector3};
+
+use crate::{envmap::EnvironmentMap, light::LightSample, util::{orthonormal_basis, random_f64}};
+
+// Sky radiance comes out in kcd/m^2; scale it so a white floor in full sun renders near 1
+const SKY_UNIT: f64 = 0.025;
+// Luminance of the sun above the atmosphere, in kcd/m^2
+const SUN_LUMINANCE: f64 = 2.0e6;
+// Angular radius of the sun disk as seen from the earth
+const SUN_RADIUS_DEGREES: f64 = 0.2665;
+// Wavelengths in micrometres the sun's transmittance is evaluated at for red, green and blue
+const WAVELENGTHS: [f64; 3] = [0.65, 0.57, 0.475];
+// Resolution of the table the sky is importance sampled from
+const TABLE_WIDTH: usize = 128;
+const TABLE_HEIGHT: usize = 64;
+
+// Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight" (1999): a clear sky
+// whose colour follows the sun's position and the haziness (turbidity) of the air, plus the
+// sun disk itself. +y is up; below the horizon the sky is reflected by a uniform ground.
+pub struct PhysicalSky {
+    sun_direction: Unit<Vector3<f64>>,
+    // Perez coefficients A to E for luminance Y and chromaticities x and y
+    perez: [[f64; 5]; 3],
+    // Yxy at the zenith, divided by the Perez function there so radiance() needs one division less
+    zenith: [f64; 3],
+    sun_radiance: Vector3<f64>,
+    cos_sun_radius: f64,
+    ground: Vector3<f64>,
+    intensity: f64,
+    // The sky without the sun, tabulated only to decide where to send light samples
+    table: EnvironmentMap,
+}
+
+impl Default for PhysicalSky {
+    fn default() -> Self {
+        PhysicalSky::new(45.0, 0.0, 3.0)
+    }
+}
+
+impl PhysicalSky {
+    // Sun elevation above the horizon and azimuth clockwise from -z towards +x, in degrees.
+    // Turbidity runs from about 2 (very clear) to 10 (hazy).
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64) -> Self {
+        let (elevation, azimuth) = (elevation.clamp(0.0, 90.0).to_radians(), azimuth.to_radians());
+        let sun_direction = Unit::new_normalize(Vector3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        ));
+        let t = turbidity;
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        let theta_s = PI / 2.0 - elevation;
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |c: [f64; 4]| c[0] * theta_s.powi(3) + c[1] * theta_s.powi(2) + c[2] * theta_s + c[3];
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+        let zenith = [zenith_luminance, zenith_x, zenith_y];
+
+        let mut sky = PhysicalSky {
+            sun_direction,
+            perez,
+            zenith: std::array::from_fn(|i| zenith[i] / perez_function(&perez[i], 0.0, theta_s)),
+            sun_radiance: sun_radiance(theta_s, turbidity) * SKY_UNIT,
+            cos_sun_radius: SUN_RADIUS_DEGREES.to_radians().cos(),
+            ground: Vector3::new(0.3, 0.3, 0.3),
+            intensity: 1.0,
+            table: EnvironmentMap::new(1, 1, vec![Vector3::zeros()]),
+        };
+        sky.table = EnvironmentMap::from_fn(TABLE_WIDTH, TABLE_HEIGHT, |direction| sky.sky_radiance(direction));
+        sky
+    }
+
+    // Albedo of the ground that reflects the sky below the horizon
+    pub fn with_ground(mut self, ground: Vector3<f64>) -> Self {
+        self.ground = ground;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn sun_direction(&self) -> Unit<Vector3<f64>> {
+        self.sun_direction
+    }
+
+    // Sky radiance without the sun disk or intensity
+    fn sky_radiance(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        let d = direction.normalize();
+        let (d, ground) = if d.y < 0.0 {
+            (Vector3::new(d.x, -d.y, d.z), self.ground)
+        } else {
+            (d, Vector3::new(1.0, 1.0, 1.0))
+        };
+        // Keep cos(theta) away from zero, where the Perez function blows up at the horizon
+        let theta = d.y.max(0.01).acos();
+        let gamma = d.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+        let luminance = self.zenith[0] * perez_function(&self.perez[0], theta, gamma);
+        let x = self.zenith[1] * perez_function(&self.perez[1], theta, gamma);
+        let y = self.zenith[2] * perez_function(&self.perez[2], theta, gamma);
+        xyy_to_rgb(x, y, luminance).component_mul(&ground) * SKY_UNIT
+    }
+
+    fn in_sun(&self, direction: &Vector3<f64>) -> bool {
+        let d = direction.normalize();
+        d.y >= 0.0 && d.dot(&self.sun_direction) >= self.cos_sun_radius
+    }
+
+    pub fn radiance(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        let mut radiance = self.sky_radiance(direction);
+        if self.in_sun(direction) {
+            radiance += self.sun_radiance;
+        }
+        radiance * self.intensity
+    }
+
+    fn sun_pdf(&self, direction: &Vector3<f64>) -> f64 {
+        if direction.normalize().dot(&self.sun_direction) >= self.cos_sun_radius {
+            1.0 / (2.0 * PI * (1.0 - self.cos_sun_radius))
+        } else {
+            0.0
+        }
+    }
+
+    // One direction on the sun disk and one following the brightness of the sky. Drawing both
+    // every time, rather than choosing between them, keeps the sun from showing up as noise.
+    pub fn sample(&self) -> Vec<LightSample> {
+        let cos_theta = 1.0 - random_f64() * (1.0 - self.cos_sun_radius);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random_f64();
+        let (s, t) = orthonormal_basis(&self.sun_direction);
+        let sun = (s * phi.cos() + t * phi.sin()) * sin_theta + self.sun_direction.into_inner() * cos_theta;
+        let sky = self.table.sample().map(|sample| sample.direction);
+        std::iter::once(sun)
+            .chain(sky)
+            .map(|direction| LightSample {
+                direction,
+                distance: f64::INFINITY,
+                radiance: self.radiance(&direction),
+                pdf: self.pdf(&direction),
+            })
+            .collect()
+    }
+
+    // Combined density of the two samples drawn by sample()
+    pub fn pdf(&self, direction: &Vector3<f64>) -> f64 {
+        self.sun_pdf(direction) + self.table.pdf(direction)
+    }
+}
+
+// Relative sky brightness at zenith angle theta and angle gamma from the sun
+fn perez_function(c: &[f64; 5], theta: f64, gamma: f64) -> f64 {
+    (1.0 + c[0] * (c[1] / theta.cos()).exp()) * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+}
+
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Vector3<f64> {
+    if y <= 0.0 {
+        return Vector3::zeros();
+    }
+    let xyz = Vector3::new(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+    let rgb = Vector3::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    );
+    rgb.map(|c| c.max(0.0))
+}
+
+// Sunlight after Rayleigh and aerosol scattering along the path through the atmosphere,
+// following the appendix of Preetham et al. with ozone and water vapour left out
+fn sun_radiance(theta_s: f64, turbidity: f64) -> Vector3<f64> {
+    // Relative optical air mass (Kasten), which stays finite with the sun on the horizon
+    let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let transmittance = |lambda: f64| {
+        let rayleigh = 0.008735 * lambda.powf(-4.08);
+        let aerosol = beta * lambda.powf(-1.3);
+        (-(rayleigh + aerosol) * air_mass).exp()
+    };
+    Vector3::new(
+        transmittance(WAVELENGTHS[0]),
+        transmittance(WAVELENGTHS[1]),
+        transmittance(WAVELENGTHS[2]),
+    ) * SUN_LUMINANCE
+}
@@ -0,0 +1,190 @@
+use std::cell::Cell;
+use std::sync::OnceLock;
+
+use nalgebra::{Matrix3, Vector3};
+
+// Range and spacing of the wavelengths spectra are integrated over, in nanometres
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+const LAMBDA_STEP: f64 = 5.0;
+
+// Piecewise Gaussian with different widths either side of the mean
+fn lobe(lambda: f64, mean: f64, below: f64, above: f64) -> f64 {
+    let width = if lambda < mean { below } else { above };
+    let x = (lambda - mean) / width;
+    (-0.5 * x * x).exp()
+}
+
+// CIE 1931 colour matching functions at `lambda` nanometres, from the multi-lobe fit of
+// Wyman, Sloan and Shirley (2013)
+pub fn cie_xyz(lambda: f64) -> Vector3<f64> {
+    Vector3::new(
+        1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8),
+    )
+}
+
+// Linear sRGB of a CIE XYZ colour
+pub fn xyz_to_rgb(xyz: &Vector3<f64>) -> Vector3<f64> {
+    let m = Matrix3::new(
+        3.2404542, -1.5371385, -0.4985314,
+        -0.9692660, 1.8760108, 0.0415560,
+        0.0556434, -0.2040259, 1.0572252,
+    );
+    m * xyz
+}
+
+// XYZ of the spectrum `s`, scaled so that a constant spectrum of 1 has Y = 1
+pub fn spectrum_to_xyz(s: impl Fn(f64) -> f64) -> Vector3<f64> {
+    let mut xyz = Vector3::zeros();
+    let mut y_integral = 0.0;
+    let mut lambda = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX {
+        let cmf = cie_xyz(lambda);
+        xyz += cmf * s(lambda);
+        y_integral += cmf.y;
+        lambda += LAMBDA_STEP;
+    }
+    xyz / y_integral
+}
+
+// Planck's law: spectral radiance of a black body at `temperature` kelvin, at `lambda` nanometres
+pub fn blackbody(lambda: f64, temperature: f64) -> f64 {
+    if temperature <= 0.0 {
+        return 0.0;
+    }
+    const C: f64 = 299_792_458.0;
+    const H: f64 = 6.626_070_15e-34;
+    const KB: f64 = 1.380_649e-23;
+    let l = lambda * 1e-9;
+    2.0 * H * C * C / (l.powi(5) * ((H * C / (l * KB * temperature)).exp() - 1.0))
+}
+
+// Black body spectrum scaled to a peak of 1 as in pbrt, so it stays in a usable range across
+// temperatures
+pub fn blackbody_normalized(lambda: f64, temperature: f64) -> f64 {
+    if temperature <= 0.0 {
+        return 0.0;
+    }
+    // Wien's displacement law gives the wavelength of the peak
+    let peak = blackbody(2.897_771_955e6 / temperature, temperature);
+    blackbody(lambda, temperature) / peak
+}
+
+// Linear sRGB colour of a black body at `temperature` kelvin, from its normalized spectrum: dull
+// red around 1000K, through orange and white to blue above 6500K
+pub fn blackbody_rgb(temperature: f64) -> Vector3<f64> {
+    if temperature <= 0.0 {
+        return Vector3::zeros();
+    }
+    xyz_to_rgb(&spectrum_to_xyz(|lambda| blackbody_normalized(lambda, temperature))).map(|c| c.max(0.0))
+}
+
+// Smits (1999) spectra for turning RGB reflectances into spectra, in ten even bins from 380 to
+// 720 nanometres: white and the six primaries and secondaries a colour is made up from
+const SMITS_MIN: f64 = 380.0;
+const SMITS_MAX: f64 = 720.0;
+const SMITS_WHITE: [f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+// Value of a Smits spectrum at `lambda`, linear between bin centres and flat beyond the ends
+fn smits(spectrum: &[f64; 10], lambda: f64) -> f64 {
+    let width = (SMITS_MAX - SMITS_MIN) / 10.0;
+    let x = ((lambda - SMITS_MIN) / width - 0.5).clamp(0.0, 9.0);
+    let i = (x as usize).min(8);
+    let f = x - i as f64;
+    spectrum[i] * (1.0 - f) + spectrum[i + 1] * f
+}
+
+// Value at `lambda` of a smooth spectrum with roughly the colour `rgb`: white scaled by the
+// smallest component, then the secondary and primary that make up the rest (Smits 1999).
+// Scaling the colour scales the spectrum, so it works as well for light as for reflectances.
+pub fn rgb_to_spectrum(rgb: &Vector3<f64>, lambda: f64) -> f64 {
+    let (r, g, b) = (rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0));
+    let s = |spectrum: &[f64; 10]| smits(spectrum, lambda);
+    if r <= g && r <= b {
+        r * s(&SMITS_WHITE)
+            + if g <= b {
+                (g - r) * s(&SMITS_CYAN) + (b - g) * s(&SMITS_BLUE)
+            } else {
+                (b - r) * s(&SMITS_CYAN) + (g - b) * s(&SMITS_GREEN)
+            }
+    } else if g <= r && g <= b {
+        g * s(&SMITS_WHITE)
+            + if r <= b {
+                (r - g) * s(&SMITS_MAGENTA) + (b - r) * s(&SMITS_BLUE)
+            } else {
+                (b - g) * s(&SMITS_MAGENTA) + (r - b) * s(&SMITS_RED)
+            }
+    } else {
+        b * s(&SMITS_WHITE)
+            + if r <= g {
+                (r - b) * s(&SMITS_YELLOW) + (g - r) * s(&SMITS_GREEN)
+            } else {
+                (g - b) * s(&SMITS_YELLOW) + (r - g) * s(&SMITS_RED)
+            }
+    }
+}
+
+thread_local! {
+    // Wavelength the path this thread is tracing carries, in spectral rendering
+    static WAVELENGTH: Cell<Option<f64>> = const { Cell::new(None) };
+}
+
+// Make the paths this thread traces carry light of `lambda` nanometres, or RGB again for None
+pub fn set_wavelength(lambda: Option<f64>) {
+    WAVELENGTH.with(|wavelength| wavelength.set(lambda));
+}
+
+pub fn wavelength() -> Option<f64> {
+    WAVELENGTH.with(|wavelength| wavelength.get())
+}
+
+// A colour as it applies to the current path: unchanged when rendering in RGB, and the value of
+// its spectrum at the path's wavelength in all three channels when rendering spectrally. Colours
+// pass through here where they enter a path, so integrators work the same in both modes.
+pub fn upsample(rgb: Vector3<f64>) -> Vector3<f64> {
+    match wavelength() {
+        Some(lambda) => Vector3::repeat(rgb_to_spectrum(&rgb, lambda)),
+        None => rgb,
+    }
+}
+
+// Draw a wavelength from `u`, with density roughly following the eye's sensitivity so little
+// time goes into wavelengths that hardly show (pbrt's visible wavelength sampling). Returns the
+// wavelength and its density per nanometre.
+pub fn sample_wavelength(u: f64) -> (f64, f64) {
+    let lambda = (538.0 - 138.888889 * (0.85691062 - 1.82750197 * u).atanh()).clamp(LAMBDA_MIN, LAMBDA_MAX);
+    let pdf = 0.0039398042 / (0.0072 * (lambda - 538.0)).cosh().powi(2);
+    (lambda, pdf)
+}
+
+// Integral of the Y matching function, and the sRGB colour of a constant spectrum, which is
+// balanced back to white so that neutral colours stay neutral
+fn white_balance() -> &'static (f64, Vector3<f64>) {
+    static WHITE: OnceLock<(f64, Vector3<f64>)> = OnceLock::new();
+    WHITE.get_or_init(|| {
+        let mut y_integral = 0.0;
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            y_integral += cie_xyz(lambda).y * LAMBDA_STEP;
+            lambda += LAMBDA_STEP;
+        }
+        (y_integral, xyz_to_rgb(&spectrum_to_xyz(|_| 1.0)))
+    })
+}
+
+// Linear sRGB estimate of a pixel from radiance carried at `lambda`, drawn with density `pdf`;
+// all of its channels hold the same value in spectral rendering
+pub fn spectral_to_rgb(radiance: &Vector3<f64>, lambda: f64, pdf: f64) -> Vector3<f64> {
+    let (y_integral, white) = white_balance();
+    let value = radiance.mean();
+    xyz_to_rgb(&(cie_xyz(lambda) * value / (pdf * y_integral))).component_div(white)
+}
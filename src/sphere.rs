@@ -1,7 +1,7 @@
 use crate::{Ray, hitrecord::{Hitable, HitRecord}, aabb::AABB};
 use std::{sync::Arc};
 
-use nalgebra::{Point3, Vector3};
+use nalgebra::{Point3, Vector2, Vector3};
 
 use crate::material::Material;
 
@@ -30,7 +30,9 @@ impl Hitable for Sphere {
     let c = oc.dot(&oc) - self.radius * self.radius;
     let discriminant = b * b - a * c;
     if discriminant > 0.0 {
-        let t = (-b - discriminant.sqrt()) / a;
+        // The far root is where rays starting inside the sphere leave it
+        let near = (-b - discriminant.sqrt()) / a;
+        let t = if near > t_min { near } else { (-b + discriminant.sqrt()) / a };
         if t < t_max && t > t_min {
             let p = ray.point_at_parameter(t);
             let normal = (p - self.center) / self.radius;
@@ -39,6 +41,9 @@ impl Hitable for Sphere {
                 p,
                 normal,
                 material: Arc::clone(&self.material),
+                uv: Vector2::zeros(),
+                barycentric: None,
+                color: None,
             });
         }
     }
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use nalgebra::Point3;
+
+use crate::{loaderror::LoadError, material::Material, mesh::TriangleMesh};
+
+const HEADER_SIZE: usize = 80;
+const TRIANGLE_SIZE: usize = 50;
+
+fn read_f32(bytes: &[u8], offset: usize) -> f32 {
+    f32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+// Loads a binary STL file. Facets are flat shaded from their winding, and
+// coincident corners are welded so the mesh shares vertices like the other importers.
+pub fn load_stl(path: impl AsRef<Path>, material: Arc<dyn Material>) -> Result<TriangleMesh, LoadError> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|e| LoadError::io(path, e))?;
+    if bytes.len() < HEADER_SIZE + 4 {
+        return Err(LoadError::format(path, "file is too short to be a binary STL"));
+    }
+
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    let expected = HEADER_SIZE + 4 + count * TRIANGLE_SIZE;
+    if bytes.len() != expected {
+        let message = if bytes.starts_with(b"solid") {
+            "ASCII STL is not supported, export as binary STL".to_string()
+        } else {
+            format!("header declares {} triangles ({} bytes) but file has {} bytes", count, expected, bytes.len())
+        };
+        return Err(LoadError::format(path, message));
+    }
+    if count == 0 {
+        return Err(LoadError::format(path, "file has no triangles"));
+    }
+
+    let mut positions = Vec::new();
+    let mut lookup: HashMap<[u32; 3], usize> = HashMap::new();
+    let mut indices = Vec::with_capacity(count);
+    for triangle in 0..count {
+        // Skip the stored facet normal, it is frequently wrong in exported files
+        let base = HEADER_SIZE + 4 + triangle * TRIANGLE_SIZE + 12;
+        let mut corners = [0; 3];
+        for (corner, index) in corners.iter_mut().enumerate() {
+            let offset = base + corner * 12;
+            let xyz = [read_f32(&bytes, offset), read_f32(&bytes, offset + 4), read_f32(&bytes, offset + 8)];
+            if xyz.iter().any(|c| !c.is_finite()) {
+                return Err(LoadError::format(path, format!("triangle {} has a non-finite vertex", triangle)));
+            }
+            // Adding 0.0 folds -0.0 into 0.0 so both weld together
+            let key = xyz.map(|c| (c + 0.0).to_bits());
+            *index = *lookup.entry(key).or_insert_with(|| {
+                positions.push(Point3::new(xyz[0] as f64, xyz[1] as f64, xyz[2] as f64));
+                positions.len() - 1
+            });
+        }
+        indices.push(corners);
+    }
+
+    Ok(TriangleMesh::new(positions, Vec::new(), Vec::new(), indices, material))
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::*;
+    use crate::{lambertian::Lambertian, util::temp_file};
+
+    fn grey() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Vector3::new(0.5, 0.5, 0.5)))
+    }
+
+    fn stl(triangles: &[[[f32; 3]; 3]]) -> Vec<u8> {
+        let mut bytes = vec![0u8; HEADER_SIZE];
+        bytes.extend_from_slice(&(triangles.len() as u32).to_le_bytes());
+        for triangle in triangles {
+            bytes.extend_from_slice(&[0u8; 12]);
+            for value in triangle.iter().flatten() {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0u8; 2]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn welds_shared_corners() {
+        let square = [
+            [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]],
+            [[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+        ];
+        let mesh = load_stl(temp_file("square.stl", stl(&square)), grey()).unwrap();
+        assert_eq!((mesh.vertex_count(), mesh.triangle_count()), (4, 2));
+    }
+
+    #[test]
+    fn truncated_file_is_rejected() {
+        let mut bytes = stl(&[[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]]]);
+        bytes.truncate(bytes.len() - 1);
+        let error = load_stl(temp_file("truncated.stl", bytes), grey()).err().unwrap();
+        assert!(error.to_string().contains("declares 1 triangles"));
+    }
+
+    #[test]
+    fn file_without_triangles_is_rejected() {
+        let path = temp_file("empty.stl", stl(&[]));
+        let error = load_stl(&path, grey()).err().expect("an empty mesh has nothing to render");
+        assert!(matches!(error, LoadError::Format { .. }));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{Ray, hitrecord::{Hitable, HitRecord}, aabb::AABB};
+use std::sync::Arc;
+
+use nalgebra::{Point3, Vector2, Vector3};
+
+use crate::material::Material;
+
+// Watertight ray/triangle intersection (Woop, Benthin and Wald 2013).
+// Returns the ray parameter and the barycentric weights (b0, b1, b2) of the three vertices.
+pub fn intersect_triangle(
+    ray: &Ray,
+    p0: &Point3<f64>,
+    p1: &Point3<f64>,
+    p2: &Point3<f64>,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64, f64, f64)> {
+    // Permute axes so that the dominant ray direction becomes z
+    let d = ray.direction;
+    let kz = d.iamax();
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    if d[kz] < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    // Shear so the ray points along +z
+    let sx = d[kx] / d[kz];
+    let sy = d[ky] / d[kz];
+    let sz = 1.0 / d[kz];
+
+    let a = p0 - ray.origin;
+    let b = p1 - ray.origin;
+    let c = p2 - ray.origin;
+
+    let ax = a[kx] - sx * a[kz];
+    let ay = a[ky] - sy * a[kz];
+    let bx = b[kx] - sx * b[kz];
+    let by = b[ky] - sy * b[kz];
+    let cx = c[kx] - sx * c[kz];
+    let cy = c[ky] - sy * c[kz];
+
+    // Scaled barycentrics as 2D edge functions
+    let u = cx * by - cy * bx;
+    let v = ax * cy - ay * cx;
+    let w = bx * ay - by * ax;
+
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+
+    let az = sz * a[kz];
+    let bz = sz * b[kz];
+    let cz = sz * c[kz];
+    let t = (u * az + v * bz + w * cz) / det;
+    if t <= t_min || t >= t_max {
+        return None;
+    }
+
+    Some((t, u / det, v / det, w / det))
+}
+
+pub fn triangle_bounds(p0: &Point3<f64>, p1: &Point3<f64>, p2: &Point3<f64>) -> AABB {
+    let min = Point3::new(
+        p0.x.min(p1.x).min(p2.x),
+        p0.y.min(p1.y).min(p2.y),
+        p0.z.min(p1.z).min(p2.z),
+    );
+    let max = Point3::new(
+        p0.x.max(p1.x).max(p2.x),
+        p0.y.max(p1.y).max(p2.y),
+        p0.z.max(p1.z).max(p2.z),
+    );
+    AABB::new(min, max).pad(1e-4)
+}
+
+pub struct Triangle {
+    vertices: [Point3<f64>; 3],
+    normals: Option<[Vector3<f64>; 3]>,
+    uvs: Option<[Vector2<f64>; 3]>,
+    material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(v0: Point3<f64>, v1: Point3<f64>, v2: Point3<f64>, material: Arc<dyn Material>) -> Self {
+        Triangle {
+            vertices: [v0, v1, v2],
+            normals: None,
+            uvs: None,
+            material,
+        }
+    }
+
+    pub fn with_normals(mut self, normals: [Vector3<f64>; 3]) -> Self {
+        self.normals = Some(normals);
+        self
+    }
+
+    pub fn with_uvs(mut self, uvs: [Vector2<f64>; 3]) -> Self {
+        self.uvs = Some(uvs);
+        self
+    }
+}
+
+impl Hitable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let [p0, p1, p2] = &self.vertices;
+        let (t, b0, b1, b2) = intersect_triangle(ray, p0, p1, p2, t_min, t_max)?;
+
+        let geometric_normal = (p1 - p0).cross(&(p2 - p0)).normalize();
+        let normal = match &self.normals {
+            Some([n0, n1, n2]) => {
+                let shading_normal = (b0 * n0 + b1 * n1 + b2 * n2).normalize();
+                if shading_normal.dot(&geometric_normal) < 0.0 {
+                    -shading_normal
+                } else {
+                    shading_normal
+                }
+            }
+            None => geometric_normal,
+        };
+        let uv = match &self.uvs {
+            Some([uv0, uv1, uv2]) => b0 * uv0 + b1 * uv1 + b2 * uv2,
+            None => Vector2::new(b1, b2),
+        };
+
+        Some(HitRecord {
+            t,
+            p: ray.point_at_parameter(t),
+            normal,
+            material: Arc::clone(&self.material),
+            uv,
+            barycentric: Some(Vector2::new(b1, b2)),
+            color: None,
+        })
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        let [p0, p1, p2] = &self.vertices;
+        Some(triangle_bounds(p0, p1, p2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lambertian::Lambertian;
+
+    fn straight_down(x: f64, y: f64) -> Ray {
+        Ray::new(Point3::new(x, y, 1.0), Vector3::new(0.0, 0.0, -1.0))
+    }
+
+    fn unit_triangle() -> [Point3<f64>; 3] {
+        [Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0)]
+    }
+
+    #[test]
+    fn barycentrics_weight_the_vertices_in_order() {
+        let [p0, p1, p2] = unit_triangle();
+        let (t, b0, b1, b2) = intersect_triangle(&straight_down(0.2, 0.3), &p0, &p1, &p2, 0.0, f64::INFINITY).unwrap();
+        assert!((t - 1.0).abs() < 1e-12);
+        assert!((b0 - 0.5).abs() < 1e-12 && (b1 - 0.2).abs() < 1e-12 && (b2 - 0.3).abs() < 1e-12);
+
+        let triangle = Triangle::new(p0, p1, p2, Arc::new(Lambertian::new(Vector3::new(0.5, 0.5, 0.5))));
+        let hit = triangle.hit(&straight_down(0.2, 0.3), 0.0, f64::INFINITY).unwrap();
+        assert!((hit.barycentric.unwrap() - Vector2::new(0.2, 0.3)).norm() < 1e-12);
+        assert!((hit.normal - Vector3::new(0.0, 0.0, 1.0)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn vertices_and_edges_are_hit() {
+        let [p0, p1, p2] = unit_triangle();
+        let (_, _, b1, _) = intersect_triangle(&straight_down(1.0, 0.0), &p0, &p1, &p2, 0.0, f64::INFINITY).unwrap();
+        assert_eq!(b1, 1.0);
+        let (_, b0, _, _) = intersect_triangle(&straight_down(0.0, 0.5), &p0, &p1, &p2, 0.0, f64::INFINITY).unwrap();
+        assert_eq!(b0, 0.5);
+        assert!(intersect_triangle(&straight_down(0.6, 0.6), &p0, &p1, &p2, 0.0, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn shared_edges_leak_no_rays() {
+        // Two triangles splitting a quad along its diagonal, with rays at awkward angles
+        // through points on the diagonal
+        let corners = [Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(1.0, 1.0, 0.0), Point3::new(0.0, 1.0, 0.0)];
+        for i in 1..100 {
+            let s = i as f64 / 100.0;
+            let target = Point3::new(s, s, 0.0);
+            let origin = Point3::new(0.3 * s.sin(), 0.7, 1.3) + Vector3::new(0.1, -0.2, 0.0) * s;
+            let ray = Ray::new(origin, target - origin);
+            let first = intersect_triangle(&ray, &corners[0], &corners[1], &corners[2], 0.0, f64::INFINITY);
+            let second = intersect_triangle(&ray, &corners[0], &corners[2], &corners[3], 0.0, f64::INFINITY);
+            assert!(first.is_some() || second.is_some(), "ray through ({}, {}) slipped between the triangles", s, s);
+        }
+    }
+}
@@ -1,13 +1,9 @@
-use std::{sync::{Arc, Mutex}, collections::HashMap};
+use std::{sync::Arc, cell::RefCell};
 
 use nalgebra::{Vector3, Point3};
-use rand::Rng;
+use rand::{Rng, RngCore, SeedableRng, rngs::SmallRng};
 
-
-
-
-
-use crate::{ray::Ray, hitrecord::{Hitable, HitRecord}, light::{Light, self}, aabb::AABB, bvhnode::BVHNode, kdnode::KdNode};
+use crate::{ray::Ray, hitrecord::{Hitable, HitRecord}, light::{Light, self}, aabb::AABB, scene::Scene, renderer::MisHeuristic};
 
 pub fn refract(v: Vector3<f64>, n: Vector3<f64>, ni_over_nt: f64) -> Option<Vector3<f64>> {
     let uv = v.normalize();
@@ -32,102 +28,68 @@ pub fn schlick(cosine: f64, ref_idx: f64) -> f64 {
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
 
-// Implement the Material trait for Dielectric
-
-
-
-pub fn ray_color_dup(ray: &Ray, world: &KdNode, depth: u32) -> Vector3<f64> {
-    if depth == 0 {
-        return Vector3::new(0.0, 0.0, 0.0);
-    }
-    //let l = Light::new(Point3::new(0.0, 0.0, 14.0),0.01);
-    // if let Some(hit_record) = world.hit(ray, 0.001, f64::INFINITY) {
-    //     if let Some(scatter_record) = hit_record.material.scatter(ray, &hit_record) {
-    //         return scatter_record.attenuation * ray_color(&scatter_record.scattered, world, depth - 1);
-    //     }
-    //     // if let Some((attenuation, scattered)) = hit_record.material.scatter(ray, &hit_record) {
-    //     //     return attenuation.component_mul(&color(&scattered, world, depth - 1));
-    //     // }
-    //     return Vector3::new(0.0, 0.0, 0.0);
-    // }
-    if let Some(hit_record) = world.hit(ray, 0.001, f64::INFINITY) {
-        let scatter_result = hit_record.material.scatter(ray, &hit_record);
-        if let Some((attenuation, scattered_ray)) = scatter_result {
-            //let shadow = is_in_shadow(world, &hit_record.p, &l);
-            // return attenuation.component_mul( &ray_color(&scattered_ray, world, depth - 1));
-            
-            
-            return attenuation.component_mul(&ray_color_dup(&scattered_ray, world, depth - 1));
-            
-        }
-        return Vector3::new(0.0, 0.0, 0.0);
-    }
-
-    let unit_direction = ray.direction.normalize();
-    let t = 0.5 * (unit_direction.y + 1.0);
-    Vector3::new(1.0, 1.0, 1.0) * (1.0 - t) + Vector3::new(0.5, 0.7, 1.0) * t
-}
-// pub fn ray_color(ray: &Ray, world: &Arc<BVHNode>, depth: u32) -> Vector3<f64> {
-//     if depth == 0 {
-//         return Vector3::new(0.0, 0.0, 0.0);
-//     }
-//     let l = Light::new(Point3::new(0.0, 0.0, 14.0), 0.01);
-
-//     if let Some(hit_record) = world.hit(ray, 0.001, f64::INFINITY) {
-//         let scatter_result = hit_record.material.scatter(ray, &hit_record);
-//         if let Some((attenuation, scattered_ray)) = scatter_result {
-//             // let shadow = is_in_shadow(world, &hit_record.p, &l);
-//             return attenuation.component_mul(&ray_color(&scattered_ray, world, depth - 1));
-//         }
-//         return Vector3::new(0.0, 0.0, 0.0);
-//     }
-
-//     let unit_direction = ray.direction.normalize();
-//     let t = 0.5 * (unit_direction.y + 1.0);
-//     Vector3::new(1.0, 1.0, 1.0) * (1.0 - t) + Vector3::new(0.5, 0.7, 1.0) * t
-// }
-pub fn ray_color(ray: &Ray, world: &Arc<BVHNode>, depth: u32,background_cache: &Mutex<HashMap<(i32, i32), Vector3<f64>>>,) -> Vector3<f64> {
-    if depth == 0 || random_f64() < 0.001 {
-        return Vector3::new(0.0, 0.0, 0.0);
+// Decode an sRGB-encoded channel in [0, 1] to linear intensity
+#[inline]
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
     }
-    //let l = Light::new(Point3::new(0.0, 0.0, 14.0), 0.01);
-
-    
+}
 
-    if let Some(hit_record) = world.hit(ray, 0.001, f64::INFINITY) {
-        let scatter_result = hit_record.material.scatter(ray, &hit_record);
-        if let Some((attenuation, scattered_ray)) = scatter_result {
-            let color = attenuation.component_mul(&ray_color(&scattered_ray, world, depth - 1,background_cache));
-            
-            return color;
+// Direct light from the scene's lights that `include` accepts, and from the background when it
+// can be sampled, reflected at the hit towards the ray's origin, weighted against the chance of
+// BSDF sampling finding the same light
+pub fn sample_lights(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    world: &dyn Hitable,
+    scene: &Scene,
+    heuristic: MisHeuristic,
+    include: impl Fn(&Light) -> bool,
+) -> Vector3<f64> {
+    let material = &hit_record.material;
+    let samples = scene
+        .lights
+        .iter()
+        .filter(|light| include(light))
+        .map(|light| (light.sample(&hit_record.p), light.is_delta()))
+        .chain(scene.background.sample().into_iter().map(|sample| (Some(sample), false)));
+    let mut direct = Vector3::new(0.0, 0.0, 0.0);
+    for (sample, is_delta) in samples {
+        let Some(sample) = sample else {
+            continue;
+        };
+        let f = material.eval(ray, hit_record, &sample.direction);
+        if f == Vector3::zeros() {
+            continue;
         }
-        
-        return Vector3::new(0.0, 0.0, 0.0);
-    }
-    
-
-    let unit_direction = ray.direction.normalize();
-    let t = 0.5 * (unit_direction.y + 1.0);
-    //let color = Vector3::new(1.0, 1.0, 1.0) * (1.0 - t) + Vector3::new(0.5, 0.7, 1.0) * t;
-    //memo.insert(*ray, color);
-    let color = {
-        let mut cache = background_cache.lock().unwrap();
-        let key = (
-            (unit_direction.x * 1000.0) as i32,
-            (unit_direction.y * 1000.0) as i32,
-        );
-        let quantized_t = (t * 100.0).round() as i32;
-
-        if let Some(color) = cache.get(&(quantized_t,quantized_t)) {
-            *color
+        let visibility = visibility(world, &hit_record.p, &sample.direction, sample.distance);
+        if visibility == 0.0 {
+            continue;
+        }
+        let weight = if is_delta {
+            1.0
         } else {
-            let new_color =
-                Vector3::new(1.0, 1.0, 1.0) * (1.0 - t) + Vector3::new(0.5, 0.7, 1.0) * t;
-            cache.insert((quantized_t,quantized_t), new_color);
-            new_color
+            heuristic.weight(sample.pdf, material.pdf(ray, hit_record, &sample.direction))
+        };
+        direct += f.component_mul(&sample.radiance) * weight * visibility / sample.pdf;
+    }
+    direct
+}
+// Closest light the ray reaches within (t_min, t_max), with the t it is reached at and the
+// radiance it sees
+pub fn hit_light<'a>(lights: &'a [Light], ray: &Ray, t_min: f64, t_max: f64) -> Option<(&'a Light, f64, Vector3<f64>)> {
+    let mut closest = None;
+    let mut closest_t = t_max;
+    for light in lights {
+        if let Some((t, radiance)) = light.hit(ray, t_min, closest_t) {
+            closest_t = t;
+            closest = Some((light, t, radiance));
         }
-    };
-    color
+    }
+    closest
 }
 impl Hitable for Vec<Arc<dyn Hitable>> {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
@@ -143,29 +105,58 @@ impl Hitable for Vec<Arc<dyn Hitable>> {
 
         closest_hit
     }
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let mut transmittance = 1.0;
+        for object in self {
+            transmittance *= object.transmittance(ray, t_min, t_max);
+            if transmittance == 0.0 {
+                break;
+            }
+        }
+        transmittance
+    }
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
         return None;
     }
 }
+thread_local! {
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy());
+    // Stands in for RNG while set, e.g. the sample vector a Metropolis chain mutates
+    static SOURCE: RefCell<Option<Box<dyn RngCore>>> = RefCell::new(None);
+}
+// Restart this thread's random stream; the renderer reseeds per pixel so images are reproducible
+pub fn seed_rng(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
+// Draw this thread's random numbers from `source` instead of its generator, until it is set
+// back to None
+pub fn set_random_source(source: Option<Box<dyn RngCore>>) {
+    SOURCE.with(|current| *current.borrow_mut() = source);
+}
+#[inline]
+pub fn with_rng<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    SOURCE.with(|source| match &mut *source.borrow_mut() {
+        Some(source) => f(source.as_mut()),
+        None => RNG.with(|rng| f(&mut *rng.borrow_mut())),
+    })
+}
 #[inline]
 pub fn random_f64() -> f64 {
-    rand::thread_rng().gen_range(0.0..1.0)
+    with_rng(|rng| rng.gen_range(0.0..1.0))
 }
 #[inline]
 pub fn random_in_unit_sphere() -> Vector3<f64> {
-    let mut rng = rand::thread_rng();
-    loop {
+    with_rng(|rng| loop {
         let p = 2.0 * Vector3::new(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>())
             - Vector3::new(1.0, 1.0, 1.0);
         if p.magnitude_squared() < 1.0 {
             return p;
         }
-    }
+    })
 }
 #[inline]
 pub fn random_unit_vector(normal: Vector3<f64>) -> Vector3<f64> {
-    let mut rng = rand::thread_rng();
-    loop {
+    with_rng(|rng| loop {
         let random_vector = Vector3::new(rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0));
         if random_vector.magnitude_squared() >= 1.0 {
             continue;
@@ -173,12 +164,22 @@ pub fn random_unit_vector(normal: Vector3<f64>) -> Vector3<f64> {
         if random_vector.dot(&normal) > 0.0 {
             return random_vector.normalize();
         }
-    }
+    })
+}
+// Cosine weighted direction about +z, pdf cos(theta) / pi
+#[inline]
+pub fn random_cosine_direction() -> Vector3<f64> {
+    with_rng(|rng| {
+        let r1: f64 = rng.gen();
+        let r2: f64 = rng.gen();
+        let phi = 2.0 * std::f64::consts::PI * r1;
+        let r = r2.sqrt();
+        Vector3::new(phi.cos() * r, phi.sin() * r, (1.0 - r2).sqrt())
+    })
 }
 #[inline]
 pub fn random_in_unit_disk() -> Vector3<f64> {
-    let mut rng = rand::thread_rng();
-    loop {
+    with_rng(|rng| loop {
         let p = Vector3::new(
             rng.gen_range(-1.0..1.0),
             rng.gen_range(-1.0..1.0),
@@ -187,17 +188,31 @@ pub fn random_in_unit_disk() -> Vector3<f64> {
         if p.magnitude_squared() < 1.0 {
             return p;
         }
-    }
+    })
+}
+// Fraction of the light from `distance` away along the unit `direction` that reaches `point`
+// through the world: 0 when something blocks the way, less than 1 through participating media
+pub fn visibility(world: &dyn Hitable, point: &Point3<f64>, direction: &Vector3<f64>, distance: f64) -> f64 {
+    let shadow_ray = Ray::new(*point, *direction);
+    world.transmittance(&shadow_ray, 0.001, distance - 0.001)
+}
+// Two unit vectors that complete `n` to a right-handed orthonormal basis (Duff et al. 2017)
+pub fn orthonormal_basis(n: &Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+    let sign = 1.0_f64.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    (
+        Vector3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        Vector3::new(b, sign + n.y * n.y * a, -n.y),
+    )
+}
+// Write `contents` to a file called `name` in a scratch directory of this test run, for the
+// loaders that read from paths
+#[cfg(test)]
+pub fn temp_file(name: &str, contents: impl AsRef<[u8]>) -> std::path::PathBuf {
+    let directory = std::env::temp_dir().join(format!("rtracer-test-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join(name);
+    std::fs::write(&path, contents).unwrap();
+    path
 }
-// fn is_in_shadow(world: &Vec<Box<dyn Hitable>>, point: &Point3<f64>, light: &Light) -> bool {
-//     let light_direction = (light.source() - *point).normalize();
-//     let shadow_ray = Ray::new(*point, light_direction);
-
-//     let t_max = (light.source() - *point).magnitude();
-
-//     if let Some(shadow_hit_record) = world.hit(&shadow_ray, 0.001, t_max) {
-//         true
-//     } else {
-//         false
-//     }
-// }
\ No newline at end of file
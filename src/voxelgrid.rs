@@ -0,0 +1,132 @@
+use std::fs;
+use std::path::Path;
+
+use nalgebra::Point3;
+
+use crate::{aabb::AABB, loaderror::LoadError};
+
+// "VOL", version 3, encoding, resolution, channels and bounding box
+const HEADER_SIZE: usize = 48;
+// Encoding code of 32-bit float data, the only one supported
+const FLOAT32: i32 = 1;
+
+fn read_i32(bytes: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn read_f32(bytes: &[u8], offset: usize) -> f32 {
+    f32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+// Dense grid of scalar values, such as the density or temperature a fluid simulation writes
+// out, with values at voxel centres
+pub struct VoxelGrid {
+    resolution: [usize; 3],
+    // Where the grid sits in the world, as recorded in the file
+    pub bounds: AABB,
+    values: Vec<f32>,
+    max: f64,
+}
+
+impl VoxelGrid {
+    // `values` in x-fastest order, then y, then z
+    pub fn new(resolution: [usize; 3], bounds: AABB, values: Vec<f32>) -> Self {
+        assert_eq!(values.len(), resolution.iter().product::<usize>(), "grid size must match its resolution");
+        let max = values.iter().fold(0.0f64, |max, &v| max.max(v as f64));
+        VoxelGrid { resolution, bounds, values, max }
+    }
+
+    pub fn resolution(&self) -> [usize; 3] {
+        self.resolution
+    }
+
+    // Largest value in the grid, bounding every lookup
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f64 {
+        let [nx, ny, _] = self.resolution;
+        self.values[(z * ny + y) * nx + x] as f64
+    }
+
+    // Trilinearly interpolated value at `uvw`, the position inside the grid's box scaled to
+    // the unit cube; 0 outside it
+    pub fn lookup(&self, uvw: &Point3<f64>) -> f64 {
+        if (0..3).any(|a| !(0.0..=1.0).contains(&uvw[a])) {
+            return 0.0;
+        }
+        let mut base = [0; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            let n = self.resolution[axis];
+            let x = (uvw[axis] * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+            base[axis] = (x as usize).min(n.saturating_sub(2));
+            fraction[axis] = x - base[axis] as f64;
+        }
+        let next = |axis: usize| (base[axis] + 1).min(self.resolution[axis] - 1);
+        let mut value = 0.0;
+        for corner in 0..8 {
+            let (cx, cy, cz) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let weight = [(cx, 0), (cy, 1), (cz, 2)]
+                .iter()
+                .map(|&(c, axis)| if c == 0 { 1.0 - fraction[axis] } else { fraction[axis] })
+                .product::<f64>();
+            if weight > 0.0 {
+                let x = if cx == 0 { base[0] } else { next(0) };
+                let y = if cy == 0 { base[1] } else { next(1) };
+                let z = if cz == 0 { base[2] } else { next(2) };
+                value += weight * self.voxel(x, y, z);
+            }
+        }
+        value
+    }
+}
+
+// Load a single channel grid in Mitsuba's binary .vol format: "VOL" and a version byte of 3,
+// then little-endian i32 encoding (1 for float32), x, y and z resolution and channel count, the
+// f32 bounding box (min then max), and the values with x varying fastest
+pub fn load_grid(path: impl AsRef<Path>) -> Result<VoxelGrid, LoadError> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|e| LoadError::io(path, e))?;
+    if bytes.len() < HEADER_SIZE || &bytes[..3] != b"VOL" {
+        return Err(LoadError::format(path, "not a .vol voxel grid"));
+    }
+    if bytes[3] != 3 {
+        return Err(LoadError::format(path, format!("unsupported .vol version {}", bytes[3])));
+    }
+    let encoding = read_i32(&bytes, 4);
+    if encoding != FLOAT32 {
+        return Err(LoadError::format(path, format!("unsupported .vol encoding {}, only float32 (1) is", encoding)));
+    }
+    let resolution = [read_i32(&bytes, 8), read_i32(&bytes, 12), read_i32(&bytes, 16)];
+    if resolution.iter().any(|&n| n <= 0) {
+        return Err(LoadError::format(path, "grid resolution must be positive"));
+    }
+    let channels = read_i32(&bytes, 20);
+    if channels != 1 {
+        return Err(LoadError::format(path, format!("grid has {} channels, expected 1", channels)));
+    }
+    let corner = |offset: usize| {
+        Point3::new(read_f32(&bytes, offset) as f64, read_f32(&bytes, offset + 4) as f64, read_f32(&bytes, offset + 8) as f64)
+    };
+    let (min, max) = (corner(24), corner(36));
+    if !(0..3).all(|a| min[a] < max[a]) {
+        return Err(LoadError::format(path, "grid bounding box is empty"));
+    }
+
+    let resolution = resolution.map(|n| n as usize);
+    let count: usize = resolution.iter().product();
+    let expected = HEADER_SIZE + count * 4;
+    if bytes.len() != expected {
+        return Err(LoadError::format(
+            path,
+            format!("header declares {} voxels ({} bytes) but file has {} bytes", count, expected, bytes.len()),
+        ));
+    }
+    let values: Vec<f32> = (0..count).map(|i| read_f32(&bytes, HEADER_SIZE + i * 4)).collect();
+    if values.iter().any(|v| !v.is_finite() || *v < 0.0) {
+        return Err(LoadError::format(path, "grid values must be finite and not negative"));
+    }
+    Ok(VoxelGrid::new(resolution, AABB::new(min, max), values))
+}